@@ -0,0 +1,470 @@
+//! On-disk cache of extracted metadata, so unchanged files are not parsed again.
+//!
+//! Each entry stores a file's metadata together with a [`Fingerprint`]: its size,
+//! modification time and a hash of its first and last [`SAMPLE_BYTES`]. An entry is
+//! only used while all three still match the file, so edited or replaced files are
+//! re-read automatically. The cache is a single JSON file in [`default_path`]; it is
+//! discarded whenever the docmeta version changes, because newer versions may extract
+//! more or different metadata.
+use crate::{extract::Tags, utils};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::UNIX_EPOCH,
+};
+
+/// Bytes hashed from each end of a file for its [`Fingerprint`].
+const SAMPLE_BYTES: u64 = 64 * 1024;
+
+/// Errors that can occur when saving or clearing the cache.
+#[derive(Debug, thiserror::Error)]
+pub enum CacheError {
+    /// The cache file could not be written or removed.
+    #[error("Unable to access cache {}: {source}", .path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+}
+
+/// What identifies a version of a file's contents without reading all of it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fingerprint {
+    /// Size in bytes.
+    size: u64,
+    /// Modification time in nanoseconds since `UNIX_EPOCH`.
+    modified: u128,
+    /// BLAKE3 hash of the first and last [`SAMPLE_BYTES`] of the file.
+    hash: String,
+}
+
+impl Fingerprint {
+    /// Take the fingerprint of the file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err` if the file cannot be opened, read or stat'd.
+    pub fn of(path: &Path) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let meta = file.metadata()?;
+        let size = meta.len();
+        let modified = meta
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos());
+
+        let mut hasher = blake3::Hasher::new();
+        io::copy(&mut (&mut file).take(SAMPLE_BYTES), &mut hasher)?;
+        if size > SAMPLE_BYTES {
+            file.seek(SeekFrom::Start(
+                size.saturating_sub(SAMPLE_BYTES).max(SAMPLE_BYTES),
+            ))?;
+            io::copy(&mut file, &mut hasher)?;
+        }
+
+        Ok(Self {
+            size,
+            modified,
+            hash: hasher.finalize().to_hex().to_string(),
+        })
+    }
+}
+
+/// A cached file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    #[serde(with = "utils::stored_path")]
+    path: PathBuf,
+    fingerprint: Fingerprint,
+    tags: Tags,
+}
+
+/// The cache file's contents.
+#[derive(Serialize, Deserialize)]
+struct Stored {
+    /// The docmeta version that wrote the cache.
+    version: String,
+    entries: Vec<Entry>,
+}
+
+/// The metadata cache for one run.
+///
+/// Lookups can happen on several threads at once; the cache is written back with
+/// [`Cache::save`].
+#[derive(Debug)]
+pub struct Cache {
+    path: PathBuf,
+    entries: Mutex<HashMap<PathBuf, Entry>>,
+    dirty: Mutex<bool>,
+}
+
+/// The cache file: `<cache dir>/docmeta/metadata.json`, or `None` if the platform has no
+/// cache directory.
+pub fn default_path() -> Option<PathBuf> {
+    dirs::cache_dir().map(|d| d.join(clap::crate_name!()).join("metadata.json"))
+}
+
+impl Cache {
+    /// Load the cache at `path`. A missing, unreadable or outdated cache starts empty.
+    pub fn load<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref();
+        let entries = match File::open(path) {
+            Ok(file) => match serde_json::from_reader::<_, Stored>(BufReader::new(file)) {
+                Ok(stored) if stored.version == clap::crate_version!() => stored.entries,
+                Ok(stored) => {
+                    log::debug!(
+                        "Discarding metadata cache written by version {}",
+                        stored.version
+                    );
+                    Vec::new()
+                }
+                Err(err) => {
+                    log::warn!("Ignoring unreadable cache {}: {err}", path.display());
+                    Vec::new()
+                }
+            },
+            Err(err) => {
+                if err.kind() != io::ErrorKind::NotFound {
+                    log::warn!("Ignoring unreadable cache {}: {err}", path.display());
+                }
+                Vec::new()
+            }
+        };
+        log::debug!(
+            "Loaded {} cached file(s) from {}",
+            entries.len(),
+            path.display()
+        );
+
+        Self {
+            path: path.to_path_buf(),
+            entries: Mutex::new(entries.into_iter().map(|e| (e.path.clone(), e)).collect()),
+            dirty: Mutex::new(false),
+        }
+    }
+
+    /// Return the cached metadata of `file` if it is still current, or read it with
+    /// `extract` and cache the result.
+    ///
+    /// Files that cannot be fingerprinted are read without caching, and failed reads are
+    /// never cached.
+    ///
+    /// # Errors
+    ///
+    /// Returns whatever `extract` returns.
+    pub fn get_or_extract<F>(&self, file: &Path, extract: F) -> anyhow::Result<Tags>
+    where
+        F: FnOnce(&Path) -> anyhow::Result<Tags>,
+    {
+        let key = key(file);
+        let fingerprint = match Fingerprint::of(file) {
+            Ok(fingerprint) => fingerprint,
+            Err(err) => {
+                log::debug!("Not caching {}: {err}", file.display());
+                return extract(file);
+            }
+        };
+
+        if let Some(entry) = self.lock_entries().get(&key) {
+            if entry.fingerprint == fingerprint {
+                log::debug!("Using cached metadata for {}", file.display());
+                return Ok(entry.tags.clone());
+            }
+            log::debug!("Cached metadata for {} is stale", file.display());
+        }
+
+        let tags = extract(file)?;
+        self.lock_entries().insert(
+            key.clone(),
+            Entry {
+                path: key,
+                fingerprint,
+                tags: tags.clone(),
+            },
+        );
+        *self.lock_dirty() = true;
+        Ok(tags)
+    }
+
+    /// Replace the cached metadata of `file` with `tags`, e.g. once more has been read
+    /// from it. Files without a current entry are left out.
+    pub fn update(&self, file: &Path, tags: &Tags) {
+        if let Some(entry) = self.lock_entries().get_mut(&key(file)) {
+            entry.tags.clone_from(tags);
+            *self.lock_dirty() = true;
+        }
+    }
+
+    /// Move the entry of a file that was renamed from `from` to `to`, so the next run
+    /// finds it under its new name.
+    pub fn renamed(&self, from: &Path, to: &Path) {
+        let mut entries = self.lock_entries();
+        if let Some(mut entry) = entries.remove(&key(from)) {
+            entry.path = key(to);
+            entries.insert(entry.path.clone(), entry);
+            *self.lock_dirty() = true;
+        }
+    }
+
+    /// Drop the entries of files that no longer exist, and return how many were dropped.
+    ///
+    /// This checks every entry's file, so it is left to `cache prune` rather than done on
+    /// every save.
+    pub fn prune(&self) -> usize {
+        let mut entries = self.lock_entries();
+        let before = entries.len();
+        entries.retain(|path, _| path.exists());
+        let pruned = before - entries.len();
+        if pruned > 0 {
+            *self.lock_dirty() = true;
+        }
+        pruned
+    }
+
+    /// Write the cache back to disk if anything changed.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CacheError::Io`] if the cache file cannot be written.
+    pub fn save(&self) -> Result<(), CacheError> {
+        if !*self.lock_dirty() {
+            return Ok(());
+        }
+        let io_err = |source| CacheError::Io {
+            path: self.path.clone(),
+            source,
+        };
+
+        let mut entries: Vec<Entry> = self.lock_entries().values().cloned().collect();
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        let stored = Stored {
+            version: clap::crate_version!().to_string(),
+            entries,
+        };
+
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir).map_err(io_err)?;
+        }
+        // Write to a temporary file first so an interrupted save never leaves a
+        // truncated cache behind. It is named after the process, so runs saving at the
+        // same time don't write into each other's file; the last rename wins.
+        let temp = self
+            .path
+            .with_extension(format!("json.{}.tmp", std::process::id()));
+        let mut writer = BufWriter::new(File::create(&temp).map_err(io_err)?);
+        serde_json::to_writer(&mut writer, &stored)
+            .map_err(io::Error::other)
+            .map_err(io_err)?;
+        writer.flush().map_err(io_err)?;
+        drop(writer);
+        std::fs::rename(&temp, &self.path).map_err(io_err)?;
+
+        *self.lock_dirty() = false;
+        log::debug!("Metadata cache saved to {}", self.path.display());
+        Ok(())
+    }
+
+    fn lock_entries(&self) -> std::sync::MutexGuard<'_, HashMap<PathBuf, Entry>> {
+        self.entries
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    fn lock_dirty(&self) -> std::sync::MutexGuard<'_, bool> {
+        self.dirty
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+/// Delete the cache at `path`. Returns `false` if there was no cache.
+///
+/// # Errors
+///
+/// Returns [`CacheError::Io`] if the file exists but cannot be removed.
+pub fn clear<P: AsRef<Path>>(path: P) -> Result<bool, CacheError> {
+    let path = path.as_ref();
+    match std::fs::remove_file(path) {
+        Ok(()) => Ok(true),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(source) => Err(CacheError::Io {
+            path: path.to_path_buf(),
+            source,
+        }),
+    }
+}
+
+/// The absolute form of `path`, so the same file is found from any working directory.
+fn key(path: &Path) -> PathBuf {
+    std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        fs,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+    use tempfile::tempdir;
+
+    /// An extractor that counts how often it is called.
+    fn counting(calls: &AtomicUsize) -> impl Fn(&Path) -> anyhow::Result<Tags> + '_ {
+        move |path| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            let contents = fs::read_to_string(path)?;
+            Ok(Tags::from([("Title".to_string(), Some(contents))]))
+        }
+    }
+
+    #[test]
+    fn unchanged_files_are_served_from_cache_across_runs() {
+        let dir = tempdir().expect("temp dir");
+        let book = dir.path().join("book.epub");
+        let cache_file = dir.path().join("cache").join("metadata.json");
+        fs::write(&book, "First").expect("write");
+        let calls = AtomicUsize::new(0);
+
+        let cache = Cache::load(&cache_file);
+        cache
+            .get_or_extract(&book, counting(&calls))
+            .expect("extract");
+        cache.save().expect("save");
+
+        let cache = Cache::load(&cache_file);
+        let tags = cache
+            .get_or_extract(&book, counting(&calls))
+            .expect("cached");
+        assert_eq!(calls.load(Ordering::SeqCst), 1, "file was parsed again");
+        assert_eq!(tags["Title"].as_deref(), Some("First"));
+    }
+
+    #[test]
+    fn changed_files_are_read_again() {
+        let dir = tempdir().expect("temp dir");
+        let book = dir.path().join("book.epub");
+        fs::write(&book, "First").expect("write");
+        let calls = AtomicUsize::new(0);
+        let cache = Cache::load(dir.path().join("metadata.json"));
+
+        cache
+            .get_or_extract(&book, counting(&calls))
+            .expect("extract");
+        fs::write(&book, "Second edition").expect("rewrite");
+        let tags = cache
+            .get_or_extract(&book, counting(&calls))
+            .expect("extract");
+
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert_eq!(tags["Title"].as_deref(), Some("Second edition"));
+    }
+
+    #[test]
+    fn failed_reads_are_not_cached() {
+        let dir = tempdir().expect("temp dir");
+        let book = dir.path().join("book.epub");
+        fs::write(&book, "x").expect("write");
+        let cache = Cache::load(dir.path().join("metadata.json"));
+
+        let fail = |_: &Path| -> anyhow::Result<Tags> { anyhow::bail!("broken") };
+        assert!(cache.get_or_extract(&book, fail).is_err());
+        assert!(cache.get_or_extract(&book, fail).is_err());
+        cache.save().expect("save");
+        assert!(
+            !dir.path().join("metadata.json").exists(),
+            "nothing to save"
+        );
+    }
+
+    #[test]
+    fn renamed_files_keep_their_entry() {
+        let dir = tempdir().expect("temp dir");
+        let (old, new) = (dir.path().join("old.epub"), dir.path().join("new.epub"));
+        fs::write(&old, "Kept").expect("write");
+        let cache_file = dir.path().join("metadata.json");
+        let calls = AtomicUsize::new(0);
+
+        let cache = Cache::load(&cache_file);
+        cache.get_or_extract(&old, counting(&calls)).expect("old");
+        fs::rename(&old, &new).expect("rename");
+        cache.renamed(&old, &new);
+        cache.save().expect("save");
+
+        let cache = Cache::load(&cache_file);
+        cache.get_or_extract(&new, counting(&calls)).expect("new");
+        assert_eq!(
+            calls.load(Ordering::SeqCst),
+            1,
+            "renamed file was parsed again"
+        );
+    }
+
+    #[test]
+    fn deleted_files_are_only_dropped_by_prune() {
+        let dir = tempdir().expect("temp dir");
+        let (kept, gone) = (dir.path().join("kept.epub"), dir.path().join("gone.epub"));
+        fs::write(&kept, "Kept").expect("write");
+        fs::write(&gone, "Gone").expect("write");
+        let cache_file = dir.path().join("metadata.json");
+        let calls = AtomicUsize::new(0);
+
+        let cache = Cache::load(&cache_file);
+        cache.get_or_extract(&kept, counting(&calls)).expect("kept");
+        cache.get_or_extract(&gone, counting(&calls)).expect("gone");
+        fs::remove_file(&gone).expect("delete");
+        cache.save().expect("save");
+
+        let cache = Cache::load(&cache_file);
+        assert_eq!(cache.lock_entries().len(), 2);
+        assert_eq!(cache.prune(), 1);
+        cache.save().expect("save");
+        assert_eq!(Cache::load(&cache_file).lock_entries().len(), 1);
+        assert_eq!(
+            fs::read_dir(dir.path()).expect("read dir").count(),
+            2,
+            "a temporary file was left behind"
+        );
+    }
+
+    #[test]
+    fn cache_from_another_version_is_discarded() {
+        let dir = tempdir().expect("temp dir");
+        let cache_file = dir.path().join("metadata.json");
+        fs::write(&cache_file, r#"{"version":"0.0.0","entries":[]}"#).expect("write");
+        assert!(Cache::load(&cache_file).lock_entries().is_empty());
+
+        fs::write(&cache_file, "not json").expect("write");
+        assert!(Cache::load(&cache_file).lock_entries().is_empty());
+    }
+
+    #[test]
+    fn fingerprint_covers_both_ends_of_large_files() {
+        let dir = tempdir().expect("temp dir");
+        let file = dir.path().join("big.pdf");
+        let mut data = vec![0_u8; 3 * SAMPLE_BYTES as usize];
+        fs::write(&file, &data).expect("write");
+        let before = Fingerprint::of(&file).expect("fingerprint");
+
+        let last = data.len() - 1;
+        data[last] = 1;
+        fs::write(&file, &data).expect("rewrite");
+        assert_ne!(
+            before.hash,
+            Fingerprint::of(&file).expect("fingerprint").hash
+        );
+    }
+
+    #[test]
+    fn clear_removes_the_cache() {
+        let dir = tempdir().expect("temp dir");
+        let cache_file = dir.path().join("metadata.json");
+        fs::write(&cache_file, "{}").expect("write");
+        assert!(clear(&cache_file).expect("clear"));
+        assert!(!clear(&cache_file).expect("clear again"));
+    }
+}
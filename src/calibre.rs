@@ -0,0 +1,646 @@
+//! Metadata kept outside the books by Calibre: the `metadata.opf` sidecar it writes next to
+//! each book, and the `metadata.db` SQLite database at the root of a library.
+//!
+//! Calibre keeps each book in a directory of its own, `<Author>/<Title> (<id>)/`, holding one
+//! file per format, the cover and the sidecar. The database is the authoritative record; the
+//! sidecars are copies of it that Calibre keeps up to date. Either can be [merged](merge) over
+//! the metadata read from the files themselves with [`Sources`].
+use crate::{epub::DC_NAMESPACE, extract::Tags, text};
+use rusqlite::{Connection, OpenFlags};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+};
+use xml::{
+    ParserConfig,
+    reader::{self, XmlEvent},
+};
+
+/// The name of the sidecar Calibre writes next to each book.
+pub const SIDECAR: &str = "metadata.opf";
+
+/// The name of the database at the root of a Calibre library.
+pub const DATABASE: &str = "metadata.db";
+
+/// The namespace of OPF attributes such as `opf:scheme`.
+const OPF_NAMESPACE: &str = "http://www.idpf.org/2007/opf";
+
+/// The start of the date Calibre stores for a book whose date is not known.
+const UNDEFINED_DATE: &str = "0101-01-01";
+
+/// Errors that can occur when reading Calibre metadata.
+#[derive(Debug, thiserror::Error)]
+pub enum CalibreError {
+    /// A sidecar could not be read.
+    #[error("Unable to read {}: {source}", .path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    /// A sidecar is not well-formed XML.
+    #[error("Malformed {}: {source}", .path.display())]
+    Malformed {
+        path: PathBuf,
+        #[source]
+        source: reader::Error,
+    },
+    /// The library database could not be opened or queried.
+    #[error("Unable to read Calibre library {}: {source}", .path.display())]
+    Database {
+        path: PathBuf,
+        #[source]
+        source: rusqlite::Error,
+    },
+    /// Files would be renamed inside a library, behind the back of its database.
+    #[error(
+        "Not renaming files in Calibre library {}: its {DATABASE} records their names. Rename the books in Calibre, or use --dry-run to preview",
+        .0.display()
+    )]
+    RenameInLibrary(PathBuf),
+}
+
+/// A book's metadata as Calibre records it.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Book {
+    title: Option<String>,
+    authors: Vec<String>,
+    description: Option<String>,
+    publisher: Option<String>,
+    date: Option<String>,
+    languages: Vec<String>,
+    /// `(scheme, value)` pairs, e.g. `("isbn", "9780441013593")`, schemes in lowercase.
+    identifiers: Vec<(String, String)>,
+    tags: Vec<String>,
+    series: Option<String>,
+    series_index: Option<f64>,
+}
+
+impl Book {
+    /// The book's metadata under docmeta's keys. Only what Calibre knows is included, so
+    /// the result can be [merged](merge) over a file's own metadata:
+    ///
+    /// * `Author` - the authors, joined with ` & ` as Calibre shows them
+    /// * `Description` - the comments, as plain text
+    /// * `Date` - the publication date, unless Calibre has none
+    /// * `Language` - the first language
+    /// * `Identifier` - the ISBN if there is one, else the UUID as a `urn:uuid:`, else the
+    ///   first other identifier
+    /// * `Tags`, `Series`, `SeriesIndex` - the tags joined with `, `, the series and the
+    ///   book's number in it
+    fn into_tags(self) -> Tags {
+        let date = self
+            .date
+            .filter(|date| !date.starts_with(UNDEFINED_DATE))
+            .map(|date| date.chars().take(10).collect());
+        let description = self.description.map(|html| {
+            text::html_to_text(&html)
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ")
+        });
+        let identifier = |scheme: &str| {
+            self.identifiers
+                .iter()
+                .find(|(s, _)| s == scheme)
+                .map(|(_, value)| value.clone())
+        };
+        let identifier = identifier("isbn")
+            .or_else(|| identifier("uuid").map(|uuid| format!("urn:uuid:{uuid}")))
+            .or_else(|| {
+                self.identifiers
+                    .iter()
+                    .find(|(scheme, _)| scheme != "calibre")
+                    .map(|(scheme, value)| format!("{scheme}:{value}"))
+            });
+        let join = |items: &[String], separator: &str| {
+            Some(items.join(separator)).filter(|joined| !joined.is_empty())
+        };
+        [
+            ("Title", self.title),
+            ("Author", join(&self.authors, " & ")),
+            ("Description", description),
+            ("Publisher", self.publisher),
+            ("Date", date),
+            ("Language", self.languages.into_iter().next()),
+            ("Identifier", identifier),
+            ("Tags", join(&self.tags, ", ")),
+            (
+                "SeriesIndex",
+                self.series
+                    .as_ref()
+                    .and(self.series_index)
+                    .map(|i| i.to_string()),
+            ),
+            ("Series", self.series),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| {
+            let value = value
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())?;
+            Some((key.to_string(), Some(value)))
+        })
+        .collect()
+    }
+}
+
+/// Put every tag in `over` that has a value into `tags`, replacing what was there.
+pub fn merge(tags: &mut Tags, over: Tags) {
+    tags.extend(over.into_iter().filter(|(_, value)| value.is_some()));
+}
+
+/// The sidecar of `file`, if it has one: the `metadata.opf` in the same directory.
+pub fn sidecar(file: &Path) -> Option<PathBuf> {
+    let sidecar = file.parent()?.join(SIDECAR);
+    sidecar.is_file().then_some(sidecar)
+}
+
+/// Read the metadata in the sidecar at `path`.
+///
+/// # Errors
+///
+/// Returns `Err` if the sidecar cannot be read or is not well-formed XML.
+pub fn read_sidecar(path: &Path) -> Result<Tags, CalibreError> {
+    let opf = std::fs::read(path).map_err(|source| CalibreError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    parse_opf(&opf)
+        .map(Book::into_tags)
+        .map_err(|source| CalibreError::Malformed {
+            path: path.to_path_buf(),
+            source,
+        })
+}
+
+/// The metadata in the package document `opf`.
+fn parse_opf(opf: &[u8]) -> Result<Book, reader::Error> {
+    let mut book = Book::default();
+    // The Dublin Core element being read, with the `opf:scheme` of identifiers, and its text.
+    let mut element: Option<(String, String, String)> = None;
+    for event in ParserConfig::new().create_reader(opf) {
+        match event? {
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } => {
+                let attribute = |key: &str, namespace: Option<&str>| {
+                    attributes
+                        .iter()
+                        .find(|a| {
+                            a.name.local_name == key && a.name.namespace.as_deref() == namespace
+                        })
+                        .map(|a| a.value.clone())
+                };
+                if name.namespace.as_deref() == Some(DC_NAMESPACE) {
+                    // Only authors count as creators; Calibre marks them `opf:role="aut"`.
+                    let role = attribute("role", Some(OPF_NAMESPACE));
+                    if name.local_name != "creator" || role.is_none_or(|r| r == "aut") {
+                        let scheme = attribute("scheme", Some(OPF_NAMESPACE)).unwrap_or_default();
+                        element = Some((name.local_name, scheme.to_lowercase(), String::new()));
+                    }
+                } else if name.local_name == "meta" {
+                    let content = attribute("content", None);
+                    match attribute("name", None).as_deref() {
+                        Some("calibre:series") => book.series = content,
+                        Some("calibre:series_index") => {
+                            book.series_index = content.and_then(|c| c.trim().parse().ok());
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            XmlEvent::Characters(text) | XmlEvent::CData(text) => {
+                if let Some((_, _, value)) = &mut element {
+                    value.push_str(&text);
+                }
+            }
+            XmlEvent::EndElement { .. } => {
+                let Some((name, scheme, value)) = element.take() else {
+                    continue;
+                };
+                let value = value.trim().to_string();
+                match name.as_str() {
+                    "title" => book.title = Some(value),
+                    "creator" => book.authors.push(value),
+                    "description" => book.description = Some(value),
+                    "publisher" => book.publisher = Some(value),
+                    "date" => book.date = Some(value),
+                    "language" => book.languages.push(value),
+                    "identifier" => book.identifiers.push((scheme, value)),
+                    "subject" => book.tags.push(value),
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(book)
+}
+
+/// The books of a Calibre library, as recorded in its database.
+#[derive(Debug, Default)]
+pub struct Library {
+    /// The directory the library is in.
+    root: PathBuf,
+    /// The metadata of each book file, by its canonical path.
+    files: BTreeMap<PathBuf, Tags>,
+}
+
+impl Library {
+    /// Read the database of the library at `root`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err` if `root` has no database or it can't be read.
+    pub fn open(root: &Path) -> Result<Self, CalibreError> {
+        let path = root.join(DATABASE);
+        let error = |source| CalibreError::Database {
+            path: path.clone(),
+            source,
+        };
+        // Calibre may have the library open; reading alongside it is safe.
+        let db = Connection::open_with_flags(
+            &path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )
+        .map_err(error)?;
+        let (books, files) = read_books(&db).map_err(error)?;
+        let mut library = Self {
+            root: root.to_path_buf(),
+            files: BTreeMap::new(),
+        };
+        for (id, directory, name, format) in files {
+            let Some(book) = books.get(&id) else { continue };
+            let file = root
+                .join(&directory)
+                .join(format!("{name}.{}", format.to_lowercase()));
+            let file = file.canonicalize().unwrap_or(file);
+            library.files.insert(file, book.clone().into_tags());
+        }
+        log::debug!(
+            "Read {} book(s) in {} file(s) from {}",
+            books.len(),
+            library.files.len(),
+            path.display()
+        );
+        Ok(library)
+    }
+
+    /// The metadata the library has for `file`, if it is one of its books.
+    pub fn get(&self, file: &Path) -> Option<&Tags> {
+        match file.canonicalize() {
+            Ok(file) => self.files.get(&file),
+            Err(_) => self.files.get(file),
+        }
+    }
+
+    /// The library's book files that exist and have one of `extensions` (ignoring case).
+    pub fn files(&self, extensions: &[&str]) -> Vec<PathBuf> {
+        self.files
+            .keys()
+            .filter(|file| {
+                file.extension()
+                    .is_some_and(|ext| extensions.iter().any(|e| ext.eq_ignore_ascii_case(e)))
+            })
+            .filter(|file| file.is_file())
+            .cloned()
+            .collect()
+    }
+}
+
+/// The file of a format of a book: the book's id, its directory in the library, the file
+/// name without extension, and the format, e.g. `EPUB`.
+type BookFile = (i64, String, String, String);
+
+/// Every book in `db` by id, and every file of them.
+fn read_books(db: &Connection) -> rusqlite::Result<(HashMap<i64, Book>, Vec<BookFile>)> {
+    let mut books = HashMap::new();
+    let mut statement = db.prepare("SELECT id, title, pubdate, series_index, uuid FROM books")?;
+    let mut rows = statement.query([])?;
+    while let Some(row) = rows.next()? {
+        let uuid: Option<String> = row.get(4)?;
+        let book = Book {
+            title: row.get(1)?,
+            date: row.get(2)?,
+            series_index: row.get(3)?,
+            identifiers: uuid
+                .map(|uuid| ("uuid".to_string(), uuid))
+                .into_iter()
+                .collect(),
+            ..Book::default()
+        };
+        books.insert(row.get::<_, i64>(0)?, book);
+    }
+
+    // Each query gives a book id and a value, in the order Calibre shows them.
+    let mut values = |sql: &str, add: &mut dyn FnMut(&mut Book, String)| -> rusqlite::Result<()> {
+        let mut statement = db.prepare(sql)?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            let id: i64 = row.get(0)?;
+            let value: Option<String> = row.get(1)?;
+            if let (Some(book), Some(value)) = (books.get_mut(&id), value) {
+                add(book, value);
+            }
+        }
+        Ok(())
+    };
+    values(
+        "SELECT l.book, a.name FROM books_authors_link l JOIN authors a ON a.id = l.author ORDER BY l.id",
+        &mut |book, name| book.authors.push(name),
+    )?;
+    values(
+        "SELECT l.book, p.name FROM books_publishers_link l JOIN publishers p ON p.id = l.publisher",
+        &mut |book, name| book.publisher = Some(name),
+    )?;
+    values(
+        "SELECT l.book, g.lang_code FROM books_languages_link l JOIN languages g ON g.id = l.lang_code ORDER BY l.item_order",
+        &mut |book, code| book.languages.push(code),
+    )?;
+    values(
+        "SELECT l.book, t.name FROM books_tags_link l JOIN tags t ON t.id = l.tag ORDER BY t.name",
+        &mut |book, name| book.tags.push(name),
+    )?;
+    values(
+        "SELECT l.book, s.name FROM books_series_link l JOIN series s ON s.id = l.series",
+        &mut |book, name| book.series = Some(name),
+    )?;
+    values("SELECT book, text FROM comments", &mut |book, text| {
+        book.description = Some(text);
+    })?;
+    values(
+        "SELECT book, lower(type) || ':' || val FROM identifiers ORDER BY id",
+        &mut |book, identifier| {
+            if let Some((scheme, value)) = identifier.split_once(':') {
+                book.identifiers
+                    .push((scheme.to_string(), value.to_string()));
+            }
+        },
+    )?;
+
+    let mut statement = db.prepare(
+        "SELECT d.book, b.path, d.name, d.format FROM data d JOIN books b ON b.id = d.book",
+    )?;
+    let files = statement
+        .query_map([], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok((books, files))
+}
+
+/// Where metadata kept outside the books is read from, to be merged over the metadata read
+/// from the files.
+#[derive(Debug, Default)]
+pub struct Sources {
+    /// Read each book's sidecar, if it has one.
+    pub sidecars: bool,
+    /// The Calibre library whose database is read.
+    pub library: Option<Library>,
+}
+
+impl Sources {
+    /// Merge the metadata kept for `file` over its `tags`: its sidecar first, then the
+    /// library's record, which is the more authoritative of the two. A sidecar that can't
+    /// be read is logged and left out.
+    pub fn merge(&self, file: &Path, tags: &mut Tags) {
+        if self.sidecars {
+            if let Some(sidecar) = sidecar(file) {
+                match read_sidecar(&sidecar) {
+                    Ok(over) => merge(tags, over),
+                    Err(err) => log::warn!("{err}"),
+                }
+            }
+        }
+        if let Some(over) = self.library.as_ref().and_then(|library| library.get(file)) {
+            merge(tags, over.clone());
+        }
+    }
+
+    /// Check that files may be renamed. Renaming is refused with a library, unless it is a
+    /// `dry_run`: the database records each book's directory and file name, so Calibre
+    /// would lose track of the books.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CalibreError::RenameInLibrary`] if renaming is refused.
+    pub fn allow_rename(&self, dry_run: bool) -> Result<(), CalibreError> {
+        match &self.library {
+            Some(library) if !dry_run => Err(CalibreError::RenameInLibrary(library.root.clone())),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A sidecar as Calibre writes it.
+    const OPF: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" unique-identifier="uuid_id" version="2.0">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:opf="http://www.idpf.org/2007/opf">
+    <dc:identifier opf:scheme="calibre" id="calibre_id">7</dc:identifier>
+    <dc:identifier opf:scheme="uuid" id="uuid_id">0b9c5a52-52b8-4d2c-a1a3-6f0c1b9a4c11</dc:identifier>
+    <dc:title>Dune</dc:title>
+    <dc:creator opf:file-as="Herbert, Frank" opf:role="aut">Frank Herbert</dc:creator>
+    <dc:contributor opf:file-as="calibre" opf:role="bkp">calibre (7.4.0)</dc:contributor>
+    <dc:date>1965-08-01T00:00:00+00:00</dc:date>
+    <dc:description>&lt;p&gt;Set on the desert   planet &lt;em&gt;Arrakis&lt;/em&gt;.&lt;/p&gt;</dc:description>
+    <dc:publisher>Chilton Books</dc:publisher>
+    <dc:identifier opf:scheme="ISBN">9780441013593</dc:identifier>
+    <dc:language>eng</dc:language>
+    <dc:subject>Science Fiction</dc:subject>
+    <dc:subject>Classics</dc:subject>
+    <meta name="calibre:series" content="Dune"/>
+    <meta name="calibre:series_index" content="1.0"/>
+  </metadata>
+</package>"#;
+
+    fn tag<'a>(tags: &'a Tags, key: &str) -> Option<&'a str> {
+        tags.get(key).and_then(Option::as_deref)
+    }
+
+    // ── sidecars ─────────────────────────────────────────────────────────────
+
+    #[test]
+    fn sidecar_metadata_is_read() {
+        let tags = parse_opf(OPF.as_bytes()).expect("parse").into_tags();
+        assert_eq!(tag(&tags, "Title"), Some("Dune"));
+        assert_eq!(tag(&tags, "Author"), Some("Frank Herbert"));
+        assert_eq!(
+            tag(&tags, "Description"),
+            Some("Set on the desert planet Arrakis.")
+        );
+        assert_eq!(tag(&tags, "Publisher"), Some("Chilton Books"));
+        assert_eq!(tag(&tags, "Date"), Some("1965-08-01"));
+        assert_eq!(tag(&tags, "Language"), Some("eng"));
+        assert_eq!(tag(&tags, "Identifier"), Some("9780441013593"));
+        assert_eq!(tag(&tags, "Tags"), Some("Science Fiction, Classics"));
+        assert_eq!(tag(&tags, "Series"), Some("Dune"));
+        assert_eq!(tag(&tags, "SeriesIndex"), Some("1"));
+    }
+
+    #[test]
+    fn unknown_values_are_left_out() {
+        let book = Book {
+            title: Some("  ".to_string()),
+            date: Some("0101-01-01T00:00:00+00:00".to_string()),
+            series_index: Some(1.0),
+            identifiers: vec![
+                ("calibre".to_string(), "7".to_string()),
+                ("uuid".to_string(), "0b9c5a52".to_string()),
+            ],
+            ..Book::default()
+        };
+        let tags = book.into_tags();
+        assert_eq!(
+            tags,
+            Tags::from([(
+                "Identifier".to_string(),
+                Some("urn:uuid:0b9c5a52".to_string())
+            )])
+        );
+    }
+
+    #[test]
+    fn merged_values_replace_the_files_own() {
+        let mut tags = Tags::from([
+            ("Title".to_string(), Some("dune_final_v2".to_string())),
+            ("Author".to_string(), Some("Unknown".to_string())),
+            ("Words".to_string(), Some("188000".to_string())),
+        ]);
+        merge(
+            &mut tags,
+            Tags::from([
+                ("Title".to_string(), Some("Dune".to_string())),
+                ("Author".to_string(), None),
+            ]),
+        );
+        assert_eq!(tag(&tags, "Title"), Some("Dune"));
+        assert_eq!(tag(&tags, "Author"), Some("Unknown"));
+        assert_eq!(tag(&tags, "Words"), Some("188000"));
+    }
+
+    #[test]
+    fn sources_read_the_sidecar_next_to_a_book() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let book = dir.path().join("Dune.epub");
+        std::fs::write(dir.path().join(SIDECAR), OPF).expect("write");
+        let mut tags = Tags::from([("Title".to_string(), Some("dune".to_string()))]);
+
+        Sources::default().merge(&book, &mut tags);
+        assert_eq!(tag(&tags, "Title"), Some("dune"));
+        let sources = Sources {
+            sidecars: true,
+            library: None,
+        };
+        sources.merge(&book, &mut tags);
+        assert_eq!(tag(&tags, "Title"), Some("Dune"));
+    }
+
+    // ── libraries ────────────────────────────────────────────────────────────
+
+    /// A library in `root` with one book, by two authors, in EPUB and PDF; only the EPUB
+    /// file exists.
+    fn write_library(root: &Path) {
+        let db = Connection::open(root.join(DATABASE)).expect("create database");
+        db.execute_batch(
+            "CREATE TABLE books (id INTEGER PRIMARY KEY, title TEXT, pubdate TIMESTAMP, series_index REAL, uuid TEXT, path TEXT);
+             CREATE TABLE authors (id INTEGER PRIMARY KEY, name TEXT);
+             CREATE TABLE books_authors_link (id INTEGER PRIMARY KEY, book INTEGER, author INTEGER);
+             CREATE TABLE publishers (id INTEGER PRIMARY KEY, name TEXT);
+             CREATE TABLE books_publishers_link (id INTEGER PRIMARY KEY, book INTEGER, publisher INTEGER);
+             CREATE TABLE languages (id INTEGER PRIMARY KEY, lang_code TEXT);
+             CREATE TABLE books_languages_link (id INTEGER PRIMARY KEY, book INTEGER, lang_code INTEGER, item_order INTEGER);
+             CREATE TABLE tags (id INTEGER PRIMARY KEY, name TEXT);
+             CREATE TABLE books_tags_link (id INTEGER PRIMARY KEY, book INTEGER, tag INTEGER);
+             CREATE TABLE series (id INTEGER PRIMARY KEY, name TEXT);
+             CREATE TABLE books_series_link (id INTEGER PRIMARY KEY, book INTEGER, series INTEGER);
+             CREATE TABLE comments (id INTEGER PRIMARY KEY, book INTEGER, text TEXT);
+             CREATE TABLE identifiers (id INTEGER PRIMARY KEY, book INTEGER, type TEXT, val TEXT);
+             CREATE TABLE data (id INTEGER PRIMARY KEY, book INTEGER, format TEXT, name TEXT);
+             INSERT INTO books VALUES (1, 'Good Omens', '1990-05-01 00:00:00+00:00', 1.0, 'e1f2', 'Terry Pratchett/Good Omens (1)');
+             INSERT INTO authors VALUES (1, 'Terry Pratchett'), (2, 'Neil Gaiman');
+             INSERT INTO books_authors_link VALUES (1, 1, 1), (2, 1, 2);
+             INSERT INTO publishers VALUES (1, 'Gollancz');
+             INSERT INTO books_publishers_link VALUES (1, 1, 1);
+             INSERT INTO languages VALUES (1, 'eng');
+             INSERT INTO books_languages_link VALUES (1, 1, 1, 0);
+             INSERT INTO tags VALUES (1, 'Humour'), (2, 'Fantasy');
+             INSERT INTO books_tags_link VALUES (1, 1, 1), (2, 1, 2);
+             INSERT INTO comments VALUES (1, 1, '<div><p>The world ends on Saturday.</p></div>');
+             INSERT INTO identifiers VALUES (1, 1, 'isbn', '9780575048003');
+             INSERT INTO data VALUES (1, 1, 'EPUB', 'Good Omens - Terry Pratchett'), (2, 1, 'PDF', 'Good Omens - Terry Pratchett');",
+        )
+        .expect("fill database");
+        let dir = root.join("Terry Pratchett/Good Omens (1)");
+        std::fs::create_dir_all(&dir).expect("create book directory");
+        std::fs::write(dir.join("Good Omens - Terry Pratchett.epub"), b"").expect("write");
+    }
+
+    #[test]
+    fn library_books_are_read_from_the_database() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        write_library(dir.path());
+        let library = Library::open(dir.path()).expect("open library");
+
+        let book = dir
+            .path()
+            .join("Terry Pratchett/Good Omens (1)/Good Omens - Terry Pratchett.epub");
+        let tags = library.get(&book).expect("book in library");
+        assert_eq!(tag(tags, "Title"), Some("Good Omens"));
+        assert_eq!(tag(tags, "Author"), Some("Terry Pratchett & Neil Gaiman"));
+        assert_eq!(tag(tags, "Publisher"), Some("Gollancz"));
+        assert_eq!(tag(tags, "Date"), Some("1990-05-01"));
+        assert_eq!(tag(tags, "Language"), Some("eng"));
+        assert_eq!(tag(tags, "Identifier"), Some("9780575048003"));
+        assert_eq!(tag(tags, "Tags"), Some("Fantasy, Humour"));
+        assert_eq!(
+            tag(tags, "Description"),
+            Some("The world ends on Saturday.")
+        );
+        assert_eq!(tag(tags, "Series"), None);
+        assert!(library.get(&dir.path().join("elsewhere.epub")).is_none());
+
+        // The PDF is in the database but not on disk.
+        assert_eq!(
+            library.files(&["epub", "pdf"]),
+            [book.canonicalize().expect("canonical path")]
+        );
+        assert!(library.files(&["mobi"]).is_empty());
+    }
+
+    #[test]
+    fn renaming_is_refused_in_a_library_unless_dry_run() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        write_library(dir.path());
+        let sources = Sources {
+            sidecars: true,
+            library: Some(Library::open(dir.path()).expect("open library")),
+        };
+        let err = sources.allow_rename(false).expect_err("should refuse");
+        assert!(
+            matches!(err, CalibreError::RenameInLibrary(_)),
+            "got: {err}"
+        );
+        assert!(sources.allow_rename(true).is_ok());
+        let sidecars_only = Sources {
+            sidecars: true,
+            library: None,
+        };
+        assert!(sidecars_only.allow_rename(false).is_ok());
+    }
+
+    #[test]
+    fn a_directory_without_a_database_is_not_a_library() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let err = Library::open(dir.path()).expect_err("should fail");
+        assert!(
+            err.to_string()
+                .starts_with("Unable to read Calibre library"),
+            "got: {err}"
+        );
+    }
+}
@@ -1,5 +1,16 @@
 //! Contains a single function to build the CLI
-use clap::{Arg, ArgAction, Command};
+use clap::{Arg, ArgAction, Command, builder::EnumValueParser};
+
+use std::path::PathBuf;
+
+use crate::cover::PdfCover;
+use crate::dupes::Match;
+use crate::extract::Format;
+use crate::filter::Filter;
+use crate::lint::parse_setting;
+use crate::rename_file::ConflictPolicy;
+use crate::sanitise::FsProfile;
+use crate::utils::OutputFormat;
 
 /// Build and return the top-level [`Command`] for the application.
 ///
@@ -7,26 +18,68 @@ use clap::{Arg, ArgAction, Command};
 ///
 /// | Flag / Argument | Short | Description |
 /// |-----------------|-------|-------------|
-/// | `<filename(s)>` | — | One or more files or directories to process (required) |
+/// | `<filename(s)>` | — | One or more files or directories to process (required unless `--files-from` is given) |
+/// | `--files-from <file>` | — | Also process the paths listed in `<file>`, or stdin for `-` |
+/// | `--null` | `-0` | Paths in the `--files-from` list are NUL-separated |
 /// | `--debug` | `-d` | Enable debug logging; repeat for trace level (hidden) |
 /// | `--quiet` | `-q` | Suppress all output except errors |
 /// | `--detail-off` | `-o` | Skip per-file metadata output (useful when renaming) |
 /// | `--dry-run` | `-r` | Show what would happen without making changes |
 /// | `--recursive` | `-R` | Recurse into directories, processing all supported files |
+/// | `--include <glob>` | — | Only collect files matching the glob (repeatable) |
+/// | `--exclude <glob>` | — | Skip files and directories matching the glob (repeatable) |
+/// | `--max-depth <n>` / `--min-depth <n>` | — | Limit how deep directories are walked |
+/// | `--skip-hidden` | — | Skip hidden files and directories |
+/// | `--no-follow-links` | — | Don't follow symbolic links while walking |
+/// | `--no-ignore-files` | — | Don't honour `.docmetaignore` files |
+/// | `--jobs <n>` | `-j` | Read metadata from `n` files at a time (default 1, `0` = one per CPU) |
+/// | `--no-cache` | — | Read every file, ignoring and not updating the metadata cache |
+/// | `--where <expr>` | `-w` | Only process files whose metadata matches `<expr>` |
 /// | `--rename-file <pattern>` | `-n` | Rename each file using `<pattern>` as the template |
+/// | `--interactive` | `-i` | Confirm, skip or edit each rename before anything is moved |
+/// | `--on-conflict <policy>` | — | What to do when the new filename is taken (default `number`) |
+/// | `--fs-profile <profile>` | — | Filesystem rules for new filenames (default `windows`) |
+/// | `--max-name-bytes <n>` | — | Maximum length of a new filename in bytes (default 255) |
+///
+/// Subcommands:
+///
+/// | Subcommand | Description |
+/// |------------|-------------|
+/// | `undo [journal]` | Reverse the renames recorded in a journal (default: the latest one) |
+/// | `watch <dir> -n <pattern>` | Rename new files as they arrive in `<dir>` |
+/// | `dupes <filename(s)>` | List files that are probably the same book |
+/// | `lint <filename(s)>` | Check files' metadata for problems, exiting non-zero on errors |
+/// | `cover <filename(s)> --out <dir>` | Save each book's cover image into `<dir>` |
+/// | `toc <filename(s)>` | Print each book's table of contents |
+/// | `verify <filename(s)>` | Check files are structurally intact, exiting non-zero on problems |
+/// | `cache clear` | Delete the metadata cache |
+/// | `cache prune` | Drop cached files that no longer exist |
 pub fn build() -> Command {
     Command::new(clap::crate_name!())
         .about(clap::crate_description!())
         .version(clap::crate_version!())
         // .author(clap::crate_authors!("\n"))
         .long_about("This program display eBook metadata and rename files based on this metadata.")
+        .subcommand_negates_reqs(true)
+        .args_conflicts_with_subcommands(true)
+        .args(input_args("process", &["files-from", "calibre-library"]))
         .arg(
-            Arg::new("read")
-                .value_name("filename(s)")
-                .help("One or more files or directories to process. Wildcards are supported. Use --recursive to traverse directories.")
-                .num_args(1..)
-                .required(true)
-                .action(ArgAction::Append),
+            Arg::new("files-from")
+                .long("files-from")
+                .value_name("file")
+                .help("Also process the paths listed in this file, one per line. Use '-' to read the list from stdin. Listed files are filtered like files found by --recursive.")
+                .num_args(1)
+                .value_parser(clap::value_parser!(PathBuf))
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("null")
+                .short('0')
+                .long("null")
+                .help("Paths in the --files-from list are separated by NUL bytes, as written by 'find -print0'.")
+                .num_args(0)
+                .requires("files-from")
+                .action(ArgAction::SetTrue),
         )
         .arg( // Hidden debug parameter
             Arg::new("debug")
@@ -36,6 +89,7 @@ pub fn build() -> Command {
                 .hide(true)
                 .num_args(0)
                 .action(ArgAction::Count)
+                .global(true)
         )
         .arg( // Don't print any information
             Arg::new("quiet")
@@ -44,6 +98,7 @@ pub fn build() -> Command {
                 .help("Don't produce any output except errors while working.")
                 .num_args(0)
                 .action(ArgAction::SetTrue)
+                .global(true)
         )
         .arg( // Don't export detail information
             Arg::new("detail-off")
@@ -53,6 +108,13 @@ pub fn build() -> Command {
                 .num_args(0)
                 .action(ArgAction::SetTrue)
         )
+        .arg(
+            Arg::new("stats")
+                .long("stats")
+                .help("Read the text of each file to show its Words, Characters, Pages and Images. Done anyway when --where or the rename pattern uses them.")
+                .num_args(0)
+                .action(ArgAction::SetTrue),
+        )
         .arg( // Don't export detail information
             Arg::new("dry-run")
                 .short('r')
@@ -61,13 +123,18 @@ pub fn build() -> Command {
                 .num_args(0)
                 .action(ArgAction::SetTrue)
         )
+        .args(walk_args())
+        .args(extract_args())
+        .args(calibre_args())
         .arg(
-            Arg::new("recursive")
-                .short('R')
-                .long("recursive")
-                .help("Recurse into directories and process all supported ebook files found.")
-                .num_args(0)
-                .action(ArgAction::SetTrue),
+            Arg::new("where")
+                .short('w')
+                .long("where")
+                .value_name("expr")
+                .help("Only print or rename files whose metadata matches this expression, e.g. \"Format = epub and Publisher is missing\" or \"Year < 2000\".")
+                .num_args(1)
+                .value_parser(Filter::parse)
+                .action(ArgAction::Set),
         )
         .arg( // Rename filenames
             Arg::new("rename-pattern")
@@ -79,4 +146,411 @@ pub fn build() -> Command {
                 .required(false)
                 .hide(false),
         )
+        .arg(
+            Arg::new("interactive")
+                .short('i')
+                .long("interactive")
+                .help("Ask before each rename, showing the old and new names and key metadata. Renames can be accepted, skipped or edited.")
+                .num_args(0)
+                .requires("rename-pattern")
+                .action(ArgAction::SetTrue),
+        )
+        .args(rename_args())
+        .subcommand(undo())
+        .subcommand(watch())
+        .subcommand(dupes())
+        .subcommand(lint())
+        .subcommand(cover())
+        .subcommand(toc())
+        .subcommand(verify())
+        .subcommand(cache())
+}
+
+/// Arguments that control how new filenames are made. Shared by the top level and `watch`.
+fn rename_args() -> [Arg; 3] {
+    [
+        Arg::new("on-conflict")
+            .long("on-conflict")
+            .value_name("policy")
+            .help("What to do when a file with the new name already exists.")
+            .num_args(1)
+            .value_parser(EnumValueParser::<ConflictPolicy>::new())
+            .default_value("number")
+            .action(ArgAction::Set),
+        Arg::new("fs-profile")
+            .long("fs-profile")
+            .value_name("profile")
+            .help("The filesystem rules new filenames must follow.")
+            .num_args(1)
+            .value_parser(EnumValueParser::<FsProfile>::new())
+            .default_value("windows")
+            .action(ArgAction::Set),
+        Arg::new("max-name-bytes")
+            .long("max-name-bytes")
+            .value_name("n")
+            .help("The maximum length of a new filename, including the extension, in bytes.")
+            .num_args(1)
+            .value_parser(clap::value_parser!(u16).range(8..))
+            .default_value("255")
+            .action(ArgAction::Set),
+    ]
+}
+
+/// Arguments that merge metadata kept by Calibre over the metadata read from the files.
+fn calibre_args() -> [Arg; 2] {
+    [
+        Arg::new("sidecar")
+            .long("sidecar")
+            .help("Prefer the metadata in a metadata.opf file next to each book, as Calibre writes them.")
+            .num_args(0)
+            .action(ArgAction::SetTrue),
+        Arg::new("calibre-library")
+            .long("calibre-library")
+            .value_name("dir")
+            .help("Prefer the metadata in the metadata.db of this Calibre library for the books in it. With no files given, every book in the library is read.")
+            .num_args(1)
+            .value_parser(clap::value_parser!(PathBuf))
+            .action(ArgAction::Set),
+    ]
+}
+
+/// Arguments that control how directories are walked. Only used with `--recursive`.
+fn walk_args() -> [Arg; 7] {
+    [
+        Arg::new("include")
+            .long("include")
+            .value_name("glob")
+            .help("Only process files matching this glob, e.g. '**/*.epub'. Can be given more than once.")
+            .num_args(1)
+            .action(ArgAction::Append),
+        Arg::new("exclude")
+            .long("exclude")
+            .value_name("glob")
+            .help("Skip files and directories matching this glob, e.g. '**/.Trash/**'. Can be given more than once.")
+            .num_args(1)
+            .action(ArgAction::Append),
+        Arg::new("max-depth")
+            .long("max-depth")
+            .value_name("n")
+            .help("Don't descend more than n directories below each input directory.")
+            .num_args(1)
+            .value_parser(clap::value_parser!(usize))
+            .action(ArgAction::Set),
+        Arg::new("min-depth")
+            .long("min-depth")
+            .value_name("n")
+            .help("Only process files at least n directories below each input directory (1 = directly inside it).")
+            .num_args(1)
+            .value_parser(clap::value_parser!(usize))
+            .action(ArgAction::Set),
+        Arg::new("skip-hidden")
+            .long("skip-hidden")
+            .help("Skip hidden files and directories (names starting with '.').")
+            .num_args(0)
+            .action(ArgAction::SetTrue),
+        Arg::new("no-follow-links")
+            .long("no-follow-links")
+            .help("Don't follow symbolic links while walking directories.")
+            .num_args(0)
+            .action(ArgAction::SetTrue),
+        Arg::new("no-ignore-files")
+            .long("no-ignore-files")
+            .help("Don't honour .docmetaignore files, whether in walked directories or above the files given.")
+            .num_args(0)
+            .action(ArgAction::SetTrue),
+    ]
+}
+
+/// The files to work on, and `--recursive`. `verb` says what is done with them. The files are
+/// required unless one of `required_unless` is given.
+fn input_args(verb: &str, required_unless: &[&'static str]) -> [Arg; 2] {
+    let read = Arg::new("read")
+        .value_name("filename(s)")
+        .help(format!("One or more files or directories to {verb}. Wildcards are supported. Use --recursive to traverse directories."))
+        .num_args(1..)
+        .value_parser(clap::value_parser!(PathBuf))
+        .action(ArgAction::Append);
+    let read = if required_unless.is_empty() {
+        read.required(true)
+    } else {
+        read.required_unless_present_any(required_unless.iter().copied())
+    };
+    [
+        read,
+        Arg::new("recursive")
+            .short('R')
+            .long("recursive")
+            .help(format!(
+                "Recurse into directories and {verb} all supported ebook files found."
+            ))
+            .num_args(0)
+            .action(ArgAction::SetTrue),
+    ]
+}
+
+/// Arguments that control how metadata is read from the files.
+fn extract_args() -> [Arg; 2] {
+    [
+        Arg::new("jobs")
+            .short('j')
+            .long("jobs")
+            .value_name("n")
+            .help("Read metadata from n files at a time. 0 uses one thread per CPU. Output order is not affected.")
+            .num_args(1)
+            .value_parser(clap::value_parser!(usize))
+            .default_value("1")
+            .action(ArgAction::Set),
+        no_cache_arg(),
+    ]
+}
+
+/// `--no-cache`, on its own for `watch`, which reads one file at a time.
+fn no_cache_arg() -> Arg {
+    Arg::new("no-cache")
+        .long("no-cache")
+        .help("Read every file even if its metadata is cached, and leave the cache untouched.")
+        .num_args(0)
+        .action(ArgAction::SetTrue)
+}
+
+/// `--format` for the subcommands that write a report. `what` names what is written.
+fn format_arg(what: &str) -> Arg {
+    Arg::new("format")
+        .long("format")
+        .value_name("format")
+        .help(format!("How to write the {what}."))
+        .num_args(1)
+        .value_parser(EnumValueParser::<OutputFormat>::new())
+        .default_value("text")
+        .action(ArgAction::Set)
+}
+
+/// Build the `undo` subcommand.
+fn undo() -> Command {
+    Command::new("undo")
+        .about("Reverse the renames recorded in a rename journal.")
+        .long_about("Reverse the renames recorded in a rename journal, newest first. Files that have moved or changed since they were renamed are left alone.")
+        .arg(
+            Arg::new("journal")
+                .value_name("journal")
+                .help("The journal to undo. Defaults to the most recent journal that has not been undone.")
+                .num_args(1)
+                .value_parser(clap::value_parser!(PathBuf))
+                .required(false)
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("dry-run")
+                .short('r')
+                .long("dry-run")
+                .help("Show what would be moved back without moving anything.")
+                .num_args(0)
+                .action(ArgAction::SetTrue),
+        )
+}
+
+/// Build the `watch` subcommand.
+fn watch() -> Command {
+    Command::new("watch")
+        .about("Rename new files as they arrive in a directory.")
+        .long_about("Watch a directory and rename files as they are created in it or moved into it. A file is renamed once its size has not changed for the settle period, so downloads and copies are left alone until they are complete. Runs until stopped with Ctrl-C.")
+        .arg(
+            Arg::new("dir")
+                .value_name("dir")
+                .help("The directory to watch.")
+                .num_args(1)
+                .value_parser(clap::value_parser!(PathBuf))
+                .required(true)
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("rename-pattern")
+                .short('n')
+                .long("rename-file")
+                .help("The pattern to rename new files with.")
+                .num_args(1)
+                .required(true)
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("recursive")
+                .short('R')
+                .long("recursive")
+                .help("Also watch the directory's subdirectories.")
+                .num_args(0)
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("settle")
+                .long("settle")
+                .value_name("seconds")
+                .help("How long a new file's size must stay the same before it is renamed.")
+                .num_args(1)
+                .value_parser(clap::value_parser!(u64))
+                .default_value("5")
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("dry-run")
+                .short('r')
+                .long("dry-run")
+                .help("Show what would be renamed without renaming anything.")
+                .num_args(0)
+                .action(ArgAction::SetTrue),
+        )
+        .arg(no_cache_arg())
+        .args(rename_args())
+}
+
+/// Build the `dupes` subcommand.
+fn dupes() -> Command {
+    Command::new("dupes")
+        .about("List files that are probably the same book.")
+        .long_about("List groups of files that are probably the same book: identical copies, files with the same ISBN or identifier, and files with similar titles and authors. Nothing is changed; with --prefer, each group suggests which file to keep.")
+        .args(input_args("check", &["calibre-library"]))
+        .args(walk_args())
+        .arg(
+            Arg::new("by")
+                .long("by")
+                .value_name("match")
+                .help("How to match files. Give several separated by commas.")
+                .num_args(1)
+                .value_delimiter(',')
+                .value_parser(EnumValueParser::<Match>::new())
+                .default_value("hash,identifier,title")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("similarity")
+                .long("similarity")
+                .value_name("percent")
+                .help("How alike titles and authors must be to match.")
+                .num_args(1)
+                .value_parser(clap::value_parser!(u8).range(1..=100))
+                .default_value("90")
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("prefer")
+                .long("prefer")
+                .value_name("formats")
+                .help("Suggest keeping one file per group, preferring these formats in order, e.g. 'epub,pdf'.")
+                .num_args(1)
+                .value_delimiter(',')
+                .value_parser(EnumValueParser::<Format>::new())
+                .action(ArgAction::Append),
+        )
+        .arg(format_arg("report"))
+        .args(extract_args())
+        .args(calibre_args())
+}
+
+/// Build the `lint` subcommand.
+fn lint() -> Command {
+    Command::new("lint")
+        .about("Check files' metadata for problems.")
+        .long_about("Check each file's metadata against a set of rules and report what is wrong, e.g. a missing title, a placeholder author or an ISBN with a bad check digit. Exits with a non-zero status if any rule with severity 'error' is broken, so it can gate files going into a library.")
+        .after_help("Rules, and their default severity:\n  unreadable          error    the file can't be read\n  missing-title       error    there is no title\n  missing-author      warning  there is no author\n  title-is-filename   warning  the title is just the filename\n  placeholder-author  warning  the author is e.g. 'Unknown' or 'calibre'\n  invalid-isbn        error    the ISBN has a bad check digit\n  future-year         warning  the year is after this one\n  html-description    warning  the description contains raw HTML\n  missing-language    warning  there is no language")
+        .args(input_args("check", &["calibre-library"]))
+        .args(walk_args())
+        .arg(
+            Arg::new("rule")
+                .long("rule")
+                .value_name("rule=severity")
+                .help("Set a rule's severity to error, warning or off, e.g. 'missing-author=error'. Give several separated by commas, or repeat.")
+                .num_args(1)
+                .value_delimiter(',')
+                .value_parser(parse_setting)
+                .action(ArgAction::Append),
+        )
+        .arg(format_arg("report"))
+        .args(extract_args())
+        .args(calibre_args())
+}
+
+/// Build the `cover` subcommand.
+fn cover() -> Command {
+    Command::new("cover")
+        .about("Save each book's cover image.")
+        .long_about("Save the cover image of each EPUB, MOBI, AZW3 or CBZ file into a directory, named by a template. PDFs have no cover unless --pdf first-image is given.")
+        .args(input_args("take covers from", &["calibre-library"]))
+        .args(walk_args())
+        .arg(
+            Arg::new("out")
+                .long("out")
+                .value_name("dir")
+                .help("Directory to save the covers in. It is created if need be.")
+                .num_args(1)
+                .value_parser(clap::value_parser!(PathBuf))
+                .required(true)
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("name")
+                .long("name")
+                .value_name("template")
+                .help("Name for each cover, without extension, as a rename pattern. {stem} is the book's filename without its extension.")
+                .num_args(1)
+                .default_value("{stem}")
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("pdf")
+                .long("pdf")
+                .value_name("cover")
+                .help("What to take as the cover of a PDF.")
+                .num_args(1)
+                .value_parser(EnumValueParser::<PdfCover>::new())
+                .default_value("none")
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("force")
+                .long("force")
+                .help("Replace covers that already exist in the output directory.")
+                .num_args(0)
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("dry-run")
+                .short('r')
+                .long("dry-run")
+                .help("Show which covers would be saved, without saving them.")
+                .num_args(0)
+                .action(ArgAction::SetTrue),
+        )
+        .args(extract_args())
+        .args(calibre_args())
+}
+
+/// Build the `toc` subcommand.
+fn toc() -> Command {
+    Command::new("toc")
+        .about("Print each book's table of contents.")
+        .long_about("Print the table of contents of each file: the navigation document or NCX of an EPUB, the NCX index of a MOBI, or the outline (bookmarks) of a PDF. Each entry is shown with its depth, label and target.")
+        .args(input_args("read", &[]))
+        .args(walk_args())
+        .arg(format_arg("tables of contents"))
+}
+
+/// Build the `verify` subcommand.
+fn verify() -> Command {
+    Command::new("verify")
+        .about("Check files are structurally intact.")
+        .long_about("Check that each file can be read from end to end: the ZIP central directory and CRCs of EPUB and CBZ files the mimetype, container, package document and manifest of EPUBs, the end-of-file marker, trailer and cross-reference table of PDFs, and the record list of MOBI, AZW and AZW3 files. Each problem is reported with a code, and the exit status is non-zero if any are found.")
+        .after_help("Codes:\n  unreadable             the file can't be opened or read\n  zip-central-directory  the ZIP central directory is missing or corrupt\n  zip-crc                a ZIP entry doesn't match its CRC-32\n  zip-entry              a ZIP entry can't be read\n  epub-mimetype          mimetype is not first, stored and application/epub+zip\n  epub-container         META-INF/container.xml is missing or names no OPF\n  opf-missing            the OPF the container names is missing\n  opf-malformed          the OPF is not well-formed XML\n  manifest-item-missing  a manifest item is missing from the archive\n  unique-identifier      the OPF's unique-identifier names no dc:identifier\n  spine-item-missing     a spine itemref names no manifest item\n  nav-missing            an EPUB 3 OPF has no navigation document\n  invalid-date           a dc:date is not in W3CDTF form\n  pdf-eof                there is no %%EOF marker at the end\n  pdf-startxref          startxref is missing or points at the wrong place\n  pdf-trailer            the trailer or cross-reference table can't be parsed\n  pdf-object             an object in the cross-reference table is misplaced or unreadable\n  mobi-header            the header or record list is cut short\n  mobi-record-offset     a record starts outside the file\n  mobi-record-order      a record starts before the one ahead of it")
+        .args(input_args("check", &[]))
+        .args(walk_args())
+        .arg(format_arg("report"))
+}
+
+/// Build the `cache` subcommand.
+fn cache() -> Command {
+    Command::new("cache")
+        .about("Manage the metadata cache.")
+        .subcommand_required(true)
+        .subcommand(Command::new("clear").about("Delete the metadata cache."))
+        .subcommand(
+            Command::new("prune").about("Drop the cached metadata of files that no longer exist."),
+        )
 }
@@ -0,0 +1,377 @@
+//! Extract the cover image from an ebook.
+//!
+//! EPUBs name their cover in the manifest (the `cover-image` property, or a
+//! `<meta name="cover">` pointing at a manifest item), MOBI and AZW3 files in the EXTH
+//! cover offset record, and comic book archives (CBZ) simply start with it. PDFs have no
+//! cover as such; [`PdfCover`] says whether to take the largest image on the first page.
+use clap::{ValueEnum, builder::PossibleValue};
+use epub::doc::EpubDoc;
+use mobi::Mobi;
+use std::{
+    fs::File,
+    io::{BufReader, Read},
+    path::Path,
+};
+
+/// Extensions of the files a cover can be taken from.
+pub const EXTENSIONS: &[&str] = &["epub", "mobi", "azw", "azw3", "cbz", "pdf"];
+
+/// The EXTH record holding the cover's offset from the first image record.
+const EXTH_COVER_OFFSET: u32 = 201;
+
+/// An offset meaning there is no cover.
+const NO_OFFSET: u32 = u32::MAX;
+
+/// What to take as the cover of a PDF.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PdfCover {
+    /// PDFs have no cover.
+    #[default]
+    None,
+    /// The largest image embedded in the first page, if it is a JPEG or JPEG 2000.
+    FirstImage,
+}
+
+impl ValueEnum for PdfCover {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Self::None, Self::FirstImage]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(match self {
+            Self::None => PossibleValue::new("none").help("Skip PDFs"),
+            Self::FirstImage => {
+                PossibleValue::new("first-image").help("The largest image on the first page")
+            }
+        })
+    }
+}
+
+/// A cover image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub data: Vec<u8>,
+    /// The extension for the image's type, e.g. `jpg`.
+    pub extension: &'static str,
+}
+
+impl Image {
+    /// An image of `data`, whose type is worked out from its first bytes or, failing that,
+    /// is `fallback`, e.g. from the MIME type. Returns `None` if it is not an image.
+    fn new(data: Vec<u8>, fallback: Option<&'static str>) -> Option<Self> {
+        let extension = sniff(&data).or(fallback)?;
+        Some(Self { data, extension })
+    }
+}
+
+/// The cover of the ebook at `path`, or `None` if it has none.
+///
+/// Files are told apart by their extension (see [`EXTENSIONS`]); other files have no cover.
+///
+/// # Errors
+///
+/// Returns `Err` if the file cannot be opened or parsed.
+pub fn extract(path: &Path, pdf: PdfCover) -> anyhow::Result<Option<Image>> {
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "epub" => from_epub(path),
+        "mobi" | "azw" | "azw3" => from_mobi(path),
+        "cbz" => from_cbz(path),
+        "pdf" if pdf == PdfCover::FirstImage => from_pdf(path),
+        _ => Ok(None),
+    }
+}
+
+/// The manifest item with the `cover-image` property, or the one `<meta name="cover">`
+/// names, either by id or, as some tools write, by path.
+fn from_epub(path: &Path) -> anyhow::Result<Option<Image>> {
+    let mut doc = EpubDoc::new(path)?;
+    let by_property = doc.resources.iter().find_map(|(id, item)| {
+        item.properties
+            .as_deref()
+            .is_some_and(|p| p.split_ascii_whitespace().any(|p| p == "cover-image"))
+            .then(|| id.clone())
+    });
+    let meta = doc.mdata("cover").map(|item| item.value.clone());
+
+    let resource = by_property
+        .or_else(|| meta.clone().filter(|id| doc.resources.contains_key(id)))
+        .and_then(|id| doc.get_resource(&id))
+        .map(|(data, mime)| (data, Some(mime)))
+        .or_else(|| {
+            let path = meta?;
+            let mime = doc
+                .resources
+                .values()
+                .find(|item| item.path == Path::new(&path))
+                .map(|item| item.mime.clone());
+            doc.get_resource_by_path(&path).map(|data| (data, mime))
+        });
+    Ok(resource.and_then(|(data, mime)| Image::new(data, mime.as_deref().and_then(from_mime))))
+}
+
+/// The image record the EXTH cover offset points at.
+fn from_mobi(path: &Path) -> anyhow::Result<Option<Image>> {
+    let mobi_file = Mobi::from_path(path)?;
+    let offset = mobi_file
+        .metadata
+        .exth
+        .get_record_position(EXTH_COVER_OFFSET)
+        .and_then(|values| values.first())
+        .and_then(|value| <[u8; 4]>::try_from(value.as_slice()).ok())
+        .map(u32::from_be_bytes)
+        .filter(|&offset| offset != NO_OFFSET);
+    let Some(offset) = offset else {
+        return Ok(None);
+    };
+    let index = mobi_file.metadata.mobi.first_image_index as usize + offset as usize;
+    let records = mobi_file.raw_records();
+    Ok(records
+        .records()
+        .get(index)
+        .and_then(|record| Image::new(record.content.to_vec(), None)))
+}
+
+/// The first image in the archive, in name order.
+fn from_cbz(path: &Path) -> anyhow::Result<Option<Image>> {
+    let mut archive = zip::ZipArchive::new(BufReader::new(File::open(path)?))?;
+    let mut names = archive
+        .file_names()
+        .filter(|name| !name.ends_with('/') && !name.starts_with("__MACOSX/"))
+        .filter(|name| {
+            Path::new(name)
+                .extension()
+                .and_then(|e| e.to_str())
+                .is_some_and(|e| from_extension(e).is_some())
+        })
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    names.sort_by_key(|name| name.to_lowercase());
+    let Some(first) = names.first() else {
+        return Ok(None);
+    };
+    let mut data = Vec::new();
+    archive.by_name(first)?.read_to_end(&mut data)?;
+    let extension = Path::new(first)
+        .extension()
+        .and_then(|e| e.to_str())
+        .and_then(from_extension);
+    Ok(Image::new(data, extension))
+}
+
+/// The largest image on the first page, if it is stored as a JPEG or JPEG 2000.
+fn from_pdf(path: &Path) -> anyhow::Result<Option<Image>> {
+    use pdf::{
+        enc::StreamFilter,
+        object::{Resolve, XObject},
+    };
+
+    let file = pdf::file::FileOptions::cached().open(path)?;
+    let page = file.get_page(0)?;
+    // A page without resources has no images.
+    let Ok(resources) = page.resources() else {
+        return Ok(None);
+    };
+    let mut largest = None;
+    for &xobject in resources.xobjects.values() {
+        let xobject = file.get(xobject)?;
+        if let XObject::Image(image) = &*xobject {
+            let area = u64::from(image.width) * u64::from(image.height);
+            if largest.as_ref().is_none_or(|(a, _)| area > *a) {
+                largest = Some((area, image.clone()));
+            }
+        }
+    }
+    let Some((_, image)) = largest else {
+        return Ok(None);
+    };
+    let (data, filter) = image.raw_image_data(&file)?;
+    let extension = match filter {
+        Some(StreamFilter::DCTDecode(_)) => "jpg",
+        Some(StreamFilter::JPXDecode) => "jp2",
+        _ => anyhow::bail!(
+            "the first page's image is not a JPEG or JPEG 2000, so it can't be extracted as it is"
+        ),
+    };
+    Ok(Some(Image {
+        data: data.to_vec(),
+        extension,
+    }))
+}
+
+/// The extension for the image type of `data`, judged by its magic number.
+pub fn sniff(data: &[u8]) -> Option<&'static str> {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"\xFF\xD8\xFF", "jpg"),
+        (b"\x89PNG\r\n\x1A\n", "png"),
+        (b"GIF87a", "gif"),
+        (b"GIF89a", "gif"),
+        (b"BM", "bmp"),
+        (b"\0\0\0\x0CjP  ", "jp2"),
+    ];
+    if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        return Some("webp");
+    }
+    SIGNATURES
+        .iter()
+        .find(|(magic, _)| data.starts_with(magic))
+        .map(|&(_, extension)| extension)
+}
+
+/// The extension for an image `mime` type, e.g. `svg` for `image/svg+xml`.
+fn from_mime(mime: &str) -> Option<&'static str> {
+    from_extension(mime.strip_prefix("image/")?.split('+').next()?)
+}
+
+/// The canonical extension for an image file extension, e.g. `jpg` for `JPEG`.
+fn from_extension(extension: &str) -> Option<&'static str> {
+    Some(match extension.to_ascii_lowercase().as_str() {
+        "jpg" | "jpeg" => "jpg",
+        "png" => "png",
+        "gif" => "gif",
+        "webp" => "webp",
+        "bmp" => "bmp",
+        "svg" => "svg",
+        "jp2" => "jp2",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::{ZipWriter, write::SimpleFileOptions};
+
+    const JPEG: &[u8] = b"\xFF\xD8\xFF\xE0 jpeg";
+    const PNG: &[u8] = b"\x89PNG\r\n\x1A\n png";
+
+    /// Write a zip archive of `entries` to `path`.
+    fn write_zip(path: &Path, entries: &[(&str, &[u8])]) {
+        let mut zip = ZipWriter::new(File::create(path).expect("create"));
+        for (name, data) in entries {
+            zip.start_file(*name, SimpleFileOptions::default())
+                .expect("start file");
+            zip.write_all(data).expect("write");
+        }
+        zip.finish().expect("finish");
+    }
+
+    /// Write an EPUB to `path` whose OPF has `metadata` and `manifest` elements.
+    fn write_epub(path: &Path, version: &str, metadata: &str, manifest: &str) {
+        let opf = format!(
+            r#"<?xml version="1.0"?><package xmlns="http://www.idpf.org/2007/opf" version="{version}"><metadata xmlns:dc="http://purl.org/dc/elements/1.1/"><dc:title>T</dc:title>{metadata}</metadata><manifest><item id="ch1" href="ch1.xhtml" media-type="application/xhtml+xml"/>{manifest}</manifest><spine><itemref idref="ch1"/></spine></package>"#
+        );
+        write_zip(
+            path,
+            &[
+                ("mimetype", b"application/epub+zip"),
+                (
+                    "META-INF/container.xml",
+                    br#"<?xml version="1.0"?><container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container"><rootfiles><rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/></rootfiles></container>"#,
+                ),
+                ("OEBPS/content.opf", opf.as_bytes()),
+                ("OEBPS/ch1.xhtml", b"<html/>"),
+                ("OEBPS/images/front.jpg", JPEG),
+                ("OEBPS/images/back.png", PNG),
+            ],
+        );
+    }
+
+    // ── EPUB ─────────────────────────────────────────────────────────────────
+
+    #[test]
+    fn epub_cover_is_the_cover_image_item() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let path = dir.path().join("book.epub");
+        write_epub(
+            &path,
+            "3.0",
+            r#"<meta name="cover" content="back"/>"#,
+            r#"<item id="back" href="images/back.png" media-type="image/png"/><item id="front" href="images/front.jpg" media-type="image/jpeg" properties="cover-image"/>"#,
+        );
+        let image = extract(&path, PdfCover::None)
+            .expect("read")
+            .expect("cover");
+        assert_eq!(image.data, JPEG);
+        assert_eq!(image.extension, "jpg");
+    }
+
+    #[test]
+    fn epub_cover_is_named_by_meta() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let path = dir.path().join("book.epub");
+        write_epub(
+            &path,
+            "2.0",
+            r#"<meta name="cover" content="back"/>"#,
+            r#"<item id="back" href="images/back.png" media-type="image/png"/>"#,
+        );
+        let image = extract(&path, PdfCover::None)
+            .expect("read")
+            .expect("cover");
+        assert_eq!(image.extension, "png");
+    }
+
+    #[test]
+    fn epub_without_a_cover_has_none() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let path = dir.path().join("book.epub");
+        write_epub(&path, "2.0", "", "");
+        assert_eq!(extract(&path, PdfCover::None).expect("read"), None);
+    }
+
+    // ── CBZ ──────────────────────────────────────────────────────────────────
+
+    #[test]
+    fn cbz_cover_is_the_first_image_by_name() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let path = dir.path().join("comic.cbz");
+        write_zip(
+            &path,
+            &[
+                ("ComicInfo.xml", b"<ComicInfo/>"),
+                ("page02.png", PNG),
+                ("__MACOSX/._page01.jpg", b"junk"),
+                ("Page01.jpg", JPEG),
+            ],
+        );
+        let image = extract(&path, PdfCover::None)
+            .expect("read")
+            .expect("cover");
+        assert_eq!(image.data, JPEG);
+    }
+
+    #[test]
+    fn cbz_without_images_has_no_cover() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let path = dir.path().join("comic.cbz");
+        write_zip(&path, &[("ComicInfo.xml", b"<ComicInfo/>")]);
+        assert_eq!(extract(&path, PdfCover::None).expect("read"), None);
+    }
+
+    // ── image types ──────────────────────────────────────────────────────────
+
+    #[test]
+    fn image_types_are_sniffed_from_their_data() {
+        assert_eq!(sniff(JPEG), Some("jpg"));
+        assert_eq!(sniff(PNG), Some("png"));
+        assert_eq!(sniff(b"GIF89a..."), Some("gif"));
+        assert_eq!(sniff(b"RIFF\0\0\0\0WEBPVP8 "), Some("webp"));
+        assert_eq!(sniff(b"<svg/>"), None);
+    }
+
+    #[test]
+    fn image_types_fall_back_to_the_mime_type() {
+        assert_eq!(from_mime("image/svg+xml"), Some("svg"));
+        assert_eq!(from_mime("image/JPEG"), Some("jpg"));
+        assert_eq!(from_mime("text/html"), None);
+        assert_eq!(
+            Image::new(b"<svg/>".to_vec(), from_mime("image/svg+xml")).map(|i| i.extension),
+            Some("svg")
+        );
+    }
+}
@@ -0,0 +1,495 @@
+//! Parse the many ways ebooks write dates into a [`Date`] that knows how precise it is.
+//!
+//! EPUBs mostly use ISO 8601 (`2011`, `2011-03`, `2011-03-15T04:00:00+00:00`), PDFs use
+//! `D:YYYYMMDDHHmmSS` strings, and MOBI dates are free-form: `March 2011`, `15/03/2011`,
+//! `Tue, 15 Mar 2011 10:00:00 +0000`. [`parse`] reads all of these and rejects years that
+//! are implausible for a book, such as the `0101-01-01` placeholder some tools write.
+use crate::extract::Tags;
+use std::{
+    fmt,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The earliest year accepted as a real publication date.
+const MIN_YEAR: u16 = 1000;
+/// The latest year accepted as a real publication date.
+const MAX_YEAR: u16 = 2200;
+
+const MONTHS: [&str; 12] = [
+    "january",
+    "february",
+    "march",
+    "april",
+    "may",
+    "june",
+    "july",
+    "august",
+    "september",
+    "october",
+    "november",
+    "december",
+];
+
+/// How much of a [`Date`] is known.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Precision {
+    Year,
+    Month,
+    Day,
+}
+
+/// A calendar date, possibly only to the month or year.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Date {
+    pub year: u16,
+    /// 1 to 12, if known.
+    pub month: Option<u8>,
+    /// 1 to 31, if known. Only set when `month` is.
+    pub day: Option<u8>,
+}
+
+impl Date {
+    /// A date from its parts, or `None` if the year is implausible or the month or day do
+    /// not exist (e.g. 30 February).
+    pub fn new(year: u16, month: Option<u8>, day: Option<u8>) -> Option<Self> {
+        if !(MIN_YEAR..=MAX_YEAR).contains(&year) {
+            return None;
+        }
+        if let Some(month) = month {
+            if !(1..=12).contains(&month) {
+                return None;
+            }
+            if let Some(day) = day {
+                if day == 0 || day > days_in_month(year, month) {
+                    return None;
+                }
+            }
+        } else if day.is_some() {
+            return None;
+        }
+        Some(Self { year, month, day })
+    }
+
+    /// How much of the date is known.
+    pub fn precision(&self) -> Precision {
+        match (self.month, self.day) {
+            (Some(_), Some(_)) => Precision::Day,
+            (Some(_), None) => Precision::Month,
+            _ => Precision::Year,
+        }
+    }
+}
+
+/// ISO 8601 to the date's precision: `2011`, `2011-03` or `2011-03-15`.
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (year, month, day) = (self.year, self.month.unwrap_or(0), self.day.unwrap_or(0));
+        match self.precision() {
+            Precision::Year => write!(f, "{year:04}"),
+            Precision::Month => write!(f, "{year:04}-{month:02}"),
+            Precision::Day => write!(f, "{year:04}-{month:02}-{day:02}"),
+        }
+    }
+}
+
+/// Read a date written in any of the forms described in the [module docs](self).
+///
+/// Numeric dates with the year last are read day first (`15.03.2011`, `15-03-2011`),
+/// except with slashes, where the order is worked out from which number can be a month;
+/// if both can (`03/04/2011`), only the year is kept.
+pub fn parse(raw: &str) -> Option<Date> {
+    let raw = raw.trim();
+    let raw = raw.strip_prefix("D:").unwrap_or(raw);
+    if raw.is_empty() {
+        return None;
+    }
+    compact(raw)
+        .or_else(|| year_first(raw))
+        .or_else(|| year_last(raw))
+        .or_else(|| with_month_name(raw))
+        .or_else(|| bare_year(raw))
+}
+
+/// Add the tags derived from the `Date` tag to `tags`:
+///
+/// * `Date` - the date in ISO 8601 form, to its precision (left as it is if it cannot be read)
+/// * `Year` - the four-digit year
+/// * `Month`, `Day` - two digits each, if known
+///
+/// If there is no `Date`, the `Year` tag is read instead. Returns the parsed date.
+pub fn add_tags(tags: &mut Tags) -> Option<Date> {
+    let raw = |key: &str| tags.get(key).and_then(Option::as_deref);
+    let date = raw("Date").or_else(|| raw("Year")).and_then(parse);
+    if let Some(date) = date {
+        tags.insert("Date".to_string(), Some(date.to_string()));
+    }
+    tags.insert("Year".to_string(), date.map(|d| format!("{:04}", d.year)));
+    tags.insert(
+        "Month".to_string(),
+        date.and_then(|d| d.month).map(|m| format!("{m:02}")),
+    );
+    tags.insert(
+        "Day".to_string(),
+        date.and_then(|d| d.day).map(|d| format!("{d:02}")),
+    );
+    date
+}
+
+/// `true` if `raw` is in [W3CDTF](https://www.w3.org/TR/NOTE-datetime) format, the profile of
+/// ISO 8601 that EPUB requires for `dc:date`: `YYYY`, `YYYY-MM`, `YYYY-MM-DD`, or a full date
+/// and a time to the minute, second or fraction of a second with a time zone (`Z` or `+hh:mm`).
+pub fn is_w3cdtf(raw: &str) -> bool {
+    let (date, time) = raw
+        .split_once('T')
+        .map_or((raw, None), |(d, t)| (d, Some(t)));
+    let parts: Vec<&str> = date.split('-').collect();
+    let month = |m: &str| fixed(m, 2).filter(|m| (1..=12).contains(m));
+    let valid_date = match parts.as_slice() {
+        [year] => fixed(year, 4).is_some(),
+        [year, m] => fixed(year, 4).is_some() && month(m).is_some(),
+        [year, m, day] => match (fixed(year, 4), month(m), fixed(day, 2)) {
+            (Some(year), Some(m), Some(day)) => {
+                (1..=u32::from(days_in_month(year as u16, m as u8))).contains(&day)
+            }
+            _ => false,
+        },
+        _ => false,
+    };
+    match time {
+        None => valid_date,
+        Some(time) => valid_date && parts.len() == 3 && is_w3c_time(time),
+    }
+}
+
+/// `true` if `time` is a W3CDTF time of day with a time zone, e.g. `10:45:30.5+01:00`.
+fn is_w3c_time(time: &str) -> bool {
+    let (clock, zone) = match time.strip_suffix('Z') {
+        Some(clock) => (clock, None),
+        None => match time.rfind(['+', '-']) {
+            Some(at) => (&time[..at], Some(&time[at + 1..])),
+            None => return false,
+        },
+    };
+    let hour = |h: &str| fixed(h, 2).is_some_and(|h| h < 24);
+    let minute = |m: &str| fixed(m, 2).is_some_and(|m| m < 60);
+    let valid_zone = zone.is_none_or(|zone| {
+        zone.split_once(':')
+            .is_some_and(|(h, m)| hour(h) && minute(m))
+    });
+    let (clock, fraction) = clock
+        .split_once('.')
+        .map_or((clock, None), |(c, f)| (c, Some(f)));
+    let valid_clock = match clock.split(':').collect::<Vec<_>>().as_slice() {
+        [h, m] => fraction.is_none() && hour(h) && minute(m),
+        [h, m, s] => {
+            hour(h)
+                && minute(m)
+                && minute(s)
+                && fraction.is_none_or(|f| !f.is_empty() && f.bytes().all(|b| b.is_ascii_digit()))
+        }
+        _ => false,
+    };
+    valid_zone && valid_clock
+}
+
+/// `s` as a number if it is exactly `len` ASCII digits.
+fn fixed(s: &str, len: usize) -> Option<u32> {
+    (s.len() == len && s.bytes().all(|b| b.is_ascii_digit()))
+        .then(|| s.parse().ok())
+        .flatten()
+}
+
+/// The current year, in UTC.
+pub fn this_year() -> u16 {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    year_of(secs)
+}
+
+/// The UTC year that `secs` seconds after the Unix epoch falls in.
+fn year_of(secs: u64) -> u16 {
+    let mut days = secs / 86_400;
+    let mut year = 1970;
+    loop {
+        let length = if days_in_month(year, 2) == 29 {
+            366
+        } else {
+            365
+        };
+        if days < length {
+            return year;
+        }
+        days -= length;
+        year += 1;
+    }
+}
+
+fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// `s` as a number if it is 1 to `max_len` ASCII digits.
+fn number<T: std::str::FromStr>(s: &str, max_len: usize) -> Option<T> {
+    (!s.is_empty() && s.len() <= max_len && s.bytes().all(|b| b.is_ascii_digit()))
+        .then(|| s.parse().ok())
+        .flatten()
+}
+
+/// The part of `raw` before any time of day.
+fn date_part(raw: &str) -> &str {
+    raw.split(['T', ' ']).next().unwrap_or(raw)
+}
+
+/// `YYYYMMDD`, optionally followed by a time as in PDF dates (`20110315104500+01'00'`).
+fn compact(raw: &str) -> Option<Date> {
+    let digits = raw.bytes().take_while(u8::is_ascii_digit).count();
+    if !matches!(digits, 8 | 10 | 12 | 14) {
+        return None;
+    }
+    Date::new(
+        number(&raw[..4], 4)?,
+        Some(number(&raw[4..6], 2)?),
+        Some(number(&raw[6..8], 2)?),
+    )
+}
+
+/// `YYYY`, `YYYY-MM` or `YYYY-MM-DD` (also with `/` or `.`), optionally followed by a time.
+fn year_first(raw: &str) -> Option<Date> {
+    let parts: Vec<&str> = date_part(raw).split(['-', '/', '.']).collect();
+    if parts[0].len() != 4 {
+        return None;
+    }
+    let year = number(parts[0], 4)?;
+    match parts[1..] {
+        [] => Date::new(year, None, None),
+        [month] => Date::new(year, Some(number(month, 2)?), None),
+        [month, day] => Date::new(year, Some(number(month, 2)?), Some(number(day, 2)?)),
+        _ => None,
+    }
+}
+
+/// `DD.MM.YYYY`, `DD-MM-YYYY`, `DD/MM/YYYY` or `MM/DD/YYYY`, and `MM/YYYY`.
+fn year_last(raw: &str) -> Option<Date> {
+    let part = date_part(raw);
+    let sep = part.chars().find(|c| matches!(c, '-' | '/' | '.'))?;
+    let parts: Vec<&str> = part.split(sep).collect();
+    let last = parts.last()?;
+    if last.len() != 4 {
+        return None;
+    }
+    let year = number(last, 4)?;
+    match parts[..] {
+        [month, _] => Date::new(year, Some(number(month, 2)?), None),
+        [a, b, _] => {
+            let (a, b): (u8, u8) = (number(a, 2)?, number(b, 2)?);
+            match sep {
+                '/' if a > 12 => Date::new(year, Some(b), Some(a)),
+                '/' if b > 12 => Date::new(year, Some(a), Some(b)),
+                '/' if a == b => Date::new(year, Some(a), Some(b)),
+                '/' => {
+                    log::debug!("Ambiguous date {raw}: only the year is used");
+                    Date::new(year, None, None)
+                }
+                _ => Date::new(year, Some(b), Some(a)),
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Dates with an English month name, e.g. `March 2011`, `15 Mar. 2011`,
+/// `March 15th, 2011` or `Tue, 15 Mar 2011 10:00:00 +0000`.
+fn with_month_name(raw: &str) -> Option<Date> {
+    let words: Vec<String> = raw
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+    let month = words.iter().find_map(|w| {
+        let position = (w.len() >= 3)
+            .then(|| MONTHS.iter().position(|m| m.starts_with(w.as_str())))
+            .flatten()?;
+        u8::try_from(position + 1).ok()
+    })?;
+    let year = words
+        .iter()
+        .find_map(|w| number::<u16>(w, 4).filter(|_| w.len() == 4))?;
+    // The first small number is the day; later ones belong to a time of day.
+    let day = words.iter().find_map(|w| {
+        let digits = ["st", "nd", "rd", "th"]
+            .iter()
+            .find_map(|suffix| w.strip_suffix(suffix))
+            .unwrap_or(w);
+        number::<u8>(digits, 2)
+    });
+    Date::new(year, Some(month), day).or_else(|| Date::new(year, Some(month), None))
+}
+
+/// The first four-digit number in `raw` that is a plausible year, e.g. in `c1999`.
+fn bare_year(raw: &str) -> Option<Date> {
+    raw.split(|c: char| !c.is_ascii_digit())
+        .filter(|run| run.len() == 4)
+        .find_map(|run| Date::new(number(run, 4)?, None, None))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(raw: &str) -> Option<String> {
+        parse(raw).map(|d| d.to_string())
+    }
+
+    // ── formats ────────────────────────────────────────────────────────────────
+
+    #[test]
+    fn iso_8601_variants() {
+        assert_eq!(date("2020-01-01").as_deref(), Some("2020-01-01"));
+        assert_eq!(
+            date("2011-03-15T04:00:00+00:00").as_deref(),
+            Some("2011-03-15")
+        );
+        assert_eq!(date("2011-3-5 10:00").as_deref(), Some("2011-03-05"));
+        assert_eq!(date("2011-03").as_deref(), Some("2011-03"));
+        assert_eq!(date(" 2024 ").as_deref(), Some("2024"));
+        assert_eq!(date("2011/03/15").as_deref(), Some("2011-03-15"));
+        assert_eq!(date("20110315").as_deref(), Some("2011-03-15"));
+    }
+
+    #[test]
+    fn pdf_dates() {
+        assert_eq!(
+            date("D:20110315104500+01'00'").as_deref(),
+            Some("2011-03-15")
+        );
+        assert_eq!(date("D:20110315104500Z").as_deref(), Some("2011-03-15"));
+        assert_eq!(date("D:2011").as_deref(), Some("2011"));
+    }
+
+    #[test]
+    fn locale_formats() {
+        assert_eq!(date("15/03/2011").as_deref(), Some("2011-03-15"));
+        assert_eq!(date("03/15/2011").as_deref(), Some("2011-03-15"));
+        assert_eq!(date("03/04/2011").as_deref(), Some("2011"), "ambiguous");
+        assert_eq!(date("15.03.2011").as_deref(), Some("2011-03-15"));
+        assert_eq!(date("05-03-2011").as_deref(), Some("2011-03-05"));
+        assert_eq!(date("03/2011").as_deref(), Some("2011-03"));
+    }
+
+    #[test]
+    fn month_names() {
+        assert_eq!(date("March 2011").as_deref(), Some("2011-03"));
+        assert_eq!(date("15 Mar. 2011").as_deref(), Some("2011-03-15"));
+        assert_eq!(date("March 15th, 2011").as_deref(), Some("2011-03-15"));
+        assert_eq!(
+            date("Tue, 15 Mar 2011 10:00:00 +0000").as_deref(),
+            Some("2011-03-15")
+        );
+        assert_eq!(date("Sept 2001").as_deref(), Some("2001-09"));
+    }
+
+    #[test]
+    fn free_text_with_a_year() {
+        assert_eq!(date("c1999").as_deref(), Some("1999"));
+        assert_eq!(date("First published 1954").as_deref(), Some("1954"));
+    }
+
+    // ── rejection ──────────────────────────────────────────────────────────────
+
+    #[test]
+    fn implausible_and_impossible_dates_are_rejected() {
+        assert_eq!(date("0101-01-01"), None);
+        assert_eq!(date("9999-12-31"), None);
+        assert_eq!(date("2012-02-29").as_deref(), Some("2012-02-29"));
+        // An impossible month or day still leaves the year.
+        assert_eq!(date("2011-02-30").as_deref(), Some("2011"));
+        assert_eq!(date("2011-13-01").as_deref(), Some("2011"));
+        assert_eq!(date("1900-02-29").as_deref(), Some("1900"));
+        assert_eq!(date(""), None);
+        assert_eq!(date("unknown"), None);
+    }
+
+    // ── tags ───────────────────────────────────────────────────────────────────
+
+    #[test]
+    fn tags_are_derived_from_the_date() {
+        let mut tags = Tags::from([("Date".to_string(), Some("March 15, 2011".to_string()))]);
+        assert_eq!(
+            add_tags(&mut tags).map(|d| d.precision()),
+            Some(Precision::Day)
+        );
+        assert_eq!(tags["Date"].as_deref(), Some("2011-03-15"));
+        assert_eq!(tags["Year"].as_deref(), Some("2011"));
+        assert_eq!(tags["Month"].as_deref(), Some("03"));
+        assert_eq!(tags["Day"].as_deref(), Some("15"));
+    }
+
+    #[test]
+    fn unreadable_dates_are_kept_but_yield_no_year() {
+        let mut tags = Tags::from([("Date".to_string(), Some("0101-01-01".to_string()))]);
+        assert_eq!(add_tags(&mut tags), None);
+        assert_eq!(tags["Date"].as_deref(), Some("0101-01-01"));
+        assert_eq!(tags["Year"], None);
+        assert_eq!(tags["Month"], None);
+    }
+
+    #[test]
+    fn year_is_used_when_there_is_no_date() {
+        let mut tags = Tags::from([("Year".to_string(), Some("1999".to_string()))]);
+        add_tags(&mut tags);
+        assert_eq!(tags["Year"].as_deref(), Some("1999"));
+        assert_eq!(tags["Date"].as_deref(), Some("1999"));
+    }
+
+    #[test]
+    fn year_of_counts_leap_years() {
+        assert_eq!(year_of(0), 1970);
+        assert_eq!(year_of(951_782_399), 2000, "2000-02-28T23:59:59Z");
+        assert_eq!(year_of(978_307_199), 2000, "2000-12-31T23:59:59Z");
+        assert_eq!(year_of(978_307_200), 2001, "2001-01-01T00:00:00Z");
+    }
+
+    // ── W3CDTF ─────────────────────────────────────────────────────────────────
+
+    #[test]
+    fn w3cdtf_accepts_each_precision() {
+        for raw in [
+            "2011",
+            "2011-03",
+            "2011-03-15",
+            "2012-02-29",
+            "2011-03-15T10:45Z",
+            "2011-03-15T10:45:30+01:00",
+            "2011-03-15T10:45:30.25-05:30",
+        ] {
+            assert!(is_w3cdtf(raw), "{raw} should be W3CDTF");
+        }
+    }
+
+    #[test]
+    fn w3cdtf_rejects_other_forms() {
+        for raw in [
+            "",
+            "11",
+            "2011-3",
+            "2011-13",
+            "2011-02-29",
+            "2011-03-15T10:45",
+            "2011-03-15 10:45Z",
+            "2011-03T10:45Z",
+            "2011-03-15T25:00Z",
+            "2011-03-15T10:45.5Z",
+            "2011-03-15T10:45:30+0100",
+            "15/03/2011",
+            "March 2011",
+        ] {
+            assert!(!is_w3cdtf(raw), "{raw} should not be W3CDTF");
+        }
+    }
+}
@@ -0,0 +1,598 @@
+//! Find files in a library that are probably the same book.
+//!
+//! Files are grouped in up to three ways, each a [`Match`]: identical contents, the same
+//! identifier (ISBN-10 and ISBN-13 forms of one ISBN count as the same), and similar title
+//! and author. A group found by a later match that only repeats files already grouped
+//! together by an earlier one is left out.
+use crate::{
+    extract::{Format, Tags},
+    identifier,
+    utils::{self, OutputFormat},
+};
+use clap::{ValueEnum, builder::PossibleValue};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+/// A way in which two files can be the same book.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Match {
+    /// Byte-for-byte identical files.
+    Hash,
+    /// The same identifier, e.g. ISBN.
+    Identifier,
+    /// Similar title and author.
+    Title,
+}
+
+impl Match {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Hash => "hash",
+            Self::Identifier => "identifier",
+            Self::Title => "title",
+        }
+    }
+
+    fn heading(self) -> &'static str {
+        match self {
+            Self::Hash => "Same content",
+            Self::Identifier => "Same identifier",
+            Self::Title => "Similar title and author",
+        }
+    }
+}
+
+impl ValueEnum for Match {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Self::Hash, Self::Identifier, Self::Title]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(match self {
+            Self::Hash => PossibleValue::new("hash").help("Identical contents"),
+            Self::Identifier => {
+                PossibleValue::new("identifier").help("The same ISBN or identifier")
+            }
+            Self::Title => PossibleValue::new("title").help("Similar title and author"),
+        })
+    }
+}
+
+/// What is known about one file in the library.
+#[derive(Debug, Clone)]
+pub struct Book {
+    pub path: PathBuf,
+    pub format: Option<Format>,
+    pub size: u64,
+    pub year: Option<String>,
+    pub identifier: Option<String>,
+    pub title: Option<String>,
+    pub author: Option<String>,
+}
+
+impl Book {
+    /// Describe the file at `path`, whose metadata is `tags`.
+    pub fn new(path: PathBuf, size: u64, tags: &Tags) -> Self {
+        let tag = |key: &str| {
+            tags.get(key)
+                .cloned()
+                .flatten()
+                .filter(|v| !v.trim().is_empty())
+        };
+        Self {
+            format: Format::of(&path),
+            path,
+            size,
+            year: tag("Year"),
+            identifier: tag("Identifier"),
+            title: tag("Title"),
+            author: tag("Author"),
+        }
+    }
+}
+
+/// A set of files that match each other.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Group {
+    /// How the files match.
+    pub by: Match,
+    /// What they have in common: the content hash, the identifier, or the normalised
+    /// title and author.
+    pub key: String,
+    /// Indexes into the list of books, in path order.
+    pub books: Vec<usize>,
+    /// The book suggested for keeping, if formats were given to prefer.
+    pub keep: Option<usize>,
+}
+
+/// Group `books` by each of the matches in `by`, in that order.
+///
+/// `similarity` is the lowest similarity (0 to 1) at which titles and authors count as
+/// the same. `prefer` lists formats from most to least wanted; if it is not empty, each
+/// group gets a [`Group::keep`] suggestion.
+pub fn find(books: &[Book], by: &[Match], similarity: f64, prefer: &[Format]) -> Vec<Group> {
+    let mut groups: Vec<Group> = Vec::new();
+    let mut by = by.to_vec();
+    by.sort();
+    by.dedup();
+    for matcher in by {
+        let found = match matcher {
+            Match::Hash => by_hash(books),
+            Match::Identifier => by_key(0..books.len(), |i| {
+                books[i]
+                    .identifier
+                    .as_deref()
+                    .and_then(identifier::parse)
+                    .map(|id| id.to_string())
+            }),
+            Match::Title => by_title(books, similarity),
+        };
+        for (key, mut members) in found {
+            members.sort_by(|&a, &b| books[a].path.cmp(&books[b].path));
+            let repeat = groups
+                .iter()
+                .any(|g| members.iter().all(|m| g.books.contains(m)));
+            if !repeat {
+                groups.push(Group {
+                    by: matcher,
+                    key,
+                    books: members,
+                    keep: None,
+                });
+            }
+        }
+    }
+
+    if !prefer.is_empty() {
+        for group in &mut groups {
+            group.keep = group
+                .books
+                .iter()
+                .copied()
+                .min_by(|&a, &b| keep_order(&books[a], &books[b], prefer));
+        }
+    }
+    groups
+}
+
+/// Order in which books are suggested for keeping: preferred format first, then the most
+/// recent edition, then the largest file.
+fn keep_order(a: &Book, b: &Book, prefer: &[Format]) -> std::cmp::Ordering {
+    let rank = |book: &Book| {
+        book.format
+            .and_then(|f| prefer.iter().position(|&p| p == f))
+            .unwrap_or(prefer.len())
+    };
+    rank(a)
+        .cmp(&rank(b))
+        .then_with(|| b.year.cmp(&a.year))
+        .then_with(|| b.size.cmp(&a.size))
+        .then_with(|| a.path.cmp(&b.path))
+}
+
+/// Groups of byte-identical books. Only books of the same size are hashed.
+fn by_hash(books: &[Book]) -> Vec<(String, Vec<usize>)> {
+    let mut by_size: HashMap<u64, Vec<usize>> = HashMap::new();
+    for (i, book) in books.iter().enumerate() {
+        by_size.entry(book.size).or_default().push(i);
+    }
+    let mut sizes: Vec<_> = by_size.into_values().filter(|v| v.len() > 1).collect();
+    sizes.sort();
+
+    sizes
+        .into_iter()
+        .flat_map(|candidates| {
+            by_key(candidates, |i| match utils::hash_file(&books[i].path) {
+                Ok(hash) => Some(hash),
+                Err(err) => {
+                    log::warn!("Unable to read {}: {err}", books[i].path.display());
+                    None
+                }
+            })
+        })
+        .collect()
+}
+
+/// Groups of two or more of the books at `indexes` that share the same `key`. Books
+/// without a key are left out.
+fn by_key<I, F>(indexes: I, key: F) -> Vec<(String, Vec<usize>)>
+where
+    I: IntoIterator<Item = usize>,
+    F: Fn(usize) -> Option<String>,
+{
+    let mut groups: HashMap<String, Vec<usize>> = HashMap::new();
+    for i in indexes {
+        if let Some(key) = key(i) {
+            groups.entry(key).or_default().push(i);
+        }
+    }
+    let mut groups: Vec<_> = groups.into_iter().filter(|(_, v)| v.len() > 1).collect();
+    groups.sort_by(|a, b| a.1.cmp(&b.1));
+    groups
+}
+
+/// Groups of books whose normalised titles and authors are both at least `similarity`
+/// alike. Books without both a title and an author are left out, as a title alone (e.g.
+/// "Poems") says too little.
+///
+/// Comparing every pair would take too long on a large library, so books are only
+/// compared with those sharing the first word of their title or the longest part of their
+/// author's name. A pair that differs in both is too far apart to be alike anyway.
+fn by_title(books: &[Book], similarity: f64) -> Vec<(String, Vec<usize>)> {
+    let keys: Vec<(usize, String, String)> = books
+        .iter()
+        .enumerate()
+        .filter_map(|(i, b)| {
+            let title = normalise_title(b.title.as_deref()?);
+            let author = normalise_author(b.author.as_deref()?);
+            (!title.is_empty() && !author.is_empty()).then_some((i, title, author))
+        })
+        .collect();
+
+    // Union-find, so similarity chains (A~B, B~C) end up in one group.
+    let mut parent: Vec<usize> = (0..keys.len()).collect();
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+    // Keyed by whether the word is from the title, and the word.
+    let mut blocks: HashMap<(bool, &str), Vec<usize>> = HashMap::new();
+    for (k, (_, title, author)) in keys.iter().enumerate() {
+        if let Some(word) = title.split(' ').next() {
+            blocks.entry((true, word)).or_default().push(k);
+        }
+        if let Some(part) = author.split(' ').max_by_key(|part| part.chars().count()) {
+            blocks.entry((false, part)).or_default().push(k);
+        }
+    }
+    let alike = |x: &str, y: &str| x == y || strsim::normalized_levenshtein(x, y) >= similarity;
+    for members in blocks.values() {
+        for (i, &a) in members.iter().enumerate() {
+            for &b in &members[i + 1..] {
+                let (ra, rb) = (root(&mut parent, a), root(&mut parent, b));
+                if ra != rb && alike(&keys[a].1, &keys[b].1) && alike(&keys[a].2, &keys[b].2) {
+                    parent[rb.max(ra)] = ra.min(rb);
+                }
+            }
+        }
+    }
+
+    let mut groups: HashMap<usize, Vec<usize>> = HashMap::new();
+    for k in 0..keys.len() {
+        let r = root(&mut parent, k);
+        groups.entry(r).or_default().push(k);
+    }
+    let mut groups: Vec<_> = groups
+        .into_iter()
+        .filter(|(_, v)| v.len() > 1)
+        .map(|(r, v)| {
+            let key = format!("{} / {}", keys[r].1, keys[r].2);
+            (key, v.into_iter().map(|k| keys[k].0).collect())
+        })
+        .collect();
+    groups.sort_by(|a: &(String, Vec<usize>), b| a.1.cmp(&b.1));
+    groups
+}
+
+/// Lowercase words of `text`, with punctuation dropped.
+fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+}
+
+/// A title reduced to what editions share: subtitles, bracketed notes such as
+/// "(Penguin Classics)", punctuation, case and a leading article are dropped.
+fn normalise_title(title: &str) -> String {
+    let main = title.split([':', '(', '[']).next().unwrap_or(title);
+    let main = main.split(" - ").next().unwrap_or(main);
+    let mut words: Vec<String> = words(main).collect();
+    if words.len() > 1 && matches!(words[0].as_str(), "the" | "a" | "an") {
+        words.remove(0);
+    }
+    words.join(" ")
+}
+
+/// An author reduced to their sorted lowercase name parts, so "Tolkien, J.R.R." and
+/// "J. R. R. Tolkien" are the same.
+fn normalise_author(author: &str) -> String {
+    let mut words: Vec<String> = words(author).collect();
+    words.sort();
+    words.join(" ")
+}
+
+/// Write `groups` of `books` to `out` in the given format.
+///
+/// # Errors
+///
+/// Returns `Err` if writing fails.
+pub fn write<W: Write>(
+    groups: &[Group],
+    books: &[Book],
+    format: OutputFormat,
+    mut out: W,
+) -> io::Result<()> {
+    match format {
+        OutputFormat::Json => {
+            let report: Vec<JsonGroup> = groups
+                .iter()
+                .map(|g| JsonGroup {
+                    by: g.by.name(),
+                    key: &g.key,
+                    files: g
+                        .books
+                        .iter()
+                        .map(|&i| JsonFile {
+                            path: &books[i].path,
+                            format: books[i].format.map(Format::extension),
+                            size: books[i].size,
+                            year: books[i].year.as_deref(),
+                            keep: g.keep.map(|k| k == i),
+                        })
+                        .collect(),
+                })
+                .collect();
+            serde_json::to_writer_pretty(&mut out, &report)?;
+            writeln!(out)
+        }
+        OutputFormat::Text => {
+            for (n, group) in groups.iter().enumerate() {
+                if n > 0 {
+                    writeln!(out)?;
+                }
+                writeln!(out, "{} ({}):", group.by.heading(), group.key)?;
+                for &i in &group.books {
+                    let book = &books[i];
+                    let advice = match group.keep {
+                        Some(k) if k == i => "keep    ",
+                        Some(_) => "remove  ",
+                        None => "",
+                    };
+                    writeln!(
+                        out,
+                        "  {advice}{:<5} {:>10}  {:<4}  {}",
+                        book.format.map_or("?", Format::name),
+                        utils::human_size(book.size),
+                        book.year.as_deref().unwrap_or("-"),
+                        book.path.display()
+                    )?;
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+/// A group in the JSON report.
+#[derive(Serialize)]
+struct JsonGroup<'a> {
+    #[serde(rename = "match")]
+    by: &'static str,
+    key: &'a str,
+    files: Vec<JsonFile<'a>>,
+}
+
+/// A file in the JSON report.
+#[derive(Serialize)]
+struct JsonFile<'a> {
+    #[serde(serialize_with = "utils::stored_path::serialize")]
+    path: &'a Path,
+    format: Option<&'static str>,
+    size: u64,
+    year: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    keep: Option<bool>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn book(path: &str, size: u64, tags: &[(&str, &str)]) -> Book {
+        let tags: Tags = tags
+            .iter()
+            .map(|(k, v)| ((*k).to_string(), Some((*v).to_string())))
+            .collect();
+        Book::new(PathBuf::from(path), size, &tags)
+    }
+
+    // ── normalisation ──────────────────────────────────────────────────────────
+
+    #[test]
+    fn titles_and_authors_drop_edition_noise() {
+        assert_eq!(
+            normalise_title("The Hobbit: or There and Back Again"),
+            "hobbit"
+        );
+        assert_eq!(
+            normalise_title("Hobbit (Collins Modern Classics)"),
+            "hobbit"
+        );
+        assert_eq!(normalise_title("The"), "the");
+        assert_eq!(
+            normalise_author("Tolkien, J.R.R."),
+            normalise_author("J. R. R. Tolkien")
+        );
+    }
+
+    // ── grouping ───────────────────────────────────────────────────────────────
+
+    #[test]
+    fn identical_files_are_grouped_by_hash() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let mut books = Vec::new();
+        for (name, contents) in [("a.epub", "same"), ("b.epub", "same"), ("c.epub", "diff")] {
+            let path = dir.path().join(name);
+            std::fs::write(&path, contents).expect("write");
+            books.push(Book::new(path, 4, &Tags::new()));
+        }
+
+        let groups = find(&books, &[Match::Hash], 0.9, &[]);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].books, vec![0, 1]);
+        assert_eq!(
+            groups[0].key,
+            utils::hash_file(&books[0].path).expect("hash")
+        );
+    }
+
+    #[test]
+    fn editions_are_grouped_by_identifier_and_title() {
+        let books = vec![
+            book(
+                "hobbit.epub",
+                10,
+                &[("Identifier", "urn:isbn:9780306406157")],
+            ),
+            book("hobbit.mobi", 20, &[("Identifier", "0306406152")]),
+            book(
+                "hobbit.pdf",
+                30,
+                &[("Title", "The Hobbit"), ("Author", "Tolkien, J.R.R.")],
+            ),
+            book(
+                "hobbit-2.pdf",
+                40,
+                &[
+                    ("Title", "Hobbit: Illustrated"),
+                    ("Author", "J R R Tolkein"),
+                ],
+            ),
+            book("poems.pdf", 50, &[("Title", "Hobbit")]),
+        ];
+
+        let groups = find(&books, &[Match::Title, Match::Identifier], 0.8, &[]);
+        assert_eq!(groups.len(), 2, "{groups:?}");
+        assert_eq!(
+            (groups[0].by, groups[0].books.clone()),
+            (Match::Identifier, vec![0, 1])
+        );
+        assert_eq!(groups[0].key, "9780306406157");
+        assert_eq!(
+            (groups[1].by, groups[1].books.clone()),
+            (Match::Title, vec![3, 2])
+        );
+    }
+
+    #[test]
+    fn titles_are_compared_within_title_and_author_blocks() {
+        let books = vec![
+            book(
+                "dune.epub",
+                10,
+                &[("Title", "Dune"), ("Author", "Frank Herbert")],
+            ),
+            book(
+                "dune.pdf",
+                20,
+                &[("Title", "Dune"), ("Author", "Frank Herbret")],
+            ),
+            book(
+                "foundation.epub",
+                30,
+                &[
+                    ("Title", "Foundation and Empire"),
+                    ("Author", "Isaac Asimov"),
+                ],
+            ),
+            book(
+                "foundation.pdf",
+                40,
+                &[
+                    ("Title", "Fondation and Empire"),
+                    ("Author", "Isaac Asimov"),
+                ],
+            ),
+            book(
+                "emma.epub",
+                50,
+                &[("Title", "Emma"), ("Author", "Jane Austen")],
+            ),
+        ];
+
+        let groups = by_title(&books, 0.8);
+        assert_eq!(
+            groups.into_iter().map(|(_, g)| g).collect::<Vec<_>>(),
+            [vec![0, 1], vec![2, 3]]
+        );
+    }
+
+    #[test]
+    fn groups_already_found_are_not_repeated() {
+        let books = vec![
+            book(
+                "a.epub",
+                1,
+                &[
+                    ("Title", "Dune"),
+                    ("Author", "Frank Herbert"),
+                    ("Identifier", "x"),
+                ],
+            ),
+            book(
+                "b.epub",
+                1,
+                &[
+                    ("Title", "Dune"),
+                    ("Author", "Frank Herbert"),
+                    ("Identifier", "x"),
+                ],
+            ),
+        ];
+        let groups = find(&books, &[Match::Identifier, Match::Title], 0.9, &[]);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].by, Match::Identifier);
+    }
+
+    #[test]
+    fn keep_suggestion_follows_format_preference_then_year() {
+        let books = vec![
+            book("a.pdf", 9, &[("Identifier", "x"), ("Year", "2020")]),
+            book("b.epub", 1, &[("Identifier", "x"), ("Year", "2001")]),
+            book("c.epub", 1, &[("Identifier", "x"), ("Year", "2010")]),
+        ];
+        let groups = find(&books, &[Match::Identifier], 0.9, &[Format::Epub]);
+        assert_eq!(groups[0].keep, Some(2));
+        let groups = find(
+            &books,
+            &[Match::Identifier],
+            0.9,
+            &[Format::Pdf, Format::Epub],
+        );
+        assert_eq!(groups[0].keep, Some(0));
+        assert_eq!(find(&books, &[Match::Identifier], 0.9, &[])[0].keep, None);
+    }
+
+    // ── output ─────────────────────────────────────────────────────────────────
+
+    #[test]
+    fn reports_list_each_group_in_text_and_json() {
+        let books = vec![
+            book("a.epub", 1536, &[("Identifier", "x"), ("Year", "2001")]),
+            book("b.pdf", 10, &[("Identifier", "x")]),
+        ];
+        let groups = find(&books, &[Match::Identifier], 0.9, &[Format::Epub]);
+
+        let mut text = Vec::new();
+        write(&groups, &books, OutputFormat::Text, &mut text).expect("write");
+        assert_eq!(
+            String::from_utf8(text).expect("utf-8"),
+            "Same identifier (x):\n  keep    EPUB     1.5 KiB  2001  a.epub\n  remove  PDF         10 B  -     b.pdf\n"
+        );
+
+        let mut json = Vec::new();
+        write(&groups, &books, OutputFormat::Json, &mut json).expect("write");
+        let json: serde_json::Value = serde_json::from_slice(&json).expect("json");
+        assert_eq!(json[0]["match"], "identifier");
+        assert_eq!(json[0]["files"][1]["path"], "b.pdf");
+        assert_eq!(json[0]["files"][1]["keep"], false);
+        assert_eq!(json[0]["files"][1]["year"], serde_json::Value::Null);
+    }
+}
@@ -1,14 +1,28 @@
-use crate::utils;
+use crate::{
+    date,
+    extract::NO_DRM,
+    text::{self, Content},
+    toc::Entry,
+};
 use convert_case::{Case, Casing};
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Component, Path},
+};
 
-use epub::doc::EpubDoc;
+use epub::doc::{EpubDoc, NavPoint};
+use xml::{
+    ParserConfig,
+    attribute::OwnedAttribute,
+    reader::{self, XmlEvent},
+};
+use zip::{CompressionMethod, ZipArchive};
 
 /// Get the metadata of an EPUB file and return it as a `HashMap`.
 ///
 /// # Arguments
 ///
-/// * `filename` - A string slice that holds the path to the EPUB file.
+/// * `filename` - The path to the EPUB file.
 ///
 /// # Returns
 ///
@@ -20,9 +34,10 @@ use epub::doc::EpubDoc;
 /// * `Description` - The description of the EPUB file.
 /// * `Publisher` - The publisher of the EPUB file.
 /// * `Date` - The raw date string from the EPUB file.
-/// * `Language` - The language of the EPUB file.
+/// * `Language` - The raw language string from the EPUB file.
 /// * `Identifier` - The identifier of the EPUB file.
 /// * `Year` - The four-digit year extracted from `Date`.
+/// * `DRM` - The DRM scheme protecting the file, or `None` (see [`drm`]).
 ///
 /// Keys are title-cased (e.g. `"Title"`, `"Author"`). Values are `Option<String>`:
 /// `None` when the field is absent from the file, `Some(value)` otherwise.
@@ -30,9 +45,9 @@ use epub::doc::EpubDoc;
 /// # Example
 ///
 /// ```ignore
-/// use std::collections::HashMap;
+/// use std::{collections::HashMap, path::Path};
 /// use docmeta::epub::get_metadata;
-/// let metadata = get_metadata("tests/test.epub").unwrap();
+/// let metadata = get_metadata(Path::new("tests/test.epub")).unwrap();
 /// let mut expected_metadata: HashMap<String, Option<String>> = HashMap::new();
 /// expected_metadata.insert("Title".to_string(), Some("The Title".to_string()));
 /// expected_metadata.insert("Author".to_string(), Some("The Author".to_string()));
@@ -48,8 +63,8 @@ use epub::doc::EpubDoc;
 /// # Errors
 ///
 /// Returns `Err` if the EPUB file cannot be opened or parsed.
-pub fn get_metadata(filename: &str) -> anyhow::Result<HashMap<String, Option<String>>> {
-    let doc = EpubDoc::new(filename)?;
+pub fn get_metadata(filename: &Path) -> anyhow::Result<HashMap<String, Option<String>>> {
+    let mut doc = EpubDoc::new(filename)?;
     log::debug!("metadata = {:?}", doc.metadata);
 
     let mut metadata_map: HashMap<String, Option<String>> = HashMap::new();
@@ -79,26 +94,855 @@ pub fn get_metadata(filename: &str) -> anyhow::Result<HashMap<String, Option<Str
     let year = metadata_map
         .get("Date")
         .and_then(Option::as_deref)
-        .map(utils::get_year)
-        .filter(|y| !y.is_empty());
+        .and_then(date::parse)
+        .map(|d| d.year.to_string());
     metadata_map.insert("Year".to_string(), year);
+    metadata_map.insert(
+        "DRM".to_string(),
+        Some(drm(&mut doc).unwrap_or(NO_DRM).to_string()),
+    );
 
     // return the metadata
     log::debug!("metadata_map = {metadata_map:?}");
     Ok(metadata_map)
 }
 
+/// Encryption algorithms used to obfuscate embedded fonts, which are not DRM: the IDPF's
+/// and Adobe's.
+const FONT_OBFUSCATION: &[&str] = &[
+    "http://www.idpf.org/2008/embedding",
+    "http://ns.adobe.com/pdf/enc#RC",
+];
+
+/// The DRM scheme protecting `doc`, if any: Readium LCP if it has a
+/// `META-INF/license.lcpl`, Adobe ADEPT if it has a `META-INF/rights.xml`, or `Other` if
+/// its `META-INF/encryption.xml` encrypts anything but fonts.
+fn drm<R: std::io::Read + std::io::Seek>(doc: &mut EpubDoc<R>) -> Option<&'static str> {
+    if doc.get_resource_by_path("META-INF/license.lcpl").is_some() {
+        return Some("Readium LCP");
+    }
+    if doc.get_resource_by_path("META-INF/rights.xml").is_some() {
+        return Some("Adobe ADEPT");
+    }
+    let encryption = doc.get_resource_by_path("META-INF/encryption.xml")?;
+    encrypts_more_than_fonts(&encryption).then_some("Other")
+}
+
+/// `true` if an `encryption.xml` uses any algorithm other than [`FONT_OBFUSCATION`], or
+/// can't be parsed.
+fn encrypts_more_than_fonts(encryption: &[u8]) -> bool {
+    for event in ParserConfig::new().create_reader(encryption) {
+        match event {
+            Ok(XmlEvent::StartElement {
+                name, attributes, ..
+            }) if name.local_name == "EncryptionMethod" => {
+                let algorithm = attributes
+                    .iter()
+                    .find(|a| a.name.local_name == "Algorithm")
+                    .map(|a| a.value.as_str());
+                if !algorithm.is_some_and(|a| FONT_OBFUSCATION.contains(&a)) {
+                    return true;
+                }
+            }
+            Ok(_) => {}
+            Err(_) => return true,
+        }
+    }
+    false
+}
+
+/// Read the table of contents of an EPUB file: the `toc` nav of its navigation document
+/// for EPUB 3, otherwise its NCX.
+///
+/// Targets are paths in the archive, with any `#fragment`.
+///
+/// # Errors
+///
+/// Returns `Err` if the EPUB file cannot be opened or parsed, or its navigation document is
+/// not well-formed XML.
+pub fn toc(filename: &Path) -> anyhow::Result<Vec<Entry>> {
+    let mut doc = EpubDoc::new(filename)?;
+    if let Some(nav_id) = doc.get_nav_id() {
+        let nav_path = doc.resources.get(&nav_id).map(|item| item.path.clone());
+        if let (Some(nav_path), Some((xhtml, _))) = (nav_path, doc.get_resource(&nav_id)) {
+            let entries = nav_entries(&xhtml, &nav_path)?;
+            if !entries.is_empty() {
+                return Ok(entries);
+            }
+        }
+    }
+    let mut entries = Vec::new();
+    flatten(&doc.toc, 1, &mut entries);
+    Ok(entries)
+}
+
+/// Extract the text of an EPUB file from its spine documents, in reading order, and count
+/// the images in its manifest.
+///
+/// # Errors
+///
+/// Returns `Err` if the EPUB file cannot be opened or parsed.
+pub fn text(filename: &Path) -> anyhow::Result<Content> {
+    let mut doc = EpubDoc::new(filename)?;
+    let images = doc
+        .resources
+        .values()
+        .filter(|item| item.mime.starts_with("image/"))
+        .count();
+    let ids = doc
+        .spine
+        .iter()
+        .map(|item| item.idref.clone())
+        .collect::<Vec<_>>();
+    let mut content = String::new();
+    for id in ids {
+        if let Some((xhtml, _)) = doc.get_resource_str(&id) {
+            content.push_str(&text::html_to_text(&xhtml));
+            content.push('\n');
+        }
+    }
+    Ok(Content {
+        text: content,
+        images,
+    })
+}
+
+/// Append `points` and their children, depth first, to `entries`.
+fn flatten(points: &[NavPoint], depth: usize, entries: &mut Vec<Entry>) {
+    for point in points {
+        entries.push(Entry {
+            depth,
+            label: collapse_whitespace(&point.label),
+            target: Some(point.content.to_string_lossy().replace('\\', "/")),
+        });
+        flatten(&point.children, depth + 1, entries);
+    }
+}
+
+/// The entries of the `<nav epub:type="toc">` in the navigation document `xhtml`, which is
+/// at `nav_path` in the archive. Each link or heading (`<a>` or `<span>`) is an entry,
+/// as deep as the lists it is in.
+fn nav_entries(xhtml: &[u8], nav_path: &Path) -> Result<Vec<Entry>, reader::Error> {
+    let reader = ParserConfig::new()
+        .add_entity("nbsp", "\u{a0}")
+        .create_reader(xhtml);
+    let mut entries = Vec::new();
+    let mut in_toc = false;
+    let mut lists = 0;
+    // The label being read, its link, and how many elements are open inside it.
+    let mut label: Option<(String, Option<String>, usize)> = None;
+    for event in reader {
+        match event? {
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } => match (&mut label, name.local_name.as_str()) {
+                (Some((_, _, open)), _) => *open += 1,
+                (None, "nav") if is_toc(&attributes) => in_toc = true,
+                (None, "ol") if in_toc => lists += 1,
+                (None, "a" | "span") if in_toc => {
+                    let href = attributes
+                        .iter()
+                        .find(|a| a.name.local_name == "href")
+                        .map(|a| resolve(nav_path, &a.value));
+                    label = Some((String::new(), href, 0));
+                }
+                _ => {}
+            },
+            XmlEvent::Characters(text) | XmlEvent::CData(text) | XmlEvent::Whitespace(text) => {
+                if let Some((label, _, _)) = &mut label {
+                    label.push_str(&text);
+                }
+            }
+            XmlEvent::EndElement { name } => match (&mut label, name.local_name.as_str()) {
+                (Some((_, _, open)), _) if *open > 0 => *open -= 1,
+                (Some(_), _) => {
+                    let (text, target, _) = label.take().unwrap_or_default();
+                    entries.push(Entry {
+                        depth: lists.max(1),
+                        label: collapse_whitespace(&text),
+                        target,
+                    });
+                }
+                (None, "ol") if in_toc => lists -= 1,
+                (None, "nav") if in_toc => break,
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+    Ok(entries)
+}
+
+/// `true` if a `<nav>` with `attributes` is the table of contents.
+fn is_toc(attributes: &[OwnedAttribute]) -> bool {
+    attributes.iter().any(|a| {
+        a.name.local_name == "type" && a.value.split_ascii_whitespace().any(|t| t == "toc")
+    })
+}
+
+/// The archive path of `href`, a link in the document at `base`.
+fn resolve(base: &Path, href: &str) -> String {
+    if href.contains("://") {
+        return href.to_string();
+    }
+    let (path, fragment) = href
+        .split_once('#')
+        .map_or((href, None), |(p, f)| (p, Some(f)));
+    let mut parts = base
+        .parent()
+        .into_iter()
+        .flat_map(Path::components)
+        .filter_map(|c| match c {
+            Component::Normal(part) => Some(part.to_string_lossy().into_owned()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    if path.is_empty() {
+        parts.extend(base.file_name().map(|n| n.to_string_lossy().into_owned()));
+    }
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part.to_string()),
+        }
+    }
+    let mut resolved = parts.join("/");
+    if let Some(fragment) = fragment {
+        resolved.push('#');
+        resolved.push_str(fragment);
+    }
+    resolved
+}
+
+/// Where the container document, which says where the package document is, must be.
+pub const CONTAINER_PATH: &str = "META-INF/container.xml";
+
+/// The name and content the first file in an EPUB must have.
+const MIMETYPE: (&str, &[u8]) = ("mimetype", b"application/epub+zip");
+
+/// The namespace of Dublin Core metadata elements such as `dc:date`.
+pub const DC_NAMESPACE: &str = "http://purl.org/dc/elements/1.1/";
+
+/// The archive path of the first package document (OPF) listed in a `container.xml`.
+///
+/// # Errors
+///
+/// Returns `Err` if `container` is not well-formed XML.
+pub fn rootfile(container: &[u8]) -> Result<Option<String>, reader::Error> {
+    for event in ParserConfig::new().create_reader(container) {
+        if let XmlEvent::StartElement {
+            name, attributes, ..
+        } = event?
+        {
+            if name.local_name == "rootfile" {
+                let path = attributes
+                    .into_iter()
+                    .find(|a| a.name.local_name == "full-path")
+                    .map(|a| a.value);
+                return Ok(path);
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// An item in the manifest of a package document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestItem {
+    pub id: String,
+    pub href: String,
+    pub media_type: String,
+    /// Space-separated, e.g. `nav` or `cover-image`.
+    pub properties: String,
+}
+
+impl ManifestItem {
+    /// The archive path of the item in the package document at `opf_path`, or `None` if it
+    /// is a remote resource.
+    pub fn path(&self, opf_path: &str) -> Option<String> {
+        if self.href.contains("://") {
+            return None;
+        }
+        let href = percent_decode(&self.href);
+        let path = resolve(Path::new(opf_path), &href);
+        Some(path.split('#').next().unwrap_or_default().to_string())
+    }
+}
+
+/// The parts of a package document (OPF) that are checked for problems.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Package {
+    /// The `version` attribute, e.g. `3.0`.
+    pub version: String,
+    /// The `unique-identifier` attribute: the `id` of the `dc:identifier` that identifies
+    /// the book.
+    pub unique_identifier: Option<String>,
+    /// The `id` of each `dc:identifier`.
+    pub identifier_ids: Vec<String>,
+    /// The text of each `dc:date`.
+    pub dates: Vec<String>,
+    pub manifest: Vec<ManifestItem>,
+    /// The `idref` of each spine `itemref`.
+    pub spine: Vec<String>,
+}
+
+impl Package {
+    /// Parse the package document `opf`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err` if `opf` is not well-formed XML.
+    pub fn parse(opf: &[u8]) -> Result<Self, reader::Error> {
+        let mut package = Self::default();
+        // The text of the `dc:date` being read.
+        let mut date: Option<String> = None;
+        for event in ParserConfig::new().create_reader(opf) {
+            match event? {
+                XmlEvent::StartElement {
+                    name, attributes, ..
+                } => {
+                    let attribute = |key: &str| {
+                        attributes
+                            .iter()
+                            .find(|a| a.name.local_name == key && a.name.prefix.is_none())
+                            .map(|a| a.value.clone())
+                    };
+                    let is_dc = name.namespace.as_deref() == Some(DC_NAMESPACE);
+                    match name.local_name.as_str() {
+                        "package" => {
+                            package.version = attribute("version").unwrap_or_default();
+                            package.unique_identifier = attribute("unique-identifier");
+                        }
+                        "identifier" if is_dc => {
+                            package.identifier_ids.extend(attribute("id"));
+                        }
+                        "date" if is_dc => date = Some(String::new()),
+                        "item" => package.manifest.push(ManifestItem {
+                            id: attribute("id").unwrap_or_default(),
+                            href: attribute("href").unwrap_or_default(),
+                            media_type: attribute("media-type").unwrap_or_default(),
+                            properties: attribute("properties").unwrap_or_default(),
+                        }),
+                        "itemref" => package.spine.extend(attribute("idref")),
+                        _ => {}
+                    }
+                }
+                XmlEvent::Characters(text) | XmlEvent::CData(text) => {
+                    if let Some(date) = &mut date {
+                        date.push_str(&text);
+                    }
+                }
+                XmlEvent::EndElement { .. } => {
+                    if let Some(date) = date.take() {
+                        package.dates.push(date.trim().to_string());
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(package)
+    }
+
+    /// `true` for EPUB 3 packages.
+    pub fn is_epub3(&self) -> bool {
+        self.version.trim().starts_with('3')
+    }
+}
+
+/// A conformance check on an EPUB, beyond what is needed to read its metadata.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Check {
+    /// `mimetype` is not the first file, is compressed, or has the wrong content.
+    Mimetype,
+    /// `META-INF/container.xml` is missing, malformed or names no package document.
+    Container,
+    /// The package document the container names is not in the archive.
+    OpfMissing,
+    /// The package document is not well-formed XML.
+    OpfMalformed,
+    /// A manifest item is not in the archive.
+    ManifestItemMissing,
+    /// The package's `unique-identifier` is missing or names no `dc:identifier`.
+    UniqueIdentifier,
+    /// A spine `itemref` names no manifest item.
+    SpineItemMissing,
+    /// An EPUB 3 package has no navigation document.
+    NavMissing,
+    /// A `dc:date` is not in W3CDTF format.
+    InvalidDate,
+}
+
+impl Check {
+    /// The check as it is shown, e.g. `spine-item-missing`.
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Mimetype => "epub-mimetype",
+            Self::Container => "epub-container",
+            Self::OpfMissing => "opf-missing",
+            Self::OpfMalformed => "opf-malformed",
+            Self::ManifestItemMissing => "manifest-item-missing",
+            Self::UniqueIdentifier => "unique-identifier",
+            Self::SpineItemMissing => "spine-item-missing",
+            Self::NavMissing => "nav-missing",
+            Self::InvalidDate => "invalid-date",
+        }
+    }
+}
+
+/// A problem found by a conformance [`Check`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub check: Check,
+    pub message: String,
+}
+
+impl Finding {
+    fn new(check: Check, message: impl Into<String>) -> Self {
+        Self {
+            check,
+            message: message.into(),
+        }
+    }
+}
+
+/// Check an EPUB's archive against the parts of the EPUB specification that readers rely
+/// on, much as a cut-down epubcheck would:
+///
+/// - `mimetype` is the first file, stored uncompressed, and says `application/epub+zip`;
+/// - `META-INF/container.xml` names a package document that is in the archive and parses;
+/// - every manifest item is in the archive, and every spine item is in the manifest;
+/// - the package's `unique-identifier` names one of its `dc:identifier`s;
+/// - an EPUB 3 package has a navigation document;
+/// - every `dc:date` is in [W3CDTF](https://www.w3.org/TR/NOTE-datetime) format.
+///
+/// Checks that depend on the package document are skipped if it can't be read.
+pub fn conformance<R: std::io::Read + std::io::Seek>(archive: &mut ZipArchive<R>) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    findings.extend(check_mimetype(archive));
+
+    let Some(container) = read_entry(archive, CONTAINER_PATH) else {
+        findings.push(Finding::new(
+            Check::Container,
+            format!("{CONTAINER_PATH} is missing"),
+        ));
+        return findings;
+    };
+    let opf_path = match rootfile(&container) {
+        Ok(Some(path)) => path,
+        Ok(None) => {
+            findings.push(Finding::new(
+                Check::Container,
+                format!("{CONTAINER_PATH} names no package document"),
+            ));
+            return findings;
+        }
+        Err(err) => {
+            findings.push(Finding::new(
+                Check::Container,
+                format!("{CONTAINER_PATH}: {err}"),
+            ));
+            return findings;
+        }
+    };
+    let Some(opf) = read_entry(archive, &opf_path) else {
+        findings.push(Finding::new(
+            Check::OpfMissing,
+            format!("package document {opf_path} is missing"),
+        ));
+        return findings;
+    };
+    let package = match Package::parse(&opf) {
+        Ok(package) => package,
+        Err(err) => {
+            findings.push(Finding::new(
+                Check::OpfMalformed,
+                format!("{opf_path}: {err}"),
+            ));
+            return findings;
+        }
+    };
+
+    let names = archive.file_names().collect::<HashSet<_>>();
+    for item in &package.manifest {
+        if let Some(path) = item.path(&opf_path) {
+            if !names.contains(path.as_str()) {
+                findings.push(Finding::new(
+                    Check::ManifestItemMissing,
+                    format!("manifest item {} ({path}) is missing", item.id),
+                ));
+            }
+        }
+    }
+    let ids = package
+        .manifest
+        .iter()
+        .map(|item| item.id.as_str())
+        .collect::<HashSet<_>>();
+    for idref in &package.spine {
+        if !ids.contains(idref.as_str()) {
+            findings.push(Finding::new(
+                Check::SpineItemMissing,
+                format!("spine item {idref} is not in the manifest"),
+            ));
+        }
+    }
+    match &package.unique_identifier {
+        None => findings.push(Finding::new(
+            Check::UniqueIdentifier,
+            "the package has no unique-identifier",
+        )),
+        Some(id) if !package.identifier_ids.contains(id) => findings.push(Finding::new(
+            Check::UniqueIdentifier,
+            format!("unique-identifier {id} names no dc:identifier"),
+        )),
+        Some(_) => {}
+    }
+    let has_nav = package
+        .manifest
+        .iter()
+        .any(|item| item.properties.split_ascii_whitespace().any(|p| p == "nav"));
+    if package.is_epub3() && !has_nav {
+        findings.push(Finding::new(
+            Check::NavMissing,
+            "the EPUB 3 package has no navigation document",
+        ));
+    }
+    for date in package.dates.iter().filter(|d| !date::is_w3cdtf(d)) {
+        findings.push(Finding::new(
+            Check::InvalidDate,
+            format!("dc:date {date:?} is not a W3CDTF date"),
+        ));
+    }
+    findings
+}
+
+/// Check that `mimetype` is the first file in the archive, stored uncompressed, with the
+/// right content.
+fn check_mimetype<R: std::io::Read + std::io::Seek>(
+    archive: &mut ZipArchive<R>,
+) -> Option<Finding> {
+    let (name, content) = MIMETYPE;
+    let mut first = match archive.by_index(0) {
+        Ok(first) if first.name() == name => first,
+        _ => {
+            return Some(Finding::new(
+                Check::Mimetype,
+                format!("{name} is not the first file in the archive"),
+            ));
+        }
+    };
+    if first.compression() != CompressionMethod::Stored {
+        return Some(Finding::new(
+            Check::Mimetype,
+            format!("{name} is compressed"),
+        ));
+    }
+    let mut data = Vec::new();
+    if std::io::Read::read_to_end(&mut first, &mut data).is_err() || data != content {
+        return Some(Finding::new(
+            Check::Mimetype,
+            format!("{name} does not say {}", String::from_utf8_lossy(content)),
+        ));
+    }
+    None
+}
+
+/// The data of the file called `name`, if it is in the archive and can be read.
+fn read_entry<R: std::io::Read + std::io::Seek>(
+    archive: &mut ZipArchive<R>,
+    name: &str,
+) -> Option<Vec<u8>> {
+    let mut entry = archive.by_name(name).ok()?;
+    let mut data = Vec::new();
+    std::io::Read::read_to_end(&mut entry, &mut data).ok()?;
+    Some(data)
+}
+
+/// `text` with `%XX` escapes decoded, as in a URL path. Invalid escapes are kept as they
+/// are.
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| text.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// `text` trimmed, with each run of whitespace replaced by a single space.
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::{fs::File, io::Write};
+    use zip::{ZipWriter, write::SimpleFileOptions};
 
     #[test]
     fn get_metadata_includes_year_key() {
-        let map = get_metadata("tests/fixtures/Mastering.epub").expect("should parse");
+        let map = get_metadata(Path::new("tests/fixtures/Mastering.epub")).expect("should parse");
         assert_eq!(
             map.get("Year").and_then(Option::as_deref),
             Some("2019"),
             "unexpected Year value"
         );
     }
+
+    // ── table of contents ────────────────────────────────────────────────────
+
+    #[test]
+    fn nav_entries_follow_list_nesting() {
+        let xhtml = br#"<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops"><body>
+            <nav epub:type="landmarks"><ol><li><a href="cover.xhtml">Cover</a></li></ol></nav>
+            <nav epub:type="toc"><ol>
+              <li><span>Part&nbsp;One</span><ol>
+                <li><a href="text/ch1.xhtml#start">Chapter <em>1</em></a></li>
+              </ol></li>
+              <li><a href="../notes.xhtml">
+                Notes
+              </a></li>
+            </ol></nav></body></html>"#;
+        let entries = nav_entries(xhtml, Path::new("OEBPS/nav.xhtml")).expect("parse");
+        let entry = |depth, label: &str, target: Option<&str>| Entry {
+            depth,
+            label: label.to_string(),
+            target: target.map(str::to_string),
+        };
+        assert_eq!(
+            entries,
+            vec![
+                entry(1, "Part One", None),
+                entry(2, "Chapter 1", Some("OEBPS/text/ch1.xhtml#start")),
+                entry(1, "Notes", Some("notes.xhtml")),
+            ]
+        );
+    }
+
+    #[test]
+    fn resolve_is_relative_to_the_linking_document() {
+        let base = Path::new("OEBPS/text/nav.xhtml");
+        assert_eq!(resolve(base, "ch1.xhtml"), "OEBPS/text/ch1.xhtml");
+        assert_eq!(resolve(base, "./ch1.xhtml#p2"), "OEBPS/text/ch1.xhtml#p2");
+        assert_eq!(
+            resolve(base, "../images/map.xhtml"),
+            "OEBPS/images/map.xhtml"
+        );
+        assert_eq!(resolve(base, "#intro"), "OEBPS/text/nav.xhtml#intro");
+        assert_eq!(
+            resolve(base, "https://example.com/"),
+            "https://example.com/"
+        );
+    }
+
+    // ── DRM ──────────────────────────────────────────────────────────────────
+
+    /// Write a minimal EPUB to `path`, with `extra` files added to the archive.
+    fn write_epub(path: &Path, extra: &[(&str, &[u8])]) {
+        let mut zip = ZipWriter::new(File::create(path).expect("create"));
+        let files: &[(&str, &[u8])] = &[
+            ("mimetype", b"application/epub+zip"),
+            (
+                "META-INF/container.xml",
+                br#"<?xml version="1.0"?><container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container"><rootfiles><rootfile full-path="content.opf" media-type="application/oebps-package+xml"/></rootfiles></container>"#,
+            ),
+            (
+                "content.opf",
+                br#"<?xml version="1.0"?><package xmlns="http://www.idpf.org/2007/opf" version="2.0"><metadata xmlns:dc="http://purl.org/dc/elements/1.1/"><dc:title>T</dc:title></metadata><manifest><item id="ch1" href="ch1.xhtml" media-type="application/xhtml+xml"/></manifest><spine><itemref idref="ch1"/></spine></package>"#,
+            ),
+            ("ch1.xhtml", b"<html/>"),
+        ];
+        for (name, data) in files.iter().chain(extra) {
+            zip.start_file(*name, SimpleFileOptions::default())
+                .expect("start file");
+            zip.write_all(data).expect("write");
+        }
+        zip.finish().expect("finish");
+    }
+
+    fn drm_of(extra: &[(&str, &[u8])]) -> Option<String> {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let path = dir.path().join("book.epub");
+        write_epub(&path, extra);
+        get_metadata(&path).expect("should parse")["DRM"].clone()
+    }
+
+    fn encryption_xml(algorithm: &str) -> Vec<u8> {
+        format!(
+            r#"<encryption xmlns="urn:oasis:names:tc:opendocument:xmlns:container" xmlns:enc="http://www.w3.org/2001/04/xmlenc#"><enc:EncryptedData><enc:EncryptionMethod Algorithm="{algorithm}"/><enc:CipherData><enc:CipherReference URI="ch1.xhtml"/></enc:CipherData></enc:EncryptedData></encryption>"#
+        )
+        .into_bytes()
+    }
+
+    #[test]
+    fn drm_schemes_are_recognised_by_their_files() {
+        assert_eq!(drm_of(&[]).as_deref(), Some("None"));
+        assert_eq!(
+            drm_of(&[("META-INF/rights.xml", b"<rights/>")]).as_deref(),
+            Some("Adobe ADEPT")
+        );
+        assert_eq!(
+            drm_of(&[("META-INF/license.lcpl", b"{}")]).as_deref(),
+            Some("Readium LCP")
+        );
+    }
+
+    #[test]
+    fn font_obfuscation_is_not_drm() {
+        let fonts = encryption_xml("http://www.idpf.org/2008/embedding");
+        assert!(!encrypts_more_than_fonts(&fonts));
+        assert_eq!(
+            drm_of(&[("META-INF/encryption.xml", &fonts)]).as_deref(),
+            Some("None")
+        );
+
+        let aes = encryption_xml("http://www.w3.org/2001/04/xmlenc#aes128-cbc");
+        assert!(encrypts_more_than_fonts(&aes));
+        assert_eq!(
+            drm_of(&[("META-INF/encryption.xml", &aes)]).as_deref(),
+            Some("Other")
+        );
+        assert!(encrypts_more_than_fonts(b"<encryption><unclosed"));
+    }
+
+    // ── conformance ──────────────────────────────────────────────────────────
+
+    /// An EPUB archive in memory, from `(name, data, stored)` entries.
+    fn archive(entries: &[(&str, &[u8], bool)]) -> ZipArchive<std::io::Cursor<Vec<u8>>> {
+        let mut zip = ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        for (name, data, stored) in entries {
+            let method = if *stored {
+                CompressionMethod::Stored
+            } else {
+                CompressionMethod::Deflated
+            };
+            zip.start_file(
+                *name,
+                SimpleFileOptions::default().compression_method(method),
+            )
+            .expect("start file");
+            zip.write_all(data).expect("write");
+        }
+        ZipArchive::new(zip.finish().expect("finish")).expect("archive")
+    }
+
+    /// The entries of an EPUB whose package has `version`, `package_attributes`,
+    /// `metadata`, `manifest` and `spine` elements.
+    fn conformance_of(
+        version: &str,
+        package_attributes: &str,
+        metadata: &str,
+        manifest: &str,
+        spine: &str,
+    ) -> Vec<Check> {
+        let opf = format!(
+            r#"<?xml version="1.0"?><package xmlns="http://www.idpf.org/2007/opf" version="{version}" {package_attributes}><metadata xmlns:dc="http://purl.org/dc/elements/1.1/"><dc:title>T</dc:title>{metadata}</metadata><manifest><item id="ch1" href="ch1.xhtml" media-type="application/xhtml+xml"/>{manifest}</manifest><spine><itemref idref="ch1"/>{spine}</spine></package>"#
+        );
+        let mut zip = archive(&[
+            ("mimetype", b"application/epub+zip", true),
+            (
+                CONTAINER_PATH,
+                br#"<?xml version="1.0"?><container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container"><rootfiles><rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/></rootfiles></container>"#,
+                false,
+            ),
+            ("OEBPS/content.opf", opf.as_bytes(), false),
+            ("OEBPS/ch1.xhtml", b"<html/>", false),
+            ("OEBPS/nav.xhtml", b"<html/>", false),
+        ]);
+        conformance(&mut zip).into_iter().map(|f| f.check).collect()
+    }
+
+    const IDENTIFIER: &str = r#"<dc:identifier id="uid">urn:isbn:9780306406157</dc:identifier>"#;
+    const NAV: &str =
+        r#"<item id="nav" href="nav.xhtml" media-type="application/xhtml+xml" properties="nav"/>"#;
+
+    #[test]
+    fn conformant_epub_has_no_findings() {
+        let metadata = format!("{IDENTIFIER}<dc:date>2011-03-15T10:45:30Z</dc:date>");
+        assert_eq!(
+            conformance_of("3.0", r#"unique-identifier="uid""#, &metadata, NAV, ""),
+            []
+        );
+        assert_eq!(
+            conformance_of("2.0", r#"unique-identifier="uid""#, IDENTIFIER, "", ""),
+            []
+        );
+    }
+
+    #[test]
+    fn mimetype_must_come_first_stored_and_exact() {
+        let rest = (CONTAINER_PATH, b"<container/>".as_slice(), false);
+        for entries in [
+            vec![rest, ("mimetype", b"application/epub+zip".as_slice(), true)],
+            vec![
+                ("mimetype", b"application/epub+zip".as_slice(), false),
+                rest,
+            ],
+            vec![("mimetype", b"application/zip".as_slice(), true), rest],
+        ] {
+            let findings = conformance(&mut archive(&entries));
+            assert_eq!(findings[0].check, Check::Mimetype, "{findings:?}");
+        }
+    }
+
+    #[test]
+    fn unique_identifier_must_name_a_dc_identifier() {
+        assert_eq!(
+            conformance_of("2.0", "", IDENTIFIER, "", ""),
+            [Check::UniqueIdentifier]
+        );
+        assert_eq!(
+            conformance_of("2.0", r#"unique-identifier="isbn""#, IDENTIFIER, "", ""),
+            [Check::UniqueIdentifier]
+        );
+    }
+
+    #[test]
+    fn spine_items_must_be_in_the_manifest() {
+        assert_eq!(
+            conformance_of(
+                "2.0",
+                r#"unique-identifier="uid""#,
+                IDENTIFIER,
+                "",
+                r#"<itemref idref="ch2"/>"#
+            ),
+            [Check::SpineItemMissing]
+        );
+    }
+
+    #[test]
+    fn epub3_needs_a_navigation_document() {
+        assert_eq!(
+            conformance_of("3.0", r#"unique-identifier="uid""#, IDENTIFIER, "", ""),
+            [Check::NavMissing]
+        );
+    }
+
+    #[test]
+    fn dates_must_be_w3cdtf() {
+        let metadata = format!("{IDENTIFIER}<dc:date>2011</dc:date><dc:date>15/03/2011</dc:date>");
+        assert_eq!(
+            conformance_of("2.0", r#"unique-identifier="uid""#, &metadata, "", ""),
+            [Check::InvalidDate]
+        );
+    }
 }
@@ -0,0 +1,256 @@
+//! Read metadata from any supported file, one at a time or a whole batch in parallel.
+use crate::{cache::Cache, calibre, date, epub, identifier, language, mobi, pdf, text};
+use anyhow::Context as _;
+use clap::{ValueEnum, builder::PossibleValue};
+use rayon::prelude::*;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+/// The `DRM` value of a file that no DRM scheme protects.
+pub const NO_DRM: &str = "None";
+
+/// Metadata of a single file, as returned by the format readers.
+pub type Tags = HashMap<String, Option<String>>;
+
+/// The file formats docmeta can read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Epub,
+    Mobi,
+    Pdf,
+}
+
+impl Format {
+    /// The format of `path`, judged by its extension (ignoring case).
+    pub fn of(path: &Path) -> Option<Self> {
+        let ext = path.extension()?;
+        [Self::Epub, Self::Mobi, Self::Pdf]
+            .into_iter()
+            .find(|f| ext.eq_ignore_ascii_case(f.extension()))
+    }
+
+    /// The lowercase file extension of the format.
+    pub const fn extension(self) -> &'static str {
+        match self {
+            Self::Epub => "epub",
+            Self::Mobi => "mobi",
+            Self::Pdf => "pdf",
+        }
+    }
+
+    /// The name of the format for messages, e.g. `EPUB`.
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Epub => "EPUB",
+            Self::Mobi => "MOBI",
+            Self::Pdf => "PDF",
+        }
+    }
+}
+
+impl ValueEnum for Format {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Self::Epub, Self::Mobi, Self::Pdf]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(PossibleValue::new(self.extension()))
+    }
+}
+
+/// Read the metadata of `path` with the reader for its [`Format`], without the derived
+/// tags (see [`derive`]) or the [text statistics](add_stats).
+///
+/// Files of an unknown format yield empty metadata.
+///
+/// # Errors
+///
+/// Returns `Err` if the reader cannot open or parse the file.
+pub fn metadata(path: &Path) -> anyhow::Result<Tags> {
+    let Some(format) = Format::of(path) else {
+        return Ok(Tags::new());
+    };
+    let tags = match format {
+        Format::Epub => epub::get_metadata(path),
+        Format::Mobi => mobi::get_metadata(path),
+        Format::Pdf => pdf::get_metadata(path).map_err(anyhow::Error::from),
+    };
+    tags.with_context(|| format!("failed to read {}: {}", format.name(), path.display()))
+}
+
+/// `true` if the [text statistics](text::Stats) of a file with metadata `tags` can be
+/// added but have not been yet. Files protected by DRM have none, as their text is
+/// encrypted.
+fn lacks_stats(tags: &Tags) -> bool {
+    let protected = tags
+        .get("DRM")
+        .and_then(Option::as_deref)
+        .is_some_and(|drm| drm != NO_DRM);
+    !protected && !tags.contains_key(text::KEYS[0])
+}
+
+/// Add the [text statistics](text::Stats) of `path` to its `tags`, unless they are there
+/// already or the file is protected by DRM.
+///
+/// This reads all of the book's text, so it is only done when the statistics are asked
+/// for. If the text can't be extracted they are left out and the problem is logged.
+pub fn add_stats(path: &Path, tags: &mut Tags) {
+    if !lacks_stats(tags) {
+        return;
+    }
+    match text::read(path) {
+        Ok(content) => text::add_tags(text::Stats::of(&content), tags),
+        Err(err) => log::warn!("{}: failed to extract text: {err}", path.display()),
+    }
+}
+
+/// Add the tags that are worked out from others, such as `Month` from `Date` and `ISBN13`
+/// from `Identifier`, to the `tags` of `path`. Problems found on the way, such as a bad
+/// ISBN check digit, are logged.
+///
+/// Kept apart from [`metadata`] so that cached metadata always gets the current derived
+/// tags.
+pub fn derive(path: &Path, tags: &mut Tags) {
+    date::add_tags(tags);
+    language::add_tags(tags);
+    if let Some(identifier::Identifier::InvalidIsbn(isbn)) = identifier::add_tags(tags) {
+        log::warn!("{}: ISBN {isbn} has a bad check digit", path.display());
+    }
+}
+
+/// Read the metadata of every file in `files` using up to `jobs` threads (`0` means one
+/// per CPU). The results are in the same order as `files`, whatever order the files
+/// finish in. Each result includes the [derived](derive) tags.
+///
+/// With `stats`, the [text statistics](add_stats) are added too. With a `cache`, files
+/// that have not changed since they were cached are not read again, and newly read files
+/// and statistics are added to it. The metadata in `sources` is merged over each file's
+/// own before the tags are derived, and is never cached.
+///
+/// # Errors
+///
+/// Returns `Err` if the thread pool cannot be started. Failures to read individual files
+/// are returned in their place in the list.
+pub fn all(
+    files: &[PathBuf],
+    jobs: usize,
+    cache: Option<&Cache>,
+    sources: &calibre::Sources,
+    stats: bool,
+) -> anyhow::Result<Vec<anyhow::Result<Tags>>> {
+    let read = |file: &PathBuf| {
+        let mut tags = match cache {
+            Some(cache) => cache.get_or_extract(file, metadata)?,
+            None => metadata(file)?,
+        };
+        if stats && lacks_stats(&tags) {
+            add_stats(file, &mut tags);
+            if let Some(cache) = cache {
+                cache.update(file, &tags);
+            }
+        }
+        sources.merge(file, &mut tags);
+        derive(file, &mut tags);
+        Ok(tags)
+    };
+    if jobs == 1 {
+        return Ok(files.iter().map(read).collect());
+    }
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(jobs)
+        .build()
+        .context("failed to start worker threads")?;
+    log::debug!(
+        "Extracting metadata on {} threads",
+        pool.current_num_threads()
+    );
+    Ok(pool.install(|| files.par_iter().map(read).collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_is_judged_by_extension() {
+        assert_eq!(Format::of(Path::new("a/b.EPUB")), Some(Format::Epub));
+        assert_eq!(Format::of(Path::new("b.mobi")), Some(Format::Mobi));
+        assert_eq!(Format::of(Path::new("b.pdf")), Some(Format::Pdf));
+        assert_eq!(Format::of(Path::new("b.txt")), None);
+        assert_eq!(Format::of(Path::new("pdf")), None);
+    }
+
+    #[test]
+    fn parallel_results_keep_input_order() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        // Unknown formats read as empty metadata; broken PDFs fail. Interleave both so a
+        // reordering would show.
+        let files: Vec<PathBuf> = (0..32)
+            .map(|i| {
+                let ext = if i % 3 == 0 { "pdf" } else { "txt" };
+                let path = dir.path().join(format!("{i:02}.{ext}"));
+                std::fs::write(&path, b"not a real file").expect("write");
+                path
+            })
+            .collect();
+
+        let results = all(&files, 4, None, &calibre::Sources::default(), false).expect("pool");
+        assert_eq!(results.len(), files.len());
+        for (i, result) in results.iter().enumerate() {
+            assert_eq!(result.is_err(), i % 3 == 0, "result {i} is out of place");
+        }
+    }
+
+    #[test]
+    fn read_errors_name_the_format_and_file() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let path = dir.path().join("broken.epub");
+        std::fs::write(&path, b"not a zip").expect("write");
+        let err = metadata(&path).expect_err("should fail");
+        assert!(
+            err.to_string().starts_with("failed to read EPUB:"),
+            "got: {err}"
+        );
+    }
+
+    #[test]
+    fn stats_are_only_read_when_asked_for_and_then_cached() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let file = dir.path().join("text.pdf");
+        std::fs::copy("tests/fixtures/text.pdf", &file).expect("copy fixture");
+        let cache = Cache::load(dir.path().join("cache.json"));
+        let sources = calibre::Sources::default();
+        let read = |stats| {
+            all(
+                std::slice::from_ref(&file),
+                1,
+                Some(&cache),
+                &sources,
+                stats,
+            )
+            .expect("pool")
+            .remove(0)
+            .expect("metadata")
+        };
+
+        assert!(!read(false).contains_key("Words"));
+        assert_eq!(read(true).get("Words"), Some(&Some("5".to_string())));
+        cache.save().expect("save");
+
+        // The statistics come from the cache now, even when not asked for.
+        let cache = Cache::load(dir.path().join("cache.json"));
+        let tags = all(
+            std::slice::from_ref(&file),
+            1,
+            Some(&cache),
+            &sources,
+            false,
+        )
+        .expect("pool")
+        .remove(0)
+        .expect("metadata");
+        assert_eq!(tags.get("Words"), Some(&Some("5".to_string())));
+    }
+}
@@ -0,0 +1,544 @@
+//! `--where` expressions: decide from a file's metadata whether it is processed.
+//!
+//! An expression combines comparisons with `and`, `or`, `not` and parentheses:
+//!
+//! ```text
+//! Format = epub and Publisher is missing
+//! Year < 2000 or not (Title ~ "^The ")
+//! ```
+//!
+//! | Comparison | Meaning |
+//! |------------|---------|
+//! | `Field = value`, `Field != value` | Equal / not equal, ignoring case |
+//! | `Field ~ regex`, `Field !~ regex` | The regular expression matches / does not match |
+//! | `Field < n`, `<=`, `>`, `>=` | Numeric comparison, e.g. on `Year` |
+//! | `Field is missing`, `Field is present` | The field is absent or empty / has a value |
+//!
+//! Field names are the metadata keys (see the README), matched ignoring case, plus the
+//! virtual fields `Format` (`epub`, `mobi` or `pdf`) and `Filename` (the file name without
+//! its directory). Values containing spaces or operator characters must be quoted with
+//! `"` or `'`; inside quotes, `\"`, `\'` and `\\` are the only escapes. A comparison on a
+//! missing field is false, except `!=` and `!~`.
+use regex::Regex;
+use std::{cmp::Ordering, collections::HashMap, path::Path};
+
+/// Errors that can occur when parsing a `--where` expression.
+#[derive(Debug, thiserror::Error)]
+pub enum FilterError {
+    /// The expression does not follow the grammar.
+    #[error("{message} at position {position}")]
+    Syntax { message: String, position: usize },
+    /// A `~` or `!~` operand is not a valid regular expression.
+    #[error("Invalid regular expression {pattern:?}: {source}")]
+    Regex {
+        pattern: String,
+        #[source]
+        source: regex::Error,
+    },
+}
+
+/// A parsed `--where` expression.
+#[derive(Debug, Clone)]
+pub struct Filter {
+    expr: Expr,
+}
+
+impl Filter {
+    /// Parse `input` into a filter.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`FilterError::Syntax`] if `input` is not a valid expression and
+    /// [`FilterError::Regex`] if a regular expression does not compile.
+    pub fn parse(input: &str) -> Result<Self, FilterError> {
+        let tokens = tokenize(input)?;
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            end: input.len(),
+        };
+        let expr = parser.or()?;
+        if let Some((token, position)) = parser.tokens.get(parser.pos) {
+            return Err(syntax(format!("Unexpected {token}"), *position));
+        }
+        Ok(Self { expr })
+    }
+
+    /// The fields the filter compares, as written.
+    pub fn fields(&self) -> Vec<&str> {
+        let mut fields = Vec::new();
+        self.expr.fields(&mut fields);
+        fields
+    }
+
+    /// `true` if the file at `path` with metadata `tags` satisfies the filter.
+    pub fn matches(&self, path: &Path, tags: &HashMap<String, Option<String>>) -> bool {
+        self.expr.eval(&|field| lookup(field, path, tags))
+    }
+}
+
+/// The value of `field` for the file at `path`, or `None` if it is missing or blank.
+fn lookup<'a>(
+    field: &str,
+    path: &'a Path,
+    tags: &'a HashMap<String, Option<String>>,
+) -> Option<std::borrow::Cow<'a, str>> {
+    let value = if field.eq_ignore_ascii_case("Format") {
+        path.extension()
+            .map(|ext| ext.to_string_lossy().to_ascii_lowercase().into())
+    } else if field.eq_ignore_ascii_case("Filename") {
+        path.file_name().map(|name| name.to_string_lossy())
+    } else {
+        tags.iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(field))
+            .and_then(|(_, value)| value.as_deref())
+            .map(Into::into)
+    };
+    value.filter(|v| !v.trim().is_empty())
+}
+
+// ── evaluation ───────────────────────────────────────────────────────────────
+
+#[derive(Debug, Clone)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Present(String),
+    Equals(String, String),
+    Matches(String, Regex),
+    Compare(String, Ordering, bool, f64),
+}
+
+impl Expr {
+    /// Add the fields the expression compares to `fields`.
+    fn fields<'a>(&'a self, fields: &mut Vec<&'a str>) {
+        match self {
+            Self::And(a, b) | Self::Or(a, b) => {
+                a.fields(fields);
+                b.fields(fields);
+            }
+            Self::Not(e) => e.fields(fields),
+            Self::Present(field)
+            | Self::Equals(field, _)
+            | Self::Matches(field, _)
+            | Self::Compare(field, ..) => fields.push(field),
+        }
+    }
+
+    fn eval<'a>(&self, get: &dyn Fn(&str) -> Option<std::borrow::Cow<'a, str>>) -> bool {
+        match self {
+            Self::And(a, b) => a.eval(get) && b.eval(get),
+            Self::Or(a, b) => a.eval(get) || b.eval(get),
+            Self::Not(e) => !e.eval(get),
+            Self::Present(field) => get(field).is_some(),
+            Self::Equals(field, value) => get(field).is_some_and(|v| v.eq_ignore_ascii_case(value)),
+            Self::Matches(field, re) => get(field).is_some_and(|v| re.is_match(&v)),
+            // `ordering` is the wanted result of `field.cmp(value)`; `or_equal` widens it.
+            Self::Compare(field, ordering, or_equal, value) => get(field)
+                .and_then(|v| v.trim().parse::<f64>().ok())
+                .and_then(|v| v.partial_cmp(value))
+                .is_some_and(|o| o == *ordering || (*or_equal && o == Ordering::Equal)),
+        }
+    }
+}
+
+// ── tokens ───────────────────────────────────────────────────────────────────
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    LParen,
+    RParen,
+    And,
+    Or,
+    Not,
+    Is,
+    Op(&'static str),
+    Word(String),
+    Quoted(String),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::LParen => write!(f, "'('"),
+            Self::RParen => write!(f, "')'"),
+            Self::And => write!(f, "'and'"),
+            Self::Or => write!(f, "'or'"),
+            Self::Not => write!(f, "'not'"),
+            Self::Is => write!(f, "'is'"),
+            Self::Op(op) => write!(f, "'{op}'"),
+            Self::Word(w) => write!(f, "'{w}'"),
+            Self::Quoted(q) => write!(f, "{q:?}"),
+        }
+    }
+}
+
+/// Operators, longest first so `<=` is not read as `<` followed by `=`.
+const OPERATORS: &[&str] = &[
+    "==", "!=", "!~", "<=", ">=", "&&", "||", "=", "~", "<", ">", "!",
+];
+
+fn syntax(message: impl Into<String>, position: usize) -> FilterError {
+    FilterError::Syntax {
+        message: message.into(),
+        position,
+    }
+}
+
+/// Split `input` into tokens, each with its byte offset.
+fn tokenize(input: &str) -> Result<Vec<(Token, usize)>, FilterError> {
+    let mut tokens = Vec::new();
+    let mut rest = input.char_indices().peekable();
+
+    while let Some(&(start, ch)) = rest.peek() {
+        if ch.is_whitespace() {
+            rest.next();
+            continue;
+        }
+        let token = match ch {
+            '(' => {
+                rest.next();
+                Token::LParen
+            }
+            ')' => {
+                rest.next();
+                Token::RParen
+            }
+            '"' | '\'' => {
+                rest.next();
+                let mut value = String::new();
+                loop {
+                    match rest.next() {
+                        // Only the quote and the backslash itself are escaped, so regular
+                        // expressions such as `\d{4}` can be written as they are.
+                        Some((_, '\\')) => match rest.peek() {
+                            Some(&(_, c)) if c == ch || c == '\\' => {
+                                value.push(c);
+                                rest.next();
+                            }
+                            _ => value.push('\\'),
+                        },
+                        Some((_, c)) if c == ch => break,
+                        Some((_, c)) => value.push(c),
+                        None => return Err(syntax("Unterminated string", start)),
+                    }
+                }
+                Token::Quoted(value)
+            }
+            _ => {
+                if let Some(op) = OPERATORS.iter().find(|op| input[start..].starts_with(**op)) {
+                    for _ in 0..op.len() {
+                        rest.next();
+                    }
+                    match *op {
+                        "&&" => Token::And,
+                        "||" => Token::Or,
+                        "!" => Token::Not,
+                        "==" => Token::Op("="),
+                        op => Token::Op(op),
+                    }
+                } else {
+                    let mut word = String::new();
+                    while let Some(&(_, c)) = rest.peek() {
+                        if c.is_whitespace() || "()\"'=!~<>&|".contains(c) {
+                            break;
+                        }
+                        word.push(c);
+                        rest.next();
+                    }
+                    match word.to_ascii_lowercase().as_str() {
+                        "and" => Token::And,
+                        "or" => Token::Or,
+                        "not" => Token::Not,
+                        "is" => Token::Is,
+                        _ => Token::Word(word),
+                    }
+                }
+            }
+        };
+        tokens.push((token, start));
+    }
+
+    Ok(tokens)
+}
+
+// ── parser ───────────────────────────────────────────────────────────────────
+
+/// Recursive descent over the tokens. Precedence, loosest first: `or`, `and`, `not`.
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    /// Position reported for errors at the end of the input.
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(t, _)| t)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens.get(self.pos).map_or(self.end, |(_, p)| *p)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).map(|(t, _)| t.clone());
+        self.pos += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Expr, FilterError> {
+        let mut expr = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, FilterError> {
+        let mut expr = self.not()?;
+        while self.peek() == Some(&Token::And) {
+            self.pos += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<Expr, FilterError> {
+        if self.peek() == Some(&Token::Not) {
+            self.pos += 1;
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr, FilterError> {
+        let position = self.position();
+        match self.next() {
+            Some(Token::LParen) => {
+                let expr = self.or()?;
+                let close = self.position();
+                match self.next() {
+                    Some(Token::RParen) => Ok(expr),
+                    _ => Err(syntax("Expected ')'", close)),
+                }
+            }
+            Some(Token::Word(field)) => self.comparison(field),
+            Some(token) => Err(syntax(
+                format!("Expected a field name, found {token}"),
+                position,
+            )),
+            None => Err(syntax("Expected a field name", position)),
+        }
+    }
+
+    fn comparison(&mut self, field: String) -> Result<Expr, FilterError> {
+        let position = self.position();
+        match self.next() {
+            Some(Token::Is) => {
+                let negated = self.peek() == Some(&Token::Not);
+                if negated {
+                    self.pos += 1;
+                }
+                let position = self.position();
+                let present = match self.next() {
+                    Some(Token::Word(w)) if w.eq_ignore_ascii_case("present") => true,
+                    Some(Token::Word(w)) if w.eq_ignore_ascii_case("missing") => false,
+                    _ => return Err(syntax("Expected 'missing' or 'present'", position)),
+                };
+                let expr = Expr::Present(field);
+                Ok(if present == negated {
+                    Expr::Not(Box::new(expr))
+                } else {
+                    expr
+                })
+            }
+            Some(Token::Op(op)) => {
+                let value = self.value()?;
+                Ok(match op {
+                    "=" => Expr::Equals(field, value),
+                    "!=" => Expr::Not(Box::new(Expr::Equals(field, value))),
+                    "~" => Expr::Matches(field, regex(value)?),
+                    "!~" => Expr::Not(Box::new(Expr::Matches(field, regex(value)?))),
+                    _ => {
+                        let number = value.parse::<f64>().map_err(|_| {
+                            syntax(format!("Expected a number after '{op}'"), position)
+                        })?;
+                        let (ordering, or_equal) = match op {
+                            "<" => (Ordering::Less, false),
+                            "<=" => (Ordering::Less, true),
+                            ">" => (Ordering::Greater, false),
+                            _ => (Ordering::Greater, true),
+                        };
+                        Expr::Compare(field, ordering, or_equal, number)
+                    }
+                })
+            }
+            _ => Err(syntax(
+                format!("Expected an operator or 'is' after '{field}'"),
+                position,
+            )),
+        }
+    }
+
+    fn value(&mut self) -> Result<String, FilterError> {
+        let position = self.position();
+        match self.next() {
+            Some(Token::Word(value) | Token::Quoted(value)) => Ok(value),
+            _ => Err(syntax("Expected a value", position)),
+        }
+    }
+}
+
+fn regex(pattern: String) -> Result<Regex, FilterError> {
+    Regex::new(&pattern).map_err(|source| FilterError::Regex { pattern, source })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(pairs: &[(&str, Option<&str>)]) -> HashMap<String, Option<String>> {
+        pairs
+            .iter()
+            .map(|(k, v)| ((*k).to_string(), v.map(str::to_string)))
+            .collect()
+    }
+
+    fn book() -> HashMap<String, Option<String>> {
+        tags(&[
+            ("Title", Some("The Rust Book")),
+            ("Author", Some("Jane Doe")),
+            ("Publisher", None),
+            ("Year", Some("1999")),
+        ])
+    }
+
+    fn check(expr: &str) -> bool {
+        Filter::parse(expr)
+            .expect("parse")
+            .matches(Path::new("lib/rust.EPUB"), &book())
+    }
+
+    // ── comparisons ──────────────────────────────────────────────────────────
+
+    #[test]
+    fn equality_ignores_case_of_fields_and_values() {
+        assert!(check("author = 'jane doe'"));
+        assert!(check("Author == \"Jane Doe\""));
+        assert!(!check("Author != 'Jane Doe'"));
+    }
+
+    #[test]
+    fn fields_lists_every_compared_field() {
+        let filter = Filter::parse("Format = epub and not (Words < 5000 or Publisher is missing)")
+            .expect("parse");
+        assert_eq!(filter.fields(), ["Format", "Words", "Publisher"]);
+    }
+
+    #[test]
+    fn regex_match() {
+        assert!(check("Title ~ '^The '"));
+        assert!(check("Title !~ Python"));
+        assert!(
+            !check("Title ~ '^the '"),
+            "regular expressions are case-sensitive"
+        );
+    }
+
+    #[test]
+    fn backslashes_reach_the_regex() {
+        assert!(check(r"Title ~ '\bRust\b'"));
+        assert!(check(r"Year ~ '^\d{4}$'"));
+        assert!(check(r#"Title != "The \"Rust\" Book""#));
+    }
+
+    #[test]
+    fn missing_and_present() {
+        assert!(check("Publisher is missing"));
+        assert!(check("Identifier is missing"));
+        assert!(check("Title is present"));
+        assert!(check("Publisher is not present"));
+        assert!(!check("Title is not present"));
+    }
+
+    #[test]
+    fn blank_values_count_as_missing() {
+        let filter = Filter::parse("Title is missing").expect("parse");
+        assert!(filter.matches(Path::new("a.pdf"), &tags(&[("Title", Some("  "))])));
+    }
+
+    #[test]
+    fn numeric_comparisons() {
+        assert!(check("Year < 2000"));
+        assert!(check("Year <= 1999"));
+        assert!(!check("Year > 1999"));
+        assert!(check("Year >= 1999.0"));
+        // Non-numeric and missing values never compare.
+        assert!(!check("Title < 5"));
+        assert!(!check("Publisher < 5"));
+    }
+
+    #[test]
+    fn comparisons_on_missing_fields() {
+        assert!(!check("Publisher = Acme"));
+        assert!(check("Publisher != Acme"));
+        assert!(!check("Publisher ~ ."));
+        assert!(check("Publisher !~ ."));
+    }
+
+    #[test]
+    fn virtual_fields() {
+        assert!(check("Format = epub"));
+        assert!(check("Filename = rust.epub"));
+        assert!(!check("Format = pdf"));
+    }
+
+    // ── boolean logic ────────────────────────────────────────────────────────
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert!(check("Format = pdf and Year < 0 or Author = 'Jane Doe'"));
+        assert!(!check("Format = pdf and (Year < 0 or Author = 'Jane Doe')"));
+    }
+
+    #[test]
+    fn not_and_symbolic_operators() {
+        assert!(check("not Format = pdf"));
+        assert!(check("!(Format = pdf) && Year < 2000"));
+        assert!(check("Format = pdf || Publisher is missing"));
+        assert!(check("NOT not Title is present"));
+    }
+
+    // ── errors ───────────────────────────────────────────────────────────────
+
+    #[test]
+    fn syntax_errors_report_position() {
+        let cases = [
+            ("Title", 5),
+            ("Title = ", 8),
+            ("(Title = a", 10),
+            ("Title = a b", 10),
+            ("Year < soon", 5),
+            ("Title is gone", 9),
+            ("= a", 0),
+            ("Title = 'open", 8),
+        ];
+        for (expr, expected) in cases {
+            match Filter::parse(expr) {
+                Err(FilterError::Syntax { position, .. }) => {
+                    assert_eq!(position, expected, "wrong position for {expr:?}");
+                }
+                other => panic!("expected a syntax error for {expr:?}, got {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_regex_is_reported() {
+        let err = Filter::parse("Title ~ '('").expect_err("should fail");
+        assert!(matches!(err, FilterError::Regex { .. }), "got: {err}");
+    }
+}
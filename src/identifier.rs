@@ -0,0 +1,364 @@
+//! Recognise and normalise book identifiers.
+//!
+//! The `Identifier` tag holds whatever the file contains: `urn:isbn:978-...`, a bare
+//! ISBN-10, a UUID, a DOI, an Amazon ASIN and so on. [`parse`] works out which of these it
+//! is, and [`add_tags`] derives the `ISBN13`, `ISBN10` and `IdentifierType` tags from it.
+use crate::extract::Tags;
+use std::fmt;
+
+/// A recognised identifier.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Identifier {
+    /// A valid ISBN. Every ISBN has an ISBN-13 form; only those starting 978 have an
+    /// ISBN-10 form.
+    Isbn {
+        isbn13: String,
+        isbn10: Option<String>,
+    },
+    /// Something shaped like an ISBN whose check digit is wrong. Holds the digits.
+    InvalidIsbn(String),
+    /// An Amazon Standard Identification Number, uppercase.
+    Asin(String),
+    /// A Digital Object Identifier such as `10.1000/182`, lowercase (DOIs ignore case).
+    Doi(String),
+    /// A UUID, lowercase.
+    Uuid(String),
+    /// Any other URN, e.g. `urn:calibre:1234`. The namespace is lowercase.
+    Urn { namespace: String, value: String },
+    /// Anything else, trimmed.
+    Other(String),
+}
+
+impl Identifier {
+    /// A short name for the kind of identifier, e.g. `ISBN`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Isbn { .. } => "ISBN",
+            Self::InvalidIsbn(_) => "Invalid ISBN",
+            Self::Asin(_) => "ASIN",
+            Self::Doi(_) => "DOI",
+            Self::Uuid(_) => "UUID",
+            Self::Urn { .. } => "URN",
+            Self::Other(_) => "Other",
+        }
+    }
+}
+
+/// The normalised identifier: ISBNs as hyphen-free ISBN-13s, URNs as `urn:<ns>:<value>`,
+/// everything else as held.
+impl fmt::Display for Identifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Isbn { isbn13, .. } => f.write_str(isbn13),
+            Self::InvalidIsbn(s)
+            | Self::Asin(s)
+            | Self::Doi(s)
+            | Self::Uuid(s)
+            | Self::Other(s) => f.write_str(s),
+            Self::Urn { namespace, value } => write!(f, "urn:{namespace}:{value}"),
+        }
+    }
+}
+
+/// Work out what kind of identifier `raw` is. Returns `None` if it is blank.
+pub fn parse(raw: &str) -> Option<Identifier> {
+    let raw = raw.trim();
+    if raw.is_empty() {
+        return None;
+    }
+
+    // Explicit prefixes first: "urn:isbn:", "isbn:", "ISBN ", "doi:", "asin:", "urn:uuid:".
+    if let Some(rest) = strip_prefix(raw, "urn:isbn:").or_else(|| strip_isbn_label(raw)) {
+        let compact = compact(rest);
+        return Some(isbn(&compact).unwrap_or(Identifier::InvalidIsbn(compact)));
+    }
+    if let Some(rest) = strip_prefix(raw, "urn:uuid:") {
+        return Some(match uuid(rest) {
+            Some(uuid) => Identifier::Uuid(uuid),
+            None => urn("uuid", rest),
+        });
+    }
+    if let Some(rest) = strip_prefix(raw, "urn:doi:")
+        .or_else(|| strip_prefix(raw, "doi:"))
+        .or_else(|| strip_prefix(raw, "https://doi.org/"))
+        .or_else(|| strip_prefix(raw, "http://dx.doi.org/"))
+    {
+        return Some(Identifier::Doi(rest.trim().to_lowercase()));
+    }
+    if let Some(rest) = strip_prefix(raw, "urn:asin:").or_else(|| strip_prefix(raw, "asin:")) {
+        return Some(Identifier::Asin(rest.trim().to_uppercase()));
+    }
+
+    // Then bare forms.
+    let compact = compact(raw);
+    if let Some(isbn) = isbn(&compact) {
+        return Some(isbn);
+    }
+    if looks_like_isbn(&compact) {
+        return Some(Identifier::InvalidIsbn(compact));
+    }
+    if let Some(uuid) = uuid(raw) {
+        return Some(Identifier::Uuid(uuid));
+    }
+    if raw.starts_with("10.") && raw.contains('/') {
+        return Some(Identifier::Doi(raw.to_lowercase()));
+    }
+    if raw.len() == 10
+        && raw.starts_with("B0")
+        && raw
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+    {
+        return Some(Identifier::Asin(raw.to_string()));
+    }
+    if let Some(rest) = strip_prefix(raw, "urn:") {
+        if let Some((namespace, value)) = rest.split_once(':') {
+            return Some(urn(namespace, value));
+        }
+    }
+    Some(Identifier::Other(raw.to_string()))
+}
+
+/// Add the tags derived from the `Identifier` tag to `tags`:
+///
+/// * `ISBN13` and `ISBN10` - the ISBN in both forms, hyphen-free, if it is valid
+/// * `IdentifierType` - the [kind](Identifier::kind) of identifier
+///
+/// Each is `None` if it does not apply. Returns the parsed identifier.
+pub fn add_tags(tags: &mut Tags) -> Option<Identifier> {
+    let parsed = tags
+        .get("Identifier")
+        .and_then(Option::as_deref)
+        .and_then(parse);
+    let (isbn13, isbn10) = match &parsed {
+        Some(Identifier::Isbn { isbn13, isbn10 }) => (Some(isbn13.clone()), isbn10.clone()),
+        _ => (None, None),
+    };
+    tags.insert("ISBN13".to_string(), isbn13);
+    tags.insert("ISBN10".to_string(), isbn10);
+    tags.insert(
+        "IdentifierType".to_string(),
+        parsed.as_ref().map(|id| id.kind().to_string()),
+    );
+    parsed
+}
+
+/// `raw` without `prefix`, ignoring case.
+fn strip_prefix<'a>(raw: &'a str, prefix: &str) -> Option<&'a str> {
+    let head = raw.get(..prefix.len())?;
+    head.eq_ignore_ascii_case(prefix)
+        .then(|| &raw[prefix.len()..])
+}
+
+/// `raw` without a leading "ISBN" label, e.g. "ISBN: 0-306-40615-2" or "isbn 0306406152".
+fn strip_isbn_label(raw: &str) -> Option<&str> {
+    let rest = strip_prefix(raw, "isbn")?;
+    let trimmed = rest.trim_start_matches(['-', ':', ' ']);
+    (trimmed.len() < rest.len() || rest.starts_with(|c: char| c.is_ascii_digit()))
+        .then_some(trimmed)
+}
+
+/// `raw` with hyphens and spaces removed and a lowercase `x` check digit made uppercase.
+fn compact(raw: &str) -> String {
+    raw.chars()
+        .filter(|c| !matches!(c, '-' | ' '))
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+/// `true` if `compact` has the shape of an ISBN, whatever its check digit.
+fn looks_like_isbn(compact: &str) -> bool {
+    let all_digits = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+    if !compact.is_ascii() {
+        return false;
+    }
+    match compact.len() {
+        13 => all_digits(compact) && (compact.starts_with("978") || compact.starts_with("979")),
+        10 => {
+            all_digits(&compact[..9])
+                && compact[9..].chars().all(|c| c.is_ascii_digit() || c == 'X')
+        }
+        _ => false,
+    }
+}
+
+/// `compact` as an [`Identifier::Isbn`] if it is a valid ISBN-10 or ISBN-13.
+fn isbn(compact: &str) -> Option<Identifier> {
+    if !looks_like_isbn(compact) {
+        return None;
+    }
+    let digits: Vec<u32> = compact
+        .chars()
+        .map(|c| {
+            if c == 'X' {
+                10
+            } else {
+                c.to_digit(10).unwrap_or(0)
+            }
+        })
+        .collect();
+    if digits.len() == 10 {
+        let sum: u32 = digits.iter().zip((1..=10).rev()).map(|(d, w)| d * w).sum();
+        if sum % 11 != 0 {
+            return None;
+        }
+        let body = format!("978{}", &compact[..9]);
+        return Some(Identifier::Isbn {
+            isbn13: format!("{body}{}", isbn13_check(&body)),
+            isbn10: Some(compact.to_string()),
+        });
+    }
+    if isbn13_check(&compact[..12]) != digits[12] {
+        return None;
+    }
+    let isbn10 = compact.strip_prefix("978").map(|body| {
+        let body = &body[..9];
+        format!("{body}{}", isbn10_check(body))
+    });
+    Some(Identifier::Isbn {
+        isbn13: compact.to_string(),
+        isbn10,
+    })
+}
+
+/// The check digit for the first 12 digits of an ISBN-13.
+fn isbn13_check(body: &str) -> u32 {
+    let sum: u32 = body
+        .chars()
+        .filter_map(|c| c.to_digit(10))
+        .zip([1, 3].into_iter().cycle())
+        .map(|(d, w)| d * w)
+        .sum();
+    (10 - sum % 10) % 10
+}
+
+/// The check character for the first 9 digits of an ISBN-10.
+fn isbn10_check(body: &str) -> char {
+    let sum: u32 = body
+        .chars()
+        .filter_map(|c| c.to_digit(10))
+        .zip((2..=10).rev())
+        .map(|(d, w)| d * w)
+        .sum();
+    match (11 - sum % 11) % 11 {
+        10 => 'X',
+        n => char::from_digit(n, 10).unwrap_or('0'),
+    }
+}
+
+/// `raw` as a lowercase UUID if it is one (8-4-4-4-12 hex digits).
+fn uuid(raw: &str) -> Option<String> {
+    let raw = raw.trim();
+    let groups: Vec<&str> = raw.split('-').collect();
+    let shape = groups.iter().map(|g| g.len()).eq([8, 4, 4, 4, 12]);
+    (shape
+        && groups
+            .iter()
+            .all(|g| g.chars().all(|c| c.is_ascii_hexdigit())))
+    .then(|| raw.to_lowercase())
+}
+
+fn urn(namespace: &str, value: &str) -> Identifier {
+    Identifier::Urn {
+        namespace: namespace.to_lowercase(),
+        value: value.trim().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn isbn(isbn13: &str, isbn10: Option<&str>) -> Option<Identifier> {
+        Some(Identifier::Isbn {
+            isbn13: isbn13.to_string(),
+            isbn10: isbn10.map(str::to_string),
+        })
+    }
+
+    // ── ISBN ───────────────────────────────────────────────────────────────────
+
+    #[test]
+    fn isbns_are_recognised_in_any_common_form() {
+        let expected = isbn("9780306406157", Some("0306406152"));
+        assert_eq!(parse("urn:isbn:978-0-306-40615-7"), expected);
+        assert_eq!(parse("ISBN: 0-306-40615-2"), expected);
+        assert_eq!(parse("isbn 0306406152"), expected);
+        assert_eq!(parse("9780306406157"), expected);
+        assert_eq!(
+            parse("080442957x"),
+            isbn("9780804429573", Some("080442957X"))
+        );
+        assert_eq!(parse("979-10-90636-07-1"), isbn("9791090636071", None));
+    }
+
+    #[test]
+    fn bad_check_digits_are_flagged() {
+        assert_eq!(
+            parse("978-0-306-40615-8"),
+            Some(Identifier::InvalidIsbn("9780306406158".into()))
+        );
+        assert_eq!(
+            parse("0306406153"),
+            Some(Identifier::InvalidIsbn("0306406153".into()))
+        );
+        assert_eq!(
+            parse("urn:isbn:12345"),
+            Some(Identifier::InvalidIsbn("12345".into()))
+        );
+    }
+
+    // ── other identifiers ──────────────────────────────────────────────────────
+
+    #[test]
+    fn other_identifier_kinds_are_recognised() {
+        let uuid = "3F2504E0-4F89-11D3-9A0C-0305E82C3301";
+        let expected = Some(Identifier::Uuid(uuid.to_lowercase()));
+        assert_eq!(parse(&format!("urn:uuid:{uuid}")), expected);
+        assert_eq!(parse(uuid), expected);
+        assert_eq!(
+            parse("doi:10.1000/ABC"),
+            Some(Identifier::Doi("10.1000/abc".into()))
+        );
+        assert_eq!(
+            parse("https://doi.org/10.1000/182"),
+            Some(Identifier::Doi("10.1000/182".into()))
+        );
+        assert_eq!(
+            parse("B00ABCDEFG"),
+            Some(Identifier::Asin("B00ABCDEFG".into()))
+        );
+        assert_eq!(
+            parse("asin:b00abcdefg"),
+            Some(Identifier::Asin("B00ABCDEFG".into()))
+        );
+        assert_eq!(
+            parse("URN:Calibre:1234").map(|id| id.to_string()),
+            Some("urn:calibre:1234".into())
+        );
+        assert_eq!(
+            parse(" mybook-01 "),
+            Some(Identifier::Other("mybook-01".into()))
+        );
+        assert_eq!(parse("  "), None);
+    }
+
+    // ── tags ───────────────────────────────────────────────────────────────────
+
+    #[test]
+    fn tags_are_derived_from_the_identifier() {
+        let mut tags = Tags::from([(
+            "Identifier".to_string(),
+            Some("urn:isbn:978-0-306-40615-7".to_string()),
+        )]);
+        add_tags(&mut tags);
+        assert_eq!(tags["ISBN13"].as_deref(), Some("9780306406157"));
+        assert_eq!(tags["ISBN10"].as_deref(), Some("0306406152"));
+        assert_eq!(tags["IdentifierType"].as_deref(), Some("ISBN"));
+
+        let mut tags = Tags::new();
+        add_tags(&mut tags);
+        assert_eq!(tags["ISBN13"], None);
+        assert_eq!(tags["IdentifierType"], None);
+    }
+}
@@ -0,0 +1,230 @@
+//! Ask the user to confirm each planned rename before anything is moved.
+use crate::{
+    plan::Move,
+    rename_file::{self, RenameError},
+    sanitise::Sanitiser,
+};
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    io::{BufRead, Write},
+    path::{Path, PathBuf},
+};
+
+/// Metadata keys shown next to each rename to help the user decide.
+const KEY_FIELDS: &[&str] = &["Title", "Author", "Year"];
+
+/// Walk through `moves` one by one and return the renames the user accepted, as
+/// `(current path, new path)` pairs ready for [`crate::plan::build`].
+///
+/// For each move the old name, new name and the [`KEY_FIELDS`] from `tags` are written
+/// to `output`, and one answer is read from `input`:
+///
+/// - `y` (or just Enter): accept this rename
+/// - `n`: skip it
+/// - `e`: type a new name; the extension is kept if left out, and the name is sanitised
+///   with `sanitiser`
+/// - `a`: accept this and all remaining renames
+/// - `q`: stop and skip this and all remaining renames (end of input counts as `q`)
+///
+/// # Errors
+///
+/// Returns `Err` if reading from `input` or writing to `output` fails.
+pub fn review<R: BufRead, W: Write>(
+    moves: &[Move],
+    tags: &HashMap<PathBuf, HashMap<String, Option<String>>>,
+    sanitiser: &Sanitiser,
+    mut input: R,
+    mut output: W,
+) -> anyhow::Result<Vec<(PathBuf, PathBuf)>> {
+    let mut accepted = Vec::with_capacity(moves.len());
+
+    for (idx, m) in moves.iter().enumerate() {
+        writeln!(
+            output,
+            "\n[{}/{}] {}",
+            idx + 1,
+            moves.len(),
+            m.from.display()
+        )?;
+        writeln!(output, "     --> {}", m.to.display())?;
+        if let Some(file_tags) = tags.get(&m.from) {
+            for key in KEY_FIELDS {
+                let value = file_tags.get(*key).and_then(Option::as_deref);
+                writeln!(output, "     {key}: {}", value.unwrap_or("N/A"))?;
+            }
+        }
+
+        loop {
+            write!(output, "Rename? [Y]es/[n]o/[e]dit/[a]ll/[q]uit: ")?;
+            output.flush()?;
+            let Some(answer) = read_answer(&mut input)? else {
+                return Ok(accepted);
+            };
+
+            match answer.to_ascii_lowercase().as_str() {
+                "" | "y" | "yes" => accepted.push((m.from.clone(), m.to.clone())),
+                "n" | "no" => {}
+                "e" | "edit" => {
+                    write!(output, "New name: ")?;
+                    output.flush()?;
+                    let Some(name) = read_answer(&mut input)? else {
+                        return Ok(accepted);
+                    };
+                    if name.is_empty() {
+                        continue;
+                    }
+                    let Ok(edited) = edited_path(&m.from, &name, sanitiser) else {
+                        writeln!(output, "That name has no usable characters.")?;
+                        continue;
+                    };
+                    writeln!(output, "     --> {}", edited.display())?;
+                    accepted.push((m.from.clone(), edited));
+                }
+                "a" | "all" => {
+                    accepted.extend(moves[idx..].iter().map(|m| (m.from.clone(), m.to.clone())));
+                    return Ok(accepted);
+                }
+                "q" | "quit" => return Ok(accepted),
+                _ => {
+                    writeln!(output, "Please answer y, n, e, a or q.")?;
+                    continue;
+                }
+            }
+            break;
+        }
+    }
+
+    Ok(accepted)
+}
+
+/// Read one trimmed line, or `None` at end of input.
+fn read_answer<R: BufRead>(input: &mut R) -> std::io::Result<Option<String>> {
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(line.trim().to_string()))
+}
+
+/// Build the path for a name typed by the user: same directory as `from`, sanitised, and
+/// with `from`'s extension unless the user typed it.
+fn edited_path(from: &Path, name: &str, sanitiser: &Sanitiser) -> Result<PathBuf, RenameError> {
+    let ext = from.extension().unwrap_or_default();
+    let stem = match ext.to_str() {
+        Some(ext) if !ext.is_empty() => name.strip_suffix(&format!(".{ext}")).unwrap_or(name),
+        _ => name,
+    };
+    let stem = sanitiser.file_stem(stem, &ext.to_string_lossy());
+    if stem.is_empty() {
+        return Err(RenameError::EmptyResult);
+    }
+
+    let parent = from.parent().unwrap_or_else(|| Path::new("."));
+    Ok(rename_file::target_path(parent, OsStr::new(&stem), ext))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn moves(n: usize) -> Vec<Move> {
+        (1..=n)
+            .map(|i| Move {
+                from: PathBuf::from(format!("dir/old{i}.epub")),
+                to: PathBuf::from(format!("dir/new{i}.epub")),
+            })
+            .collect()
+    }
+
+    /// Run [`review`] with `answers` as input and return the accepted pairs and output.
+    fn run(moves: &[Move], answers: &str) -> (Vec<(PathBuf, PathBuf)>, String) {
+        let mut out = Vec::new();
+        let accepted = review(
+            moves,
+            &HashMap::new(),
+            &Sanitiser::default(),
+            Cursor::new(answers.as_bytes()),
+            &mut out,
+        )
+        .expect("review");
+        (accepted, String::from_utf8(out).expect("utf-8"))
+    }
+
+    #[test]
+    fn yes_and_no_accept_and_skip() {
+        let (accepted, _) = run(&moves(2), "y\nn\n");
+        assert_eq!(
+            accepted,
+            vec![("dir/old1.epub".into(), "dir/new1.epub".into())]
+        );
+    }
+
+    #[test]
+    fn empty_answer_accepts() {
+        let (accepted, _) = run(&moves(1), "\n");
+        assert_eq!(accepted.len(), 1);
+    }
+
+    #[test]
+    fn all_accepts_the_rest() {
+        let (accepted, _) = run(&moves(3), "n\na\n");
+        assert_eq!(accepted.len(), 2);
+        assert_eq!(accepted[0].0, Path::new("dir/old2.epub"));
+    }
+
+    #[test]
+    fn quit_and_end_of_input_stop_without_accepting() {
+        assert_eq!(run(&moves(3), "y\nq\ny\n").0.len(), 1);
+        assert_eq!(run(&moves(3), "y\n").0.len(), 1);
+    }
+
+    #[test]
+    fn edit_uses_typed_name_and_keeps_extension() {
+        let (accepted, _) = run(&moves(1), "e\nMy: Book\n");
+        assert_eq!(
+            accepted,
+            vec![("dir/old1.epub".into(), "dir/My - Book.epub".into())]
+        );
+
+        let (accepted, _) = run(&moves(1), "e\nOther.epub\n");
+        assert_eq!(accepted[0].1, Path::new("dir/Other.epub"));
+    }
+
+    #[test]
+    fn unusable_edit_asks_again() {
+        let (accepted, output) = run(&moves(1), "e\n???\nn\n");
+        assert!(accepted.is_empty());
+        assert!(
+            output.contains("no usable characters"),
+            "no hint in: {output}"
+        );
+    }
+
+    #[test]
+    fn unknown_answer_asks_again() {
+        let (accepted, output) = run(&moves(1), "maybe\ny\n");
+        assert_eq!(accepted.len(), 1);
+        assert!(output.contains("Please answer"), "no hint in: {output}");
+    }
+
+    #[test]
+    fn key_metadata_is_shown() {
+        let m = moves(1);
+        let file_tags = HashMap::from([("Title".to_string(), Some("The Book".to_string()))]);
+        let tags = HashMap::from([(m[0].from.clone(), file_tags)]);
+        let mut out = Vec::new();
+        review(
+            &m,
+            &tags,
+            &Sanitiser::default(),
+            Cursor::new(b"y\n".as_slice()),
+            &mut out,
+        )
+        .expect("review");
+        let out = String::from_utf8(out).expect("utf-8");
+        assert!(out.contains("Title: The Book"), "missing title in: {out}");
+        assert!(out.contains("Author: N/A"), "missing author in: {out}");
+    }
+}
@@ -0,0 +1,445 @@
+//! Rename journal: a record of every move made during a run, so the run can be undone.
+//!
+//! Each run that renames at least one file writes a JSON Lines file to [`default_dir`]
+//! (or wherever the caller points it), one [`Entry`] per line, in the order the moves
+//! were made. [`undo`] replays a journal backwards.
+//!
+//! Paths are stored with [`utils::stored_path`], so non-UTF-8 paths survive the round trip.
+use crate::utils;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// File name prefix of journals written by [`Journal::create`].
+const JOURNAL_PREFIX: &str = "rename-";
+
+/// Extension of journals that have not been undone yet.
+const JOURNAL_EXTENSION: &str = "jsonl";
+
+/// Extension given to a journal once [`undo`] has reversed all of its moves.
+const UNDONE_EXTENSION: &str = "undone";
+
+/// Errors that can occur when writing or replaying a journal.
+#[derive(Debug, thiserror::Error)]
+pub enum JournalError {
+    /// The journal file could not be created, written or read.
+    #[error("Unable to access journal {}: {source}", .path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    /// A line in the journal is not a valid entry.
+    #[error("Malformed entry on line {line} of {}: {source}", .path.display())]
+    Malformed {
+        path: PathBuf,
+        line: usize,
+        #[source]
+        source: serde_json::Error,
+    },
+    /// No journal was given and none could be found in the journal directory.
+    #[error("No journal found in {}", .0.display())]
+    NotFound(PathBuf),
+}
+
+/// A single move recorded in a journal.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    /// Where the file was before the move.
+    #[serde(with = "utils::stored_path")]
+    pub from: PathBuf,
+    /// Where the file was moved to.
+    #[serde(with = "utils::stored_path")]
+    pub to: PathBuf,
+    /// Seconds since `UNIX_EPOCH` when the move was made.
+    pub timestamp: u64,
+    /// BLAKE3 hash of the file contents, as returned by [`utils::hash_file`].
+    pub hash: String,
+}
+
+/// An append-only journal for the current run.
+///
+/// The file is only created when the first entry is recorded, so runs that rename
+/// nothing leave nothing behind.
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    file: Option<File>,
+}
+
+impl Journal {
+    /// Prepare a new journal in `dir`, named after the current time and process id.
+    pub fn create<P: AsRef<Path>>(dir: P) -> Self {
+        let path = dir.as_ref().join(format!(
+            "{JOURNAL_PREFIX}{}-{}.{JOURNAL_EXTENSION}",
+            now(),
+            std::process::id()
+        ));
+        Self { path, file: None }
+    }
+
+    /// The path the journal is (or will be) written to.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append a move of `from` to `to`. `hash` is the content hash of the file moved.
+    ///
+    /// Each entry is flushed immediately so an interrupted run still leaves a usable journal.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`JournalError::Io`] if the journal cannot be created or written.
+    pub fn record(&mut self, from: &Path, to: &Path, hash: String) -> Result<(), JournalError> {
+        let entry = Entry {
+            from: from.to_path_buf(),
+            to: to.to_path_buf(),
+            timestamp: now(),
+            hash,
+        };
+        let io_err = |source| JournalError::Io {
+            path: self.path.clone(),
+            source,
+        };
+
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => {
+                if let Some(dir) = self.path.parent() {
+                    std::fs::create_dir_all(dir).map_err(io_err)?;
+                }
+                log::debug!("Writing rename journal to {}", self.path.display());
+                self.file.insert(File::create(&self.path).map_err(io_err)?)
+            }
+        };
+
+        let mut line = serde_json::to_string(&entry)
+            .map_err(std::io::Error::other)
+            .map_err(io_err)?;
+        line.push('\n');
+        file.write_all(line.as_bytes()).map_err(io_err)?;
+        file.flush().map_err(io_err)
+    }
+
+    /// `true` if at least one entry has been recorded.
+    pub const fn is_written(&self) -> bool {
+        self.file.is_some()
+    }
+}
+
+/// The directory journals are kept in: `<data dir>/docmeta/journal`, or `None` if the
+/// platform has no data directory.
+pub fn default_dir() -> Option<PathBuf> {
+    dirs::data_local_dir().map(|d| d.join(clap::crate_name!()).join("journal"))
+}
+
+/// Return the most recent journal in `dir` that has not been undone.
+///
+/// # Errors
+///
+/// Returns [`JournalError::NotFound`] if there is no such journal, and
+/// [`JournalError::Io`] if `dir` cannot be read.
+pub fn latest<P: AsRef<Path>>(dir: P) -> Result<PathBuf, JournalError> {
+    let dir = dir.as_ref();
+    let not_found = || JournalError::NotFound(dir.to_path_buf());
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Err(not_found()),
+        Err(source) => {
+            return Err(JournalError::Io {
+                path: dir.to_path_buf(),
+                source,
+            });
+        }
+    };
+
+    entries
+        .filter_map(Result::ok)
+        .map(|e| e.path())
+        .filter(|p| {
+            utils::get_extension(p) == JOURNAL_EXTENSION
+                && p.file_name()
+                    .is_some_and(|n| n.to_string_lossy().starts_with(JOURNAL_PREFIX))
+        })
+        .max_by_key(|p| (journal_timestamp(p), p.clone()))
+        .ok_or_else(not_found)
+}
+
+/// Read every entry from the journal at `path`.
+///
+/// # Errors
+///
+/// Returns [`JournalError::Io`] if the file cannot be read and
+/// [`JournalError::Malformed`] if a non-blank line is not a valid entry.
+pub fn read<P: AsRef<Path>>(path: P) -> Result<Vec<Entry>, JournalError> {
+    let path = path.as_ref();
+    let file = File::open(path).map_err(|source| JournalError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+
+    let mut entries = Vec::new();
+    for (idx, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|source| JournalError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry = serde_json::from_str(&line).map_err(|source| JournalError::Malformed {
+            path: path.to_path_buf(),
+            line: idx + 1,
+            source,
+        })?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// The outcome of an [`undo`].
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct UndoSummary {
+    /// Moves that were reversed (or would be, in a dry run).
+    pub restored: usize,
+    /// Moves that had already been reversed by an earlier undo.
+    pub already_restored: usize,
+    /// Moves that could not be reversed safely and were left alone.
+    pub skipped: usize,
+}
+
+/// Reverse the moves recorded in the journal at `path`, newest first.
+///
+/// Before moving a file back, checks that it is still at the recorded destination,
+/// still has the recorded content hash, and that nothing has taken its original path.
+/// Entries that fail a check are logged and skipped. Once every entry has been
+/// reversed the journal is renamed with the `.undone` extension so it is not picked
+/// up by [`latest`] again.
+///
+/// # Errors
+///
+/// Returns a [`JournalError`] if the journal cannot be read or renamed. Failures to
+/// move individual files are counted in [`UndoSummary::skipped`] instead.
+pub fn undo<P: AsRef<Path>>(path: P, dry_run: bool) -> Result<UndoSummary, JournalError> {
+    let path = path.as_ref();
+    let entries = read(path)?;
+    let mut summary = UndoSummary::default();
+
+    for entry in entries.iter().rev() {
+        let (from, to) = (entry.from.as_path(), entry.to.as_path());
+
+        if !to.exists() {
+            if from.exists() && has_hash(from, &entry.hash) {
+                log::debug!("{} is already back in place.", from.display());
+                summary.already_restored += 1;
+            } else {
+                log::warn!("{} is no longer there. Skipping.", to.display());
+                summary.skipped += 1;
+            }
+            continue;
+        }
+        if !has_hash(to, &entry.hash) {
+            log::warn!(
+                "{} has changed since it was renamed. Skipping.",
+                to.display()
+            );
+            summary.skipped += 1;
+            continue;
+        }
+        if from.exists() {
+            log::warn!("{} is taken by another file. Skipping.", from.display());
+            summary.skipped += 1;
+            continue;
+        }
+
+        if dry_run {
+            log::info!("dry_run: {} --> {}", to.display(), from.display());
+        } else if let Err(err) = std::fs::rename(to, from) {
+            log::warn!(
+                "Unable to move {} back to {}: {err}",
+                to.display(),
+                from.display()
+            );
+            summary.skipped += 1;
+            continue;
+        } else {
+            log::info!("{} --> {}", to.display(), from.display());
+        }
+        summary.restored += 1;
+    }
+
+    if !dry_run && summary.skipped == 0 {
+        let done = path.with_extension(UNDONE_EXTENSION);
+        std::fs::rename(path, &done).map_err(|source| JournalError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        log::debug!("Journal marked as undone: {}", done.display());
+    }
+
+    Ok(summary)
+}
+
+/// `true` if the contents of `path` hash to `hash`. Unreadable files never match.
+fn has_hash(path: &Path, hash: &str) -> bool {
+    utils::hash_file(path).is_ok_and(|h| h == hash)
+}
+
+/// Extract the timestamp from a journal file name written by [`Journal::create`].
+fn journal_timestamp(path: &Path) -> u64 {
+    path.file_stem()
+        .and_then(|s| s.to_str())
+        .and_then(|s| s.strip_prefix(JOURNAL_PREFIX))
+        .and_then(|s| s.split('-').next())
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(0)
+}
+
+/// Seconds since `UNIX_EPOCH`, or 0 if the clock is set before it.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    /// Move `from` to `to` and record it, the way the rename engine does.
+    fn move_and_record(journal: &mut Journal, from: &Path, to: &Path) {
+        let hash = utils::hash_file(from).expect("hash");
+        fs::rename(from, to).expect("rename");
+        journal.record(from, to, hash).expect("record");
+    }
+
+    #[test]
+    fn journal_is_not_created_until_first_record() {
+        let dir = tempdir().expect("temp dir");
+        let journal = Journal::create(dir.path());
+        assert!(!journal.is_written());
+        assert!(!journal.path().exists());
+    }
+
+    #[test]
+    fn recorded_entries_round_trip() {
+        let dir = tempdir().expect("temp dir");
+        let mut journal = Journal::create(dir.path().join("journal"));
+        journal
+            .record(Path::new("a.epub"), Path::new("b.epub"), "abc".to_string())
+            .expect("record");
+        journal
+            .record(Path::new("c.pdf"), Path::new("d.pdf"), "def".to_string())
+            .expect("record");
+
+        let entries = read(journal.path()).expect("read");
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].from, Path::new("a.epub"));
+        assert_eq!(entries[1].to, Path::new("d.pdf"));
+        assert_eq!(entries[1].hash, "def");
+    }
+
+    #[test]
+    fn undo_reverses_moves_in_reverse_order() {
+        // a -> b, then c -> a: undo must move a back to c before b back to a.
+        let dir = tempdir().expect("temp dir");
+        let (a, b, c) = (
+            dir.path().join("a.epub"),
+            dir.path().join("b.epub"),
+            dir.path().join("c.epub"),
+        );
+        fs::write(&a, b"first").expect("write a");
+        fs::write(&c, b"second").expect("write c");
+
+        let mut journal = Journal::create(dir.path().join("journal"));
+        move_and_record(&mut journal, &a, &b);
+        move_and_record(&mut journal, &c, &a);
+
+        let summary = undo(journal.path(), false).expect("undo");
+        assert_eq!(summary.restored, 2);
+        assert_eq!(summary.skipped, 0);
+        assert_eq!(fs::read(&a).expect("read a"), b"first");
+        assert_eq!(fs::read(&c).expect("read c"), b"second");
+        assert!(!b.exists(), "b should be gone");
+        assert!(
+            journal.path().with_extension(UNDONE_EXTENSION).exists(),
+            "journal should be marked as undone"
+        );
+    }
+
+    #[test]
+    fn undo_skips_files_that_changed() {
+        let dir = tempdir().expect("temp dir");
+        let (a, b) = (dir.path().join("a.epub"), dir.path().join("b.epub"));
+        fs::write(&a, b"original").expect("write a");
+
+        let mut journal = Journal::create(dir.path().join("journal"));
+        move_and_record(&mut journal, &a, &b);
+        fs::write(&b, b"edited").expect("edit b");
+
+        let summary = undo(journal.path(), false).expect("undo");
+        assert_eq!(summary.skipped, 1);
+        assert!(b.exists(), "changed file must be left alone");
+        assert!(
+            journal.path().exists(),
+            "journal must be kept after a partial undo"
+        );
+    }
+
+    #[test]
+    fn undo_dry_run_moves_nothing() {
+        let dir = tempdir().expect("temp dir");
+        let (a, b) = (dir.path().join("a.epub"), dir.path().join("b.epub"));
+        fs::write(&a, b"data").expect("write a");
+
+        let mut journal = Journal::create(dir.path().join("journal"));
+        move_and_record(&mut journal, &a, &b);
+
+        let summary = undo(journal.path(), true).expect("undo");
+        assert_eq!(summary.restored, 1);
+        assert!(b.exists() && !a.exists(), "dry run must not move files");
+        assert!(journal.path().exists());
+    }
+
+    #[test]
+    fn latest_picks_newest_pending_journal() {
+        let dir = tempdir().expect("temp dir");
+        fs::write(dir.path().join("rename-100-1.jsonl"), b"").expect("write");
+        fs::write(dir.path().join("rename-300-1.undone"), b"").expect("write");
+        fs::write(dir.path().join("rename-200-1.jsonl"), b"").expect("write");
+
+        let found = latest(dir.path()).expect("latest");
+        assert_eq!(found, dir.path().join("rename-200-1.jsonl"));
+    }
+
+    #[test]
+    fn latest_in_missing_dir_is_not_found() {
+        let dir = tempdir().expect("temp dir");
+        let err = latest(dir.path().join("nope")).expect_err("should fail");
+        assert!(matches!(err, JournalError::NotFound(_)), "got: {err}");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_paths_round_trip() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let dir = tempdir().expect("temp dir");
+        let latin1 = Path::new(OsStr::from_bytes(b"caf\xe9.epub"));
+        let mut journal = Journal::create(dir.path().join("journal"));
+        journal
+            .record(latin1, Path::new("Cafe.epub"), "abc".to_string())
+            .expect("record");
+
+        let text = fs::read_to_string(journal.path()).expect("journal is UTF-8 JSON");
+        assert!(text.contains(r#""to":"Cafe.epub""#), "unexpected: {text}");
+        let entries = read(journal.path()).expect("read");
+        assert_eq!(entries[0].from, latin1);
+    }
+}
@@ -0,0 +1,333 @@
+//! Normalise the language a book declares to a BCP 47 tag.
+//!
+//! EPUBs write `en`, `EN-us`, `eng`, `en_GB` or `English`, MOBI headers carry a Windows
+//! language code, and PDFs have a `/Lang` entry in the catalog. [`normalise`] turns all of
+//! these into a BCP 47 tag such as `en-US`: the shortest ISO 639 code for the language,
+//! followed by any script and region subtags in their canonical case.
+use crate::extract::Tags;
+
+/// ISO 639 languages: the two-letter code (or three-letter where there is none), the
+/// three-letter codes that stand for it, and its English name.
+const LANGUAGES: &[(&str, &[&str], &str)] = &[
+    ("af", &["afr"], "Afrikaans"),
+    ("am", &["amh"], "Amharic"),
+    ("ar", &["ara"], "Arabic"),
+    ("as", &["asm"], "Assamese"),
+    ("az", &["aze"], "Azerbaijani"),
+    ("be", &["bel"], "Belarusian"),
+    ("bg", &["bul"], "Bulgarian"),
+    ("bn", &["ben"], "Bengali"),
+    ("bo", &["bod", "tib"], "Tibetan"),
+    ("br", &["bre"], "Breton"),
+    ("bs", &["bos"], "Bosnian"),
+    ("ca", &["cat"], "Catalan"),
+    ("cs", &["ces", "cze"], "Czech"),
+    ("cy", &["cym", "wel"], "Welsh"),
+    ("da", &["dan"], "Danish"),
+    ("de", &["deu", "ger"], "German"),
+    ("el", &["ell", "gre"], "Greek"),
+    ("en", &["eng"], "English"),
+    ("eo", &["epo"], "Esperanto"),
+    ("es", &["spa"], "Spanish"),
+    ("et", &["est"], "Estonian"),
+    ("eu", &["eus", "baq"], "Basque"),
+    ("fa", &["fas", "per"], "Persian"),
+    ("fi", &["fin"], "Finnish"),
+    ("fo", &["fao"], "Faroese"),
+    ("fr", &["fra", "fre"], "French"),
+    ("fy", &["fry"], "Western Frisian"),
+    ("ga", &["gle"], "Irish"),
+    ("gd", &["gla"], "Scottish Gaelic"),
+    ("gl", &["glg"], "Galician"),
+    ("gu", &["guj"], "Gujarati"),
+    ("he", &["heb"], "Hebrew"),
+    ("hi", &["hin"], "Hindi"),
+    ("hr", &["hrv"], "Croatian"),
+    ("hsb", &["hsb"], "Upper Sorbian"),
+    ("hu", &["hun"], "Hungarian"),
+    ("hy", &["hye", "arm"], "Armenian"),
+    ("id", &["ind"], "Indonesian"),
+    ("is", &["isl", "ice"], "Icelandic"),
+    ("it", &["ita"], "Italian"),
+    ("ja", &["jpn"], "Japanese"),
+    ("ka", &["kat", "geo"], "Georgian"),
+    ("kk", &["kaz"], "Kazakh"),
+    ("km", &["khm"], "Khmer"),
+    ("kn", &["kan"], "Kannada"),
+    ("ko", &["kor"], "Korean"),
+    ("kok", &["kok"], "Konkani"),
+    ("ku", &["kur"], "Kurdish"),
+    ("ky", &["kir"], "Kyrgyz"),
+    ("la", &["lat"], "Latin"),
+    ("lb", &["ltz"], "Luxembourgish"),
+    ("lo", &["lao"], "Lao"),
+    ("lt", &["lit"], "Lithuanian"),
+    ("lv", &["lav"], "Latvian"),
+    ("mi", &["mri", "mao"], "Maori"),
+    ("mk", &["mkd", "mac"], "Macedonian"),
+    ("ml", &["mal"], "Malayalam"),
+    ("mn", &["mon"], "Mongolian"),
+    ("mr", &["mar"], "Marathi"),
+    ("ms", &["msa", "may"], "Malay"),
+    ("mt", &["mlt"], "Maltese"),
+    ("my", &["mya", "bur"], "Burmese"),
+    ("nb", &["nob"], "Norwegian Bokmål"),
+    ("ne", &["nep"], "Nepali"),
+    ("nl", &["nld", "dut"], "Dutch"),
+    ("nn", &["nno"], "Norwegian Nynorsk"),
+    ("no", &["nor"], "Norwegian"),
+    ("oc", &["oci"], "Occitan"),
+    ("or", &["ori"], "Odia"),
+    ("pa", &["pan"], "Punjabi"),
+    ("pl", &["pol"], "Polish"),
+    ("ps", &["pus"], "Pashto"),
+    ("pt", &["por"], "Portuguese"),
+    ("rm", &["roh"], "Romansh"),
+    ("ro", &["ron", "rum"], "Romanian"),
+    ("ru", &["rus"], "Russian"),
+    ("sa", &["san"], "Sanskrit"),
+    ("se", &["sme"], "Northern Sami"),
+    ("si", &["sin"], "Sinhala"),
+    ("sk", &["slk", "slo"], "Slovak"),
+    ("sl", &["slv"], "Slovenian"),
+    ("so", &["som"], "Somali"),
+    ("sq", &["sqi", "alb"], "Albanian"),
+    ("sr", &["srp"], "Serbian"),
+    ("st", &["sot"], "Southern Sotho"),
+    ("sv", &["swe"], "Swedish"),
+    ("sw", &["swa"], "Swahili"),
+    ("ta", &["tam"], "Tamil"),
+    ("te", &["tel"], "Telugu"),
+    ("tg", &["tgk"], "Tajik"),
+    ("th", &["tha"], "Thai"),
+    ("tk", &["tuk"], "Turkmen"),
+    ("tl", &["tgl"], "Tagalog"),
+    ("tn", &["tsn"], "Tswana"),
+    ("tr", &["tur"], "Turkish"),
+    ("ts", &["tso"], "Tsonga"),
+    ("tt", &["tat"], "Tatar"),
+    ("ug", &["uig"], "Uyghur"),
+    ("uk", &["ukr"], "Ukrainian"),
+    ("ur", &["urd"], "Urdu"),
+    ("uz", &["uzb"], "Uzbek"),
+    ("vi", &["vie"], "Vietnamese"),
+    ("xh", &["xho"], "Xhosa"),
+    ("yi", &["yid"], "Yiddish"),
+    ("yo", &["yor"], "Yoruba"),
+    ("zh", &["zho", "chi"], "Chinese"),
+    ("zu", &["zul"], "Zulu"),
+];
+
+/// Other English names for languages in [`LANGUAGES`], including those the MOBI header uses.
+const ALIASES: &[(&str, &str)] = &[
+    ("azeri", "az"),
+    ("castilian", "es"),
+    ("faeroese", "fo"),
+    ("farsi", "fa"),
+    ("flemish", "nl"),
+    ("gaelic", "gd"),
+    ("kazak", "kk"),
+    ("moldavian", "ro"),
+    ("oriya", "or"),
+    ("panjabi", "pa"),
+    ("rhaetoromanic", "rm"),
+    ("sami", "se"),
+    ("sesotho", "st"),
+    ("sorbian", "hsb"),
+    ("sutu", "st"),
+];
+
+/// Tags and names that say the language is not known.
+const UNDETERMINED: &[&str] = &["und", "unknown", "neutral", "none", "n/a"];
+
+/// Normalise `raw` to a BCP 47 tag, e.g. `EN_us` to `en-US`, `fre` to `fr` and `English`
+/// to `en`.
+///
+/// Returns `None` if `raw` is empty, undetermined (`und`) or not a language.
+pub fn normalise(raw: &str) -> Option<String> {
+    let raw = raw.trim();
+    if is_undetermined(raw) {
+        return None;
+    }
+    if let Some(code) = by_name(raw) {
+        return Some(code.to_string());
+    }
+
+    let mut subtags = raw.split(['-', '_']);
+    let mut tag = primary(subtags.next()?)?;
+    // After a singleton such as `x` come extensions and private use, which stay lower case.
+    let mut extension = false;
+    for subtag in subtags {
+        if subtag.is_empty()
+            || subtag.len() > 8
+            || !subtag.chars().all(|c| c.is_ascii_alphanumeric())
+        {
+            return None;
+        }
+        tag.push('-');
+        let alphabetic = subtag.chars().all(|c| c.is_ascii_alphabetic());
+        if extension || subtag.len() == 1 {
+            extension = true;
+            tag.push_str(&subtag.to_ascii_lowercase());
+        } else if subtag.len() == 4 && alphabetic {
+            // Script, e.g. `Hant`.
+            tag.push_str(&subtag[..1].to_ascii_uppercase());
+            tag.push_str(&subtag[1..].to_ascii_lowercase());
+        } else if (subtag.len() == 2 && alphabetic)
+            || (subtag.len() == 3 && subtag.chars().all(|c| c.is_ascii_digit()))
+        {
+            // Region, e.g. `US` or `419`.
+            tag.push_str(&subtag.to_ascii_uppercase());
+        } else {
+            tag.push_str(&subtag.to_ascii_lowercase());
+        }
+    }
+    Some(tag)
+}
+
+/// The English name of the language of a BCP 47 `tag`, e.g. `French` for `fr-CA`.
+pub fn name(tag: &str) -> Option<&'static str> {
+    let primary = tag.split(['-', '_']).next()?;
+    LANGUAGES
+        .iter()
+        .find(|(code, ..)| primary.eq_ignore_ascii_case(code))
+        .map(|&(.., name)| name)
+}
+
+/// Normalise the `Language` tag and add `LanguageName`, its English name.
+///
+/// A `Language` that is not recognised is kept as it is, with no `LanguageName`.
+pub fn add_tags(tags: &mut Tags) {
+    let raw = tags.get("Language").and_then(Option::as_deref);
+    let language = raw.and_then(normalise);
+    let english = language.as_deref().and_then(name);
+    if language.is_some() || raw.is_some_and(|r| is_undetermined(r.trim())) {
+        tags.insert("Language".to_string(), language);
+    }
+    tags.insert("LanguageName".to_string(), english.map(str::to_string));
+}
+
+/// Whether `raw` says nothing about the language, e.g. it is empty or `und`.
+fn is_undetermined(raw: &str) -> bool {
+    raw.is_empty() || UNDETERMINED.iter().any(|u| raw.eq_ignore_ascii_case(u))
+}
+
+/// The code for a language written out by name, e.g. `english` or `Farsi`.
+fn by_name(name: &str) -> Option<&'static str> {
+    LANGUAGES
+        .iter()
+        .find(|(.., n)| n.eq_ignore_ascii_case(name))
+        .map(|&(code, ..)| code)
+        .or_else(|| {
+            ALIASES
+                .iter()
+                .find(|(alias, _)| alias.eq_ignore_ascii_case(name))
+                .map(|&(_, code)| code)
+        })
+}
+
+/// The shortest code for a primary language subtag, or `None` if it is not one.
+///
+/// Two- and three-letter codes not in [`LANGUAGES`] are kept, lower-cased, as BCP 47
+/// allows any registered ISO 639 code.
+fn primary(subtag: &str) -> Option<String> {
+    if !(2..=3).contains(&subtag.len()) || !subtag.chars().all(|c| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    let subtag = subtag.to_ascii_lowercase();
+    let code = LANGUAGES
+        .iter()
+        .find(|(code, three, _)| *code == subtag || three.contains(&subtag.as_str()))
+        .map_or(subtag, |(code, ..)| (*code).to_string());
+    Some(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // ── normalise ────────────────────────────────────────────────────────────
+
+    #[test]
+    fn codes_are_put_in_canonical_case() {
+        assert_eq!(normalise("en").as_deref(), Some("en"));
+        assert_eq!(normalise("EN-us").as_deref(), Some("en-US"));
+        assert_eq!(normalise(" en_GB ").as_deref(), Some("en-GB"));
+        assert_eq!(normalise("zh-hant-tw").as_deref(), Some("zh-Hant-TW"));
+        assert_eq!(normalise("es-419").as_deref(), Some("es-419"));
+        assert_eq!(normalise("de-CH-1996").as_deref(), Some("de-CH-1996"));
+        assert_eq!(normalise("en-x-US").as_deref(), Some("en-x-us"));
+    }
+
+    #[test]
+    fn three_letter_codes_become_two_letter_ones() {
+        assert_eq!(normalise("eng").as_deref(), Some("en"));
+        assert_eq!(normalise("fre").as_deref(), Some("fr"));
+        assert_eq!(normalise("FRA-ca").as_deref(), Some("fr-CA"));
+        assert_eq!(normalise("ger").as_deref(), Some("de"));
+        assert_eq!(
+            normalise("haw").as_deref(),
+            Some("haw"),
+            "no two-letter code"
+        );
+    }
+
+    #[test]
+    fn english_names_become_codes() {
+        assert_eq!(normalise("English").as_deref(), Some("en"));
+        assert_eq!(normalise("french").as_deref(), Some("fr"));
+        assert_eq!(normalise("Farsi").as_deref(), Some("fa"));
+        assert_eq!(normalise("Norwegian Bokmål").as_deref(), Some("nb"));
+    }
+
+    #[test]
+    fn empty_undetermined_and_malformed_values_are_rejected() {
+        assert_eq!(normalise(""), None);
+        assert_eq!(normalise("  "), None);
+        assert_eq!(normalise("und"), None);
+        assert_eq!(normalise("Neutral"), None);
+        assert_eq!(normalise("Klingon"), None);
+        assert_eq!(normalise("e"), None);
+        assert_eq!(normalise("en--US"), None);
+        assert_eq!(normalise("en-US!"), None);
+    }
+
+    // ── name ─────────────────────────────────────────────────────────────────
+
+    #[test]
+    fn name_is_that_of_the_primary_language() {
+        assert_eq!(name("fr-CA"), Some("French"));
+        assert_eq!(name("pt"), Some("Portuguese"));
+        assert_eq!(name("tlh"), None);
+    }
+
+    // ── add_tags ─────────────────────────────────────────────────────────────
+
+    fn tags(language: Option<&str>) -> Tags {
+        Tags::from([("Language".to_string(), language.map(str::to_string))])
+    }
+
+    #[test]
+    fn language_is_normalised_and_named() {
+        let mut tags = tags(Some("EN_us"));
+        add_tags(&mut tags);
+        assert_eq!(tags["Language"].as_deref(), Some("en-US"));
+        assert_eq!(tags["LanguageName"].as_deref(), Some("English"));
+    }
+
+    #[test]
+    fn unrecognised_languages_are_kept_without_a_name() {
+        let mut tags = tags(Some("Klingon"));
+        add_tags(&mut tags);
+        assert_eq!(tags["Language"].as_deref(), Some("Klingon"));
+        assert_eq!(tags["LanguageName"], None);
+    }
+
+    #[test]
+    fn undetermined_languages_are_cleared() {
+        let mut tags = tags(Some("und"));
+        add_tags(&mut tags);
+        assert_eq!(tags["Language"], None);
+        assert_eq!(tags["LanguageName"], None);
+    }
+}
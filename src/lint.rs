@@ -0,0 +1,569 @@
+//! Check files' metadata against a set of rules, e.g. before they are added to a library.
+//!
+//! Each [`Rule`] has a [`Severity`]; `off` disables it. [`check`] returns the violations
+//! for one file and [`write`] reports them as text or JSON.
+use crate::{
+    extract::Tags,
+    identifier::{self, Identifier},
+    utils::OutputFormat,
+};
+use clap::{ValueEnum, builder::PossibleValue};
+use regex::Regex;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+
+/// Errors from parsing a rule setting.
+#[derive(Debug, thiserror::Error)]
+pub enum LintError {
+    /// A `--rule` value is not `<rule>=<severity>`.
+    #[error("Expected <rule>=<severity>, e.g. missing-author=error, not {0:?}")]
+    Setting(String),
+    /// A `--rule` value names a rule or severity that does not exist.
+    #[error("Unknown {kind} {name:?}")]
+    Unknown { kind: &'static str, name: String },
+}
+
+/// Something that can be wrong with a file's metadata.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Rule {
+    /// The file could not be read.
+    Unreadable,
+    MissingTitle,
+    MissingAuthor,
+    /// The title is just the filename, as some converters write.
+    TitleIsFilename,
+    /// The author is a placeholder such as `Unknown` or `calibre`.
+    PlaceholderAuthor,
+    /// The ISBN has a bad check digit.
+    InvalidIsbn,
+    /// The year is after the current one.
+    FutureYear,
+    /// The description contains HTML tags or entities.
+    HtmlDescription,
+    MissingLanguage,
+}
+
+impl Rule {
+    const ALL: [Self; 9] = [
+        Self::Unreadable,
+        Self::MissingTitle,
+        Self::MissingAuthor,
+        Self::TitleIsFilename,
+        Self::PlaceholderAuthor,
+        Self::InvalidIsbn,
+        Self::FutureYear,
+        Self::HtmlDescription,
+        Self::MissingLanguage,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Unreadable => "unreadable",
+            Self::MissingTitle => "missing-title",
+            Self::MissingAuthor => "missing-author",
+            Self::TitleIsFilename => "title-is-filename",
+            Self::PlaceholderAuthor => "placeholder-author",
+            Self::InvalidIsbn => "invalid-isbn",
+            Self::FutureYear => "future-year",
+            Self::HtmlDescription => "html-description",
+            Self::MissingLanguage => "missing-language",
+        }
+    }
+
+    /// How serious a violation is unless configured otherwise.
+    fn default_severity(self) -> Severity {
+        match self {
+            Self::Unreadable | Self::MissingTitle | Self::InvalidIsbn => Severity::Error,
+            _ => Severity::Warning,
+        }
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl Serialize for Rule {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+impl ValueEnum for Rule {
+    fn value_variants<'a>() -> &'a [Self] {
+        &Self::ALL
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(PossibleValue::new(self.name()))
+    }
+}
+
+/// How serious a rule violation is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// The rule is not checked.
+    Off,
+    Warning,
+    /// Makes `lint` exit with a non-zero status.
+    Error,
+}
+
+impl Severity {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::Warning => "warning",
+            Self::Error => "error",
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl Serialize for Severity {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+impl ValueEnum for Severity {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Self::Off, Self::Warning, Self::Error]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(PossibleValue::new(self.name()))
+    }
+}
+
+/// Parse a `<rule>=<severity>` setting, e.g. `missing-language=off`.
+///
+/// # Errors
+///
+/// Returns [`LintError::Setting`] if there is no `=`, and [`LintError::Unknown`] if the rule
+/// or severity does not exist.
+pub fn parse_setting(input: &str) -> Result<(Rule, Severity), LintError> {
+    let (rule, severity) = input
+        .split_once('=')
+        .ok_or_else(|| LintError::Setting(input.to_string()))?;
+    let unknown = |kind, name: &str| LintError::Unknown {
+        kind,
+        name: name.trim().to_string(),
+    };
+    let rule = Rule::from_str(rule.trim(), true).map_err(|_| unknown("rule", rule))?;
+    let severity =
+        Severity::from_str(severity.trim(), true).map_err(|_| unknown("severity", severity))?;
+    Ok((rule, severity))
+}
+
+/// The severity of each [`Rule`].
+#[derive(Debug, Clone)]
+pub struct Rules {
+    severities: HashMap<Rule, Severity>,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self {
+            severities: Rule::ALL
+                .into_iter()
+                .map(|rule| (rule, rule.default_severity()))
+                .collect(),
+        }
+    }
+}
+
+impl Rules {
+    /// The default rules with `settings` applied in order, so later ones win.
+    pub fn new(settings: impl IntoIterator<Item = (Rule, Severity)>) -> Self {
+        let mut rules = Self::default();
+        rules.severities.extend(settings);
+        rules
+    }
+
+    fn severity(&self, rule: Rule) -> Severity {
+        self.severities
+            .get(&rule)
+            .copied()
+            .unwrap_or_else(|| rule.default_severity())
+    }
+}
+
+/// One rule a file breaks.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Violation {
+    pub rule: Rule,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// Authors that stand in for a missing one, compared ignoring case.
+const PLACEHOLDER_AUTHORS: &[&str] = &[
+    "unknown",
+    "unknown author",
+    "calibre",
+    "author",
+    "admin",
+    "administrator",
+    "user",
+    "n/a",
+    "none",
+];
+
+/// An HTML tag such as `<p>` or `</b>`, or an entity such as `&amp;`.
+static HTML: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"</?[A-Za-z][A-Za-z0-9]*(\s[^<>]*)?/?>|&(#[0-9]+|#x[0-9A-Fa-f]+|[A-Za-z]+);")
+        .expect("valid regex")
+});
+
+/// Check the file at `path`, whose metadata is `tags`, against `rules`.
+///
+/// Years after `this_year` count as in the future.
+pub fn check(path: &Path, tags: &Tags, rules: &Rules, this_year: u16) -> Vec<Violation> {
+    let value = |key: &str| {
+        tags.get(key)
+            .and_then(Option::as_deref)
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+    };
+    let title = value("Title");
+    let author = value("Author");
+
+    let mut found = Vec::new();
+    match title {
+        None => found.push((Rule::MissingTitle, "The title is missing".to_string())),
+        Some(title) if is_filename(title, path) => found.push((
+            Rule::TitleIsFilename,
+            format!("The title {title:?} is just the filename"),
+        )),
+        Some(_) => {}
+    }
+    match author {
+        None => found.push((Rule::MissingAuthor, "The author is missing".to_string())),
+        Some(author)
+            if PLACEHOLDER_AUTHORS
+                .iter()
+                .any(|p| author.eq_ignore_ascii_case(p)) =>
+        {
+            found.push((
+                Rule::PlaceholderAuthor,
+                format!("The author {author:?} is a placeholder"),
+            ));
+        }
+        Some(_) => {}
+    }
+    if let Some(Identifier::InvalidIsbn(isbn)) = value("Identifier").and_then(identifier::parse) {
+        found.push((
+            Rule::InvalidIsbn,
+            format!("The ISBN {isbn} has a bad check digit"),
+        ));
+    }
+    if let Some(year) = value("Year").and_then(|y| y.parse::<u16>().ok()) {
+        if year > this_year {
+            found.push((
+                Rule::FutureYear,
+                format!("The year {year} is in the future"),
+            ));
+        }
+    }
+    if value("Description").is_some_and(|d| HTML.is_match(d)) {
+        found.push((
+            Rule::HtmlDescription,
+            "The description contains raw HTML".to_string(),
+        ));
+    }
+    if value("Language").is_none() {
+        found.push((Rule::MissingLanguage, "The language is missing".to_string()));
+    }
+
+    found
+        .into_iter()
+        .map(|(rule, message)| Violation {
+            rule,
+            severity: rules.severity(rule),
+            message,
+        })
+        .filter(|v| v.severity != Severity::Off)
+        .collect()
+}
+
+/// The violation for a file that could not be read, or `None` if that rule is off.
+pub fn unreadable(err: &anyhow::Error, rules: &Rules) -> Option<Violation> {
+    let severity = rules.severity(Rule::Unreadable);
+    (severity != Severity::Off).then(|| Violation {
+        rule: Rule::Unreadable,
+        severity,
+        message: format!("{err:#}"),
+    })
+}
+
+/// `true` if `title` is the name of the file at `path`, give or take case, the
+/// extension and the separators used in place of spaces.
+fn is_filename(title: &str, path: &Path) -> bool {
+    let words = |s: &str| {
+        s.split(|c: char| c.is_whitespace() || matches!(c, '_' | '-' | '.'))
+            .filter(|w| !w.is_empty())
+            .map(str::to_lowercase)
+            .collect::<Vec<_>>()
+    };
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy())
+        .unwrap_or_default();
+    let stem = path
+        .file_stem()
+        .map(|n| n.to_string_lossy())
+        .unwrap_or_default();
+    let title = words(title);
+    !title.is_empty() && (title == words(&name) || title == words(&stem))
+}
+
+/// The violations found in one file.
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub path: PathBuf,
+    pub violations: Vec<Violation>,
+}
+
+/// Write the `reports` of files with violations to `out` in `format`.
+///
+/// # Errors
+///
+/// Returns `Err` if writing to `out` fails.
+pub fn write<W: Write>(reports: &[Report], format: OutputFormat, mut out: W) -> io::Result<()> {
+    let reports = reports.iter().filter(|r| !r.violations.is_empty());
+    match format {
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut out, &reports.collect::<Vec<_>>())?;
+            writeln!(out)
+        }
+        OutputFormat::Text => {
+            for report in reports {
+                for v in &report.violations {
+                    writeln!(
+                        out,
+                        "{}: {}: {} [{}]",
+                        report.path.display(),
+                        v.severity,
+                        v.message,
+                        v.rule
+                    )?;
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(pairs: &[(&str, &str)]) -> Tags {
+        pairs
+            .iter()
+            .map(|(k, v)| ((*k).to_string(), Some((*v).to_string())))
+            .collect()
+    }
+
+    fn good() -> Tags {
+        tags(&[
+            ("Title", "Dune"),
+            ("Author", "Frank Herbert"),
+            ("Identifier", "urn:isbn:9780306406157"),
+            ("Year", "1965"),
+            ("Description", "Set on the desert planet Arrakis."),
+            ("Language", "en"),
+        ])
+    }
+
+    fn rules_broken(tags: &Tags) -> Vec<Rule> {
+        check(Path::new("0001.epub"), tags, &Rules::default(), 2024)
+            .into_iter()
+            .map(|v| v.rule)
+            .collect()
+    }
+
+    // ── check ────────────────────────────────────────────────────────────────
+
+    #[test]
+    fn good_metadata_breaks_no_rules() {
+        assert_eq!(rules_broken(&good()), []);
+    }
+
+    #[test]
+    fn missing_fields_are_reported() {
+        let mut tags = good();
+        tags.insert("Title".to_string(), None);
+        tags.insert("Author".to_string(), Some("  ".to_string()));
+        tags.remove("Language");
+        assert_eq!(
+            rules_broken(&tags),
+            [
+                Rule::MissingTitle,
+                Rule::MissingAuthor,
+                Rule::MissingLanguage
+            ]
+        );
+    }
+
+    #[test]
+    fn title_equal_to_the_filename_is_reported() {
+        let mut tags = good();
+        let path = Path::new("books/My_Book-v2.epub");
+        for title in ["My Book v2", "my_book-v2.epub", "MY BOOK V2"] {
+            tags.insert("Title".to_string(), Some(title.to_string()));
+            let found = check(path, &tags, &Rules::default(), 2024);
+            assert_eq!(found[0].rule, Rule::TitleIsFilename, "{title}");
+        }
+        tags.insert("Title".to_string(), Some("My Book".to_string()));
+        assert_eq!(check(path, &tags, &Rules::default(), 2024), []);
+    }
+
+    #[test]
+    fn placeholder_authors_are_reported() {
+        let mut tags = good();
+        for author in ["Unknown", "calibre", "UNKNOWN AUTHOR"] {
+            tags.insert("Author".to_string(), Some(author.to_string()));
+            assert_eq!(rules_broken(&tags), [Rule::PlaceholderAuthor], "{author}");
+        }
+    }
+
+    #[test]
+    fn invalid_isbn_future_year_and_html_are_reported() {
+        let mut tags = good();
+        tags.insert(
+            "Identifier".to_string(),
+            Some("978-0-306-40615-8".to_string()),
+        );
+        tags.insert("Year".to_string(), Some("2025".to_string()));
+        tags.insert(
+            "Description".to_string(),
+            Some("<p>Arrakis</p>".to_string()),
+        );
+        assert_eq!(
+            rules_broken(&tags),
+            [Rule::InvalidIsbn, Rule::FutureYear, Rule::HtmlDescription]
+        );
+    }
+
+    #[test]
+    fn html_is_told_apart_from_plain_text() {
+        for text in ["a &amp; b", "<br/>", "<a href=\"x\">x</a>", "&#8217;"] {
+            assert!(HTML.is_match(text), "{text}");
+        }
+        for text in ["1 < 2 and 3 > 2", "Tom & Jerry", "left <-> right"] {
+            assert!(!HTML.is_match(text), "{text}");
+        }
+    }
+
+    // ── rules ────────────────────────────────────────────────────────────────
+
+    #[test]
+    fn severities_can_be_changed_and_rules_turned_off() {
+        let mut tags = good();
+        tags.remove("Language");
+        tags.insert("Author".to_string(), None);
+        let rules = Rules::new([
+            (Rule::MissingAuthor, Severity::Error),
+            (Rule::MissingLanguage, Severity::Off),
+        ]);
+        let found = check(Path::new("0001.epub"), &tags, &rules, 2024);
+        assert_eq!(
+            found,
+            [Violation {
+                rule: Rule::MissingAuthor,
+                severity: Severity::Error,
+                message: "The author is missing".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn settings_are_parsed() {
+        assert_eq!(
+            parse_setting("missing-author=error").expect("valid"),
+            (Rule::MissingAuthor, Severity::Error)
+        );
+        assert_eq!(
+            parse_setting(" Future-Year = OFF ").expect("valid"),
+            (Rule::FutureYear, Severity::Off)
+        );
+        assert!(matches!(
+            parse_setting("missing-author"),
+            Err(LintError::Setting(_))
+        ));
+        assert!(matches!(
+            parse_setting("no-such-rule=error"),
+            Err(LintError::Unknown { kind: "rule", .. })
+        ));
+        assert!(matches!(
+            parse_setting("missing-author=fatal"),
+            Err(LintError::Unknown {
+                kind: "severity",
+                ..
+            })
+        ));
+    }
+
+    // ── write ────────────────────────────────────────────────────────────────
+
+    fn reports() -> Vec<Report> {
+        let violation = Violation {
+            rule: Rule::MissingTitle,
+            severity: Severity::Error,
+            message: "The title is missing".to_string(),
+        };
+        vec![
+            Report {
+                path: PathBuf::from("a.epub"),
+                violations: vec![violation],
+            },
+            Report {
+                path: PathBuf::from("b.epub"),
+                violations: vec![],
+            },
+        ]
+    }
+
+    #[test]
+    fn text_report_has_a_line_per_violation() {
+        let mut out = Vec::new();
+        write(&reports(), OutputFormat::Text, &mut out).expect("write");
+        assert_eq!(
+            String::from_utf8(out).expect("utf-8"),
+            "a.epub: error: The title is missing [missing-title]\n"
+        );
+    }
+
+    #[test]
+    fn json_report_lists_files_with_violations() {
+        let mut out = Vec::new();
+        write(&reports(), OutputFormat::Json, &mut out).expect("write");
+        let json: serde_json::Value = serde_json::from_slice(&out).expect("json");
+        assert_eq!(
+            json,
+            serde_json::json!([{
+                "path": "a.epub",
+                "violations": [{
+                    "rule": "missing-title",
+                    "severity": "error",
+                    "message": "The title is missing",
+                }],
+            }])
+        );
+    }
+}
@@ -1,19 +1,42 @@
 use anyhow::Context as _;
-use clap::parser::ValueSource;
-use std::collections::HashMap;
+use clap::{ArgMatches, parser::ValueSource};
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 // Logging
 use env_logger::{Builder, Target};
 use log::LevelFilter;
 
 // Document handling
+mod cache;
+mod calibre;
 mod cli;
+mod cover;
+mod date;
+mod dupes;
 mod epub;
+mod extract;
+mod filter;
+mod identifier;
+mod interactive;
+mod journal;
+mod language;
+mod lint;
 mod mobi;
 mod pdf;
+mod plan;
 mod rename_file;
+mod sanitise;
+mod text;
+mod toc;
 mod utils;
+mod verify;
 mod walker;
+mod watch;
 
 // Useful stuff
 
@@ -31,7 +54,12 @@ fn run() -> anyhow::Result<()> {
     let quiet = cli_args.get_flag("quiet");
     let detail_off = cli_args.get_flag("detail-off");
     let recursive = cli_args.get_flag("recursive");
+    let interactive = cli_args.get_flag("interactive");
+    let jobs = cli_args.get_one::<usize>("jobs").copied().unwrap_or(1);
+    let filter = cli_args.get_one::<filter::Filter>("where");
     let rename_present = cli_args.value_source("rename-pattern") == Some(ValueSource::CommandLine);
+    let on_conflict = on_conflict(&cli_args);
+    let sanitiser = sanitiser(&cli_args);
 
     // Figure out what log level to use.
     if quiet {
@@ -47,38 +75,93 @@ fn run() -> anyhow::Result<()> {
     // Initialize logging
     logbuilder.target(Target::Stdout).init();
 
+    if let Some(("undo", undo_args)) = cli_args.subcommand() {
+        return undo(undo_args);
+    }
+    if let Some(("watch", watch_args)) = cli_args.subcommand() {
+        return watch(watch_args);
+    }
+    if let Some(("dupes", dupes_args)) = cli_args.subcommand() {
+        return dupes(dupes_args);
+    }
+    if let Some(("lint", lint_args)) = cli_args.subcommand() {
+        return lint(lint_args);
+    }
+    if let Some(("cover", cover_args)) = cli_args.subcommand() {
+        return cover(cover_args);
+    }
+    if let Some(("toc", toc_args)) = cli_args.subcommand() {
+        return toc(toc_args);
+    }
+    if let Some(("verify", verify_args)) = cli_args.subcommand() {
+        return verify(verify_args);
+    }
+    if let Some(("cache", cache_args)) = cli_args.subcommand() {
+        return cache(cache_args);
+    }
+
     let inputs = cli_args
-        .get_many::<String>("read")
+        .get_many::<PathBuf>("read")
         .unwrap_or_default()
+        .cloned()
         .collect::<Vec<_>>();
-    let files = walker::collect_files(&inputs, recursive);
+    let listed = match cli_args.get_one::<PathBuf>("files-from") {
+        Some(list) => file_list(list, cli_args.get_flag("null"), interactive)?,
+        None => Vec::new(),
+    };
+    let sources = calibre_sources(&cli_args)?;
+    if rename_present {
+        sources.allow_rename(dry_run)?;
+    }
+    let files = input_files(
+        &inputs,
+        &listed,
+        recursive,
+        &walk_options(&cli_args)?,
+        &sources,
+    );
 
     // Initialize variables
-    let mut tags;
-
-    // Do the work
-    for filename in &files {
-        log::debug!("Processing filename {filename}");
-        let ext = utils::get_extension(filename);
-
-        tags = if ext.eq_ignore_ascii_case("pdf") {
-            log::info!("Processing PDF: {filename}");
-            pdf::get_metadata(filename)
-                .with_context(|| format!("failed to read PDF: {filename}"))?
-        } else if ext.eq_ignore_ascii_case("epub") {
-            log::info!("Processing EPUB: {filename}");
-            epub::get_metadata(filename)
-                .with_context(|| format!("failed to read EPUB: {filename}"))?
-        } else if ext.eq_ignore_ascii_case("mobi") {
-            log::info!("Processing MOBI: {filename}");
-            mobi::get_metadata(filename)
-                .with_context(|| format!("failed to read MOBI: {filename}"))?
-        } else {
-            log::warn!("Unknown file type: {filename}");
-            HashMap::new()
-        };
+    let mut renames = Vec::new();
+    let mut rename_tags = HashMap::new();
+    let mut journal = if rename_present && !dry_run {
+        open_journal()
+    } else {
+        None
+    };
+
+    let cache = open_cache(&cli_args);
+    // Counting words means reading all of each book's text, so only do it when asked.
+    let pattern = cli_args
+        .get_one::<String>("rename-pattern")
+        .filter(|_| rename_present);
+    let stats = cli_args.get_flag("stats")
+        || text::is_requested(filter.iter().flat_map(|filter| filter.fields()))
+        || text::is_requested(pattern.into_iter().flat_map(|p| rename_file::fields(p)));
+
+    // Do the work. Extraction may run in parallel; everything after it runs in file order.
+    let extracted = extract::all(&files, jobs, cache.as_ref(), &sources, stats)?;
+    if let Some(cache) = &cache {
+        if let Err(err) = cache.save() {
+            log::warn!("{err}");
+        }
+    }
+    for (filename, tags) in files.iter().zip(extracted) {
+        let name = filename.display();
+        log::debug!("Processing filename {name}");
+        match extract::Format::of(filename) {
+            Some(format) => log::info!("Processing {}: {name}", format.name()),
+            None => log::warn!("Unknown file type: {name}"),
+        }
+        let tags = tags?;
 
         log::debug!("tags: {tags:?}");
+        if let Some(filter) = &filter {
+            if !filter.matches(filename, &tags) {
+                log::debug!("{name} does not match --where. Skipping.");
+                continue;
+            }
+        }
         if !detail_off && !quiet {
             utils::print_metadata(&tags);
         }
@@ -88,18 +171,624 @@ fn run() -> anyhow::Result<()> {
             let pattern = cli_args
                 .get_one::<String>("rename-pattern")
                 .unwrap_or(&empty_str);
-            let res = rename_file::rename_file(filename, &tags, pattern, dry_run)
-                .with_context(|| format!("failed to rename: {filename}"))?;
-            if !quiet {
-                log::info!("{filename} --> {res}");
+            let target = rename_file::new_path(filename, &tags, pattern, &sanitiser)
+                .with_context(|| format!("failed to rename: {name}"))?;
+            renames.push((filename.clone(), target));
+            if interactive {
+                rename_tags.insert(filename.clone(), tags.clone());
             }
         }
     }
 
+    // Plan the whole batch before touching the disk, so files in it cannot collide
+    if rename_present {
+        let mut plan = plan::build(&renames, on_conflict, sanitiser.max_bytes)
+            .context("failed to plan renames")?;
+        if interactive && !plan.moves.is_empty() {
+            let accepted = interactive::review(
+                &plan.moves,
+                &rename_tags,
+                &sanitiser,
+                std::io::stdin().lock(),
+                std::io::stdout().lock(),
+            )?;
+            // Edited names may clash with each other, so plan the accepted renames again.
+            plan = plan::build(&accepted, on_conflict, sanitiser.max_bytes)
+                .context("failed to plan renames")?;
+        }
+        if dry_run && plan.uses_temporaries() && !quiet {
+            log::info!("Renames will be carried out in this order:");
+            for step in &plan.steps {
+                let note = if step.temporary { " (temporary)" } else { "" };
+                log::info!("  {} --> {}{note}", step.from.display(), step.to.display());
+            }
+        }
+        let executed = plan.execute(dry_run, journal.as_mut());
+        if !dry_run {
+            cache_renamed(cache.as_ref(), &plan.moves);
+        }
+        executed.context("failed to rename")?;
+        if !quiet {
+            for m in &plan.moves {
+                log::info!("{} --> {}", m.from.display(), m.to.display());
+            }
+        }
+    }
+
+    if let Some(journal) = journal.filter(journal::Journal::is_written) {
+        log::info!(
+            "Rename journal written to {} (use `undo` to reverse)",
+            journal.path().display()
+        );
+    }
+
     // Everything is a-okay in the end
     Ok(())
 } // fn run()
 
+//////////////////////////////////////////////////////////////////////////////////////////////////////////////
+/// The conflict policy given by `--on-conflict` in `args`.
+fn on_conflict(args: &ArgMatches) -> rename_file::ConflictPolicy {
+    args.get_one::<rename_file::ConflictPolicy>("on-conflict")
+        .copied()
+        .unwrap_or_default()
+}
+
+/// The filename rules given by `--fs-profile` and `--max-name-bytes` in `args`.
+fn sanitiser(args: &ArgMatches) -> sanitise::Sanitiser {
+    sanitise::Sanitiser {
+        profile: args
+            .get_one::<sanitise::FsProfile>("fs-profile")
+            .copied()
+            .unwrap_or_default(),
+        max_bytes: args
+            .get_one::<u16>("max-name-bytes")
+            .map_or(sanitise::DEFAULT_MAX_BYTES, |&n| usize::from(n)),
+    }
+}
+
+/// Load the metadata cache, unless `--no-cache` is given in `args`.
+fn open_cache(args: &ArgMatches) -> Option<cache::Cache> {
+    if args.get_flag("no-cache") {
+        return None;
+    }
+    let cache = cache::default_path().map(cache::Cache::load);
+    if cache.is_none() {
+        log::debug!("No cache directory found. Metadata will not be cached.");
+    }
+    cache
+}
+
+/// The Calibre metadata to merge, given by `--sidecar` and `--calibre-library` in `args`.
+fn calibre_sources(args: &ArgMatches) -> anyhow::Result<calibre::Sources> {
+    let library = args
+        .get_one::<PathBuf>("calibre-library")
+        .map(|root| calibre::Library::open(root))
+        .transpose()?;
+    Ok(calibre::Sources {
+        sidecars: args.get_flag("sidecar"),
+        library,
+    })
+}
+
+/// The files found from `inputs` and then from the `listed` paths of `--files-from`, or with
+/// neither, every book in the Calibre library of `sources` with one of the extensions in
+/// `options`.
+fn input_files(
+    inputs: &[PathBuf],
+    listed: &[PathBuf],
+    recursive: bool,
+    options: &walker::WalkOptions,
+    sources: &calibre::Sources,
+) -> Vec<PathBuf> {
+    match &sources.library {
+        Some(library) if inputs.is_empty() && listed.is_empty() => {
+            library.files(options.extensions)
+        }
+        _ => {
+            let mut files = walker::collect_files(inputs, recursive, options);
+            files.extend(walker::collect_listed_files(listed, recursive, options));
+            files
+        }
+    }
+}
+
+/// Prepare a new rename journal in the data directory.
+fn open_journal() -> Option<journal::Journal> {
+    let journal = journal::default_dir().map(journal::Journal::create);
+    if journal.is_none() {
+        log::warn!("No data directory found. Renames will not be journaled.");
+    }
+    journal
+}
+
+/// Keep the cache in step with whatever was moved, even if a later move failed.
+fn cache_renamed(cache: Option<&cache::Cache>, moves: &[plan::Move]) {
+    let Some(cache) = cache else { return };
+    for m in moves.iter().filter(|m| !m.from.exists() && m.to.exists()) {
+        cache.renamed(&m.from, &m.to);
+    }
+    if let Err(err) = cache.save() {
+        log::warn!("{err}");
+    }
+}
+
+/// Read the `--files-from` list at `path`, or from stdin if `path` is `-`.
+fn file_list(path: &Path, nul_separated: bool, interactive: bool) -> anyhow::Result<Vec<PathBuf>> {
+    if path == Path::new("-") {
+        // Interactive answers are read from stdin too, so it cannot also carry the list.
+        if interactive {
+            anyhow::bail!("--files-from - cannot be combined with --interactive");
+        }
+        return walker::read_file_list(std::io::stdin().lock(), nul_separated)
+            .context("failed to read the file list from stdin");
+    }
+    let file = std::fs::File::open(path)
+        .with_context(|| format!("failed to open file list: {}", path.display()))?;
+    walker::read_file_list(std::io::BufReader::new(file), nul_separated)
+        .with_context(|| format!("failed to read file list: {}", path.display()))
+}
+
+/// Build the [`walker::WalkOptions`] from the walk arguments in `args`.
+fn walk_options(args: &ArgMatches) -> anyhow::Result<walker::WalkOptions> {
+    let globs = |id: &str| -> anyhow::Result<Option<globset::GlobSet>> {
+        let patterns = args
+            .get_many::<String>(id)
+            .unwrap_or_default()
+            .collect::<Vec<_>>();
+        walker::glob_set(&patterns).map_err(|e| anyhow::anyhow!("invalid --{id} pattern: {e}"))
+    };
+    Ok(walker::WalkOptions {
+        include: globs("include")?,
+        exclude: globs("exclude")?,
+        min_depth: args.get_one::<usize>("min-depth").copied(),
+        max_depth: args.get_one::<usize>("max-depth").copied(),
+        skip_hidden: args.get_flag("skip-hidden"),
+        follow_links: !args.get_flag("no-follow-links"),
+        ignore_files: !args.get_flag("no-ignore-files"),
+        ..walker::WalkOptions::default()
+    })
+}
+
+/// Reverse the renames recorded in the journal given on the command line, or the latest one.
+fn undo(args: &ArgMatches) -> anyhow::Result<()> {
+    let dry_run = args.get_flag("dry-run");
+    let path = match args.get_one::<PathBuf>("journal") {
+        Some(path) => path.clone(),
+        None => {
+            let dir =
+                journal::default_dir().context("no data directory to look for journals in")?;
+            journal::latest(dir)?
+        }
+    };
+    log::info!("Undoing renames from {}", path.display());
+
+    let summary = journal::undo(&path, dry_run)?;
+    log::info!(
+        "{} restored, {} already restored, {} skipped",
+        summary.restored,
+        summary.already_restored,
+        summary.skipped
+    );
+    if summary.skipped > 0 {
+        anyhow::bail!(
+            "{} rename(s) could not be undone; {} was kept",
+            summary.skipped,
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Rename files as they arrive in the directory given to the `watch` subcommand.
+fn watch(args: &ArgMatches) -> anyhow::Result<()> {
+    let dir = args
+        .get_one::<PathBuf>("dir")
+        .context("no directory to watch")?;
+    let pattern = args
+        .get_one::<String>("rename-pattern")
+        .context("no rename pattern")?;
+    let settle = Duration::from_secs(args.get_one::<u64>("settle").copied().unwrap_or(5));
+    let dry_run = args.get_flag("dry-run");
+    let on_conflict = on_conflict(args);
+    let sanitiser = sanitiser(args);
+    let cache = open_cache(args);
+    let mut journal = if dry_run { None } else { open_journal() };
+    if !dir.is_dir() {
+        anyhow::bail!("not a directory: {}", dir.display());
+    }
+    if let Some(journal) = &journal {
+        log::info!("Renames are journaled to {}", journal.path().display());
+    }
+
+    let stats = text::is_requested(rename_file::fields(pattern));
+
+    watch::watch(dir, args.get_flag("recursive"), settle, |files| {
+        // Leave alone what a .docmetaignore marks off-limits.
+        let files = walker::collect_files(&files, false, &walker::WalkOptions::default());
+        // A bad file or a failed rename must not end the watch, so errors are only logged.
+        let mut renames = Vec::new();
+        for (file, tags) in files.iter().zip(extract::all(
+            &files,
+            1,
+            cache.as_ref(),
+            &calibre::Sources::default(),
+            stats,
+        )?) {
+            log::info!("Processing {}", file.display());
+            let target =
+                tags.and_then(|tags| Ok(rename_file::new_path(file, &tags, pattern, &sanitiser)?));
+            match target {
+                Ok(target) => renames.push((file.clone(), target)),
+                Err(err) => log::error!("failed to rename {}: {err:#}", file.display()),
+            }
+        }
+        let plan = match plan::build(&renames, on_conflict, sanitiser.max_bytes) {
+            Ok(plan) => plan,
+            Err(err) => {
+                log::error!("failed to plan renames: {err}");
+                return Ok(Vec::new());
+            }
+        };
+
+        let executed = plan.execute(dry_run, journal.as_mut());
+        if dry_run {
+            for m in &plan.moves {
+                log::info!("{} --> {}", m.from.display(), m.to.display());
+            }
+            return Ok(Vec::new());
+        }
+        cache_renamed(cache.as_ref(), &plan.moves);
+        if let Err(err) = executed {
+            log::error!("failed to rename: {err}");
+        }
+        // Report what was actually moved, and have the watcher ignore the new names.
+        Ok(plan
+            .moves
+            .iter()
+            .filter(|m| !m.from.exists() && m.to.exists())
+            .map(|m| {
+                log::info!("{} --> {}", m.from.display(), m.to.display());
+                m.to.clone()
+            })
+            .collect())
+    })
+}
+
+/// List probable duplicates among the files given to the `dupes` subcommand.
+fn dupes(args: &ArgMatches) -> anyhow::Result<()> {
+    let inputs = args
+        .get_many::<PathBuf>("read")
+        .unwrap_or_default()
+        .cloned()
+        .collect::<Vec<_>>();
+    let sources = calibre_sources(args)?;
+    let files = input_files(
+        &inputs,
+        &[],
+        args.get_flag("recursive"),
+        &walk_options(args)?,
+        &sources,
+    );
+    let jobs = args.get_one::<usize>("jobs").copied().unwrap_or(1);
+    let cache = open_cache(args);
+
+    let extracted = extract::all(&files, jobs, cache.as_ref(), &sources, false)?;
+    if let Some(cache) = &cache {
+        if let Err(err) = cache.save() {
+            log::warn!("{err}");
+        }
+    }
+    let mut books = Vec::with_capacity(files.len());
+    for (file, tags) in files.into_iter().zip(extracted) {
+        // One unreadable file should not hide the duplicates among the rest.
+        let size = std::fs::metadata(&file).map(|m| m.len());
+        match (tags, size) {
+            (Ok(tags), Ok(size)) => books.push(dupes::Book::new(file, size, &tags)),
+            (Err(err), _) => log::warn!("Skipping: {err:#}"),
+            (_, Err(err)) => log::warn!("Skipping {}: {err}", file.display()),
+        }
+    }
+
+    let by = args
+        .get_many::<dupes::Match>("by")
+        .unwrap_or_default()
+        .copied()
+        .collect::<Vec<_>>();
+    let prefer = args
+        .get_many::<extract::Format>("prefer")
+        .unwrap_or_default()
+        .copied()
+        .collect::<Vec<_>>();
+    let similarity = f64::from(args.get_one::<u8>("similarity").copied().unwrap_or(90)) / 100.0;
+    let groups = dupes::find(&books, &by, similarity, &prefer);
+
+    let format = args
+        .get_one::<utils::OutputFormat>("format")
+        .copied()
+        .unwrap_or_default();
+    dupes::write(&groups, &books, format, std::io::stdout().lock())
+        .context("failed to write the report")?;
+    if format == utils::OutputFormat::Text {
+        log::info!(
+            "{} group(s) of possible duplicates among {} file(s)",
+            groups.len(),
+            books.len()
+        );
+    }
+    Ok(())
+}
+
+/// Run the `lint` subcommand in `args`, failing if any file breaks a rule whose severity is
+/// `error`.
+fn lint(args: &ArgMatches) -> anyhow::Result<()> {
+    let inputs = args
+        .get_many::<PathBuf>("read")
+        .unwrap_or_default()
+        .cloned()
+        .collect::<Vec<_>>();
+    let sources = calibre_sources(args)?;
+    let files = input_files(
+        &inputs,
+        &[],
+        args.get_flag("recursive"),
+        &walk_options(args)?,
+        &sources,
+    );
+    let jobs = args.get_one::<usize>("jobs").copied().unwrap_or(1);
+    let cache = open_cache(args);
+    let rules = lint::Rules::new(
+        args.get_many::<(lint::Rule, lint::Severity)>("rule")
+            .unwrap_or_default()
+            .copied(),
+    );
+
+    let extracted = extract::all(&files, jobs, cache.as_ref(), &sources, false)?;
+    if let Some(cache) = &cache {
+        if let Err(err) = cache.save() {
+            log::warn!("{err}");
+        }
+    }
+    let this_year = date::this_year();
+    let reports = files
+        .into_iter()
+        .zip(extracted)
+        .map(|(path, tags)| {
+            let violations = match tags {
+                Ok(tags) => lint::check(&path, &tags, &rules, this_year),
+                Err(err) => lint::unreadable(&err, &rules).into_iter().collect(),
+            };
+            lint::Report { path, violations }
+        })
+        .collect::<Vec<_>>();
+
+    let format = args
+        .get_one::<utils::OutputFormat>("format")
+        .copied()
+        .unwrap_or_default();
+    lint::write(&reports, format, std::io::stdout().lock())
+        .context("failed to write the report")?;
+
+    let count = |severity| {
+        reports
+            .iter()
+            .flat_map(|r| &r.violations)
+            .filter(|v| v.severity == severity)
+            .count()
+    };
+    let (errors, warnings) = (count(lint::Severity::Error), count(lint::Severity::Warning));
+    if format == utils::OutputFormat::Text {
+        log::info!(
+            "{errors} error(s) and {warnings} warning(s) in {} file(s)",
+            reports.len()
+        );
+    }
+    if errors > 0 {
+        anyhow::bail!("{errors} lint error(s) found");
+    }
+    Ok(())
+}
+
+/// Run the `verify` subcommand in `args`.
+fn verify(args: &ArgMatches) -> anyhow::Result<()> {
+    let inputs = args
+        .get_many::<PathBuf>("read")
+        .unwrap_or_default()
+        .cloned()
+        .collect::<Vec<_>>();
+    let options = walker::WalkOptions {
+        extensions: verify::EXTENSIONS,
+        ..walk_options(args)?
+    };
+    let files = walker::collect_files(&inputs, args.get_flag("recursive"), &options);
+    let reports = files
+        .into_iter()
+        .map(|path| {
+            let problems = verify::check(&path);
+            verify::Report { path, problems }
+        })
+        .collect::<Vec<_>>();
+
+    let format = args
+        .get_one::<utils::OutputFormat>("format")
+        .copied()
+        .unwrap_or_default();
+    verify::write(&reports, format, std::io::stdout().lock())
+        .context("failed to write the report")?;
+
+    let broken = reports.iter().filter(|r| !r.problems.is_empty()).count();
+    if format == utils::OutputFormat::Text {
+        log::info!("{broken} of {} file(s) have problems", reports.len());
+    }
+    if broken > 0 {
+        anyhow::bail!("{broken} file(s) with structural problems found");
+    }
+    Ok(())
+}
+
+/// Run the `toc` subcommand in `args`. Files whose table of contents can't be read are
+/// logged and skipped.
+fn toc(args: &ArgMatches) -> anyhow::Result<()> {
+    let inputs = args
+        .get_many::<PathBuf>("read")
+        .unwrap_or_default()
+        .cloned()
+        .collect::<Vec<_>>();
+    let files = walker::collect_files(&inputs, args.get_flag("recursive"), &walk_options(args)?);
+    let tocs = files
+        .into_iter()
+        .filter_map(|path| match toc::read(&path) {
+            Ok(entries) => Some(toc::Toc { path, entries }),
+            Err(err) => {
+                log::warn!("{}: {err}", path.display());
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let format = args
+        .get_one::<utils::OutputFormat>("format")
+        .copied()
+        .unwrap_or_default();
+    toc::write(&tocs, format, std::io::stdout().lock())
+        .context("failed to write the tables of contents")
+}
+
+/// Run the `cover` subcommand in `args`. Files without a cover, or whose cover can't be
+/// read, are logged and skipped.
+fn cover(args: &ArgMatches) -> anyhow::Result<()> {
+    let inputs = args
+        .get_many::<PathBuf>("read")
+        .unwrap_or_default()
+        .cloned()
+        .collect::<Vec<_>>();
+    let options = walker::WalkOptions {
+        extensions: cover::EXTENSIONS,
+        ..walk_options(args)?
+    };
+    let sources = calibre_sources(args)?;
+    let files = input_files(&inputs, &[], args.get_flag("recursive"), &options, &sources);
+    let jobs = args.get_one::<usize>("jobs").copied().unwrap_or(1);
+    let out = args
+        .get_one::<PathBuf>("out")
+        .context("--out is required")?;
+    let pattern = args
+        .get_one::<String>("name")
+        .map_or("{stem}", String::as_str);
+    let pdf = args
+        .get_one::<cover::PdfCover>("pdf")
+        .copied()
+        .unwrap_or_default();
+    let dry_run = args.get_flag("dry-run");
+    let force = args.get_flag("force");
+    let cache = open_cache(args);
+
+    // The metadata is only needed for the name; a file that can't be read still gets
+    // its cover named after `{stem}`, or fails below when its cover is read.
+    let extracted = extract::all(
+        &files,
+        jobs,
+        cache.as_ref(),
+        &sources,
+        text::is_requested(rename_file::fields(pattern)),
+    )?;
+    if let Some(cache) = &cache {
+        if let Err(err) = cache.save() {
+            log::warn!("{err}");
+        }
+    }
+    if !dry_run {
+        std::fs::create_dir_all(out)
+            .with_context(|| format!("unable to create {}", out.display()))?;
+    }
+
+    let sanitiser = sanitise::Sanitiser::default();
+    let mut taken = HashSet::new();
+    let (mut saved, mut missing) = (0, 0);
+    for (file, tags) in files.iter().zip(extracted) {
+        let is_pdf = extract::Format::of(file) == Some(extract::Format::Pdf);
+        if is_pdf && pdf == cover::PdfCover::None {
+            log::info!(
+                "Skipping {}: PDFs have no cover unless --pdf first-image is given",
+                file.display()
+            );
+            continue;
+        }
+        let image = match cover::extract(file, pdf) {
+            Ok(Some(image)) => image,
+            Ok(None) => {
+                log::warn!("{}: no cover found", file.display());
+                missing += 1;
+                continue;
+            }
+            Err(err) => {
+                log::warn!("Skipping {}: {err:#}", file.display());
+                continue;
+            }
+        };
+
+        let mut tags = tags.unwrap_or_else(|err| {
+            log::debug!("No metadata for the cover's name: {err:#}");
+            extract::Tags::new()
+        });
+        let stem = file.file_stem().unwrap_or_default().to_string_lossy();
+        tags.insert("Stem".to_string(), Some(stem.into_owned()));
+        let name = sanitiser.file_stem(&rename_file::expand(pattern, &tags), image.extension);
+        if name.is_empty() {
+            log::warn!("Skipping {}: the cover's name is empty", file.display());
+            continue;
+        }
+        let target = rename_file::target_path(out, OsStr::new(&name), OsStr::new(image.extension));
+        if !taken.insert(target.clone()) {
+            log::warn!(
+                "Skipping {}: {} is taken by another cover",
+                file.display(),
+                target.display()
+            );
+            continue;
+        }
+        if target.exists() && !force {
+            log::info!(
+                "Skipping {}: {} already exists (use --force to replace it)",
+                file.display(),
+                target.display()
+            );
+            continue;
+        }
+
+        log::info!("{} --> {}", file.display(), target.display());
+        if !dry_run {
+            std::fs::write(&target, &image.data)
+                .with_context(|| format!("unable to write {}", target.display()))?;
+        }
+        saved += 1;
+    }
+    log::info!("{saved} cover(s) saved, {missing} file(s) without a cover");
+    Ok(())
+}
+
+/// Run the `cache` subcommand in `args`.
+fn cache(args: &ArgMatches) -> anyhow::Result<()> {
+    let path = cache::default_path().context("no cache directory found")?;
+    match args.subcommand() {
+        Some(("clear", _)) => {
+            if cache::clear(&path)? {
+                log::info!("Removed metadata cache {}", path.display());
+            } else {
+                log::info!("No metadata cache to remove");
+            }
+        }
+        Some(("prune", _)) => {
+            let cache = cache::Cache::load(&path);
+            let pruned = cache.prune();
+            cache.save()?;
+            log::info!("Removed {pruned} missing file(s) from the metadata cache");
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
 //////////////////////////////////////////////////////////////////////////////////////////////////////////////
 /// The actual executable function that gets called when the program in invoked.
 fn main() {
@@ -1,6 +1,30 @@
-use crate::utils;
-use mobi::Mobi;
-use std::collections::HashMap;
+use crate::{
+    cover, date,
+    extract::NO_DRM,
+    text::{self, Content},
+    toc::Entry,
+};
+use mobi::{
+    Mobi,
+    headers::{Encryption, Language},
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+};
+
+/// The EXTH record holding the book's language.
+const EXTH_LANGUAGE: u32 = 524;
+
+/// A record number meaning there is no such record.
+const NO_RECORD: u32 = u32::MAX;
+
+/// NCX index tags: the text position an entry points at, the offset of its label in the
+/// CNCX records, its depth (from 0), and, in KF8 books, the fragment and offset it points at.
+const TAG_POSITION: u8 = 1;
+const TAG_LABEL: u8 = 3;
+const TAG_DEPTH: u8 = 4;
+const TAG_FRAGMENT: u8 = 6;
 
 /// Read metadata from a MOBI file and return it as a [`HashMap`].
 ///
@@ -21,12 +45,14 @@ use std::collections::HashMap;
 /// | `"Publisher"` | Publisher name, or `None` if absent |
 /// | `"Identifier"` | ISBN, or `None` if absent |
 /// | `"Date"` | Publish date string, or `None` if absent |
+/// | `"Language"` | EXTH language record, else the header's locale as a BCP 47 tag, or `None` if neutral |
 /// | `"Year"` | Four-digit year extracted from `Date`, or `None` if absent |
+/// | `"DRM"` | `Mobipocket` if the header's encryption type is set, otherwise `None` |
 ///
 /// # Errors
 ///
 /// Returns `Err` if the file cannot be opened or parsed as a MOBI document.
-pub fn get_metadata(filename: &str) -> anyhow::Result<HashMap<String, Option<String>>> {
+pub fn get_metadata(filename: &Path) -> anyhow::Result<HashMap<String, Option<String>>> {
     let mobi_file = Mobi::from_path(filename)?;
     log::debug!("metadata = {:?}", mobi_file.metadata);
 
@@ -38,14 +64,20 @@ pub fn get_metadata(filename: &str) -> anyhow::Result<HashMap<String, Option<Str
     metadata_map.insert("Publisher".to_string(), mobi_file.publisher());
     metadata_map.insert("Identifier".to_string(), mobi_file.isbn());
     metadata_map.insert("Date".to_string(), mobi_file.publish_date());
+    metadata_map.insert("Language".to_string(), language(&mobi_file));
 
     // Extract year from the date string and store it alongside
     let year = metadata_map
         .get("Date")
         .and_then(Option::as_deref)
-        .map(utils::get_year)
-        .filter(|y| !y.is_empty());
+        .and_then(date::parse)
+        .map(|d| d.year.to_string());
     metadata_map.insert("Year".to_string(), year);
+    let drm = match mobi_file.metadata.encryption() {
+        Encryption::No => NO_DRM,
+        Encryption::OldMobiPocket | Encryption::MobiPocket => "Mobipocket",
+    };
+    metadata_map.insert("DRM".to_string(), Some(drm.to_string()));
 
     log::debug!("metadata_map = {metadata_map:?}");
 
@@ -53,17 +85,507 @@ pub fn get_metadata(filename: &str) -> anyhow::Result<HashMap<String, Option<Str
     Ok(metadata_map)
 }
 
+/// The language of `mobi_file`: the EXTH language record (e.g. `en-us`) if there is one,
+/// otherwise the BCP 47 tag for the header's locale (e.g. `en-GB`).
+fn language(mobi_file: &Mobi) -> Option<String> {
+    let record = mobi_file
+        .metadata
+        .exth
+        .get_record_position(EXTH_LANGUAGE)
+        .and_then(|values| values.first())
+        .map(|value| String::from_utf8_lossy(value).trim().to_string())
+        .filter(|value| !value.is_empty());
+    // The header's locale is a Windows LANGID: the sub-language is in the upper six bits
+    // of the byte before the primary language.
+    record.or_else(|| locale_tag(mobi_file.language(), mobi_file.metadata.mobi.locale >> 2))
+}
+
+/// The BCP 47 tag of a MOBI header locale: the primary `language` and the Windows
+/// `sublanguage` that gives its region. A sub-language without a known region gives the
+/// language alone; a neutral or unknown language gives `None`.
+fn locale_tag(language: Language, sublanguage: u8) -> Option<String> {
+    use Language::*;
+    let code = match language {
+        Afrikaans => "af",
+        Albanian => "sq",
+        Arabic => "ar",
+        Armenian => "hy",
+        Assamese => "as",
+        Azeri => "az",
+        Basque => "eu",
+        Belarusian => "be",
+        Bengali => "bn",
+        Bulgarian => "bg",
+        Catalan => "ca",
+        Chinese => "zh",
+        Czech => "cs",
+        Danish => "da",
+        Dutch => "nl",
+        English => "en",
+        Estonian => "et",
+        Faeroese => "fo",
+        Farsi => "fa",
+        Finnish => "fi",
+        French => "fr",
+        Georgian => "ka",
+        German => "de",
+        Greek => "el",
+        Gujarati => "gu",
+        Hebrew => "he",
+        Hindi => "hi",
+        Hungarian => "hu",
+        Icelandic => "is",
+        Indonesian => "id",
+        Italian => "it",
+        Japanese => "ja",
+        Kannada => "kn",
+        Kazak => "kk",
+        Konkani => "kok",
+        Korean => "ko",
+        Latvian => "lv",
+        Lithuanian => "lt",
+        Macedonian => "mk",
+        Malay => "ms",
+        Malayalam => "ml",
+        Maltese => "mt",
+        Marathi => "mr",
+        Nepali => "ne",
+        Norwegian => match sublanguage {
+            1 => return Some("nb-NO".to_string()),
+            2 => return Some("nn-NO".to_string()),
+            _ => "no",
+        },
+        Oriya => "or",
+        Polish => "pl",
+        Portuguese => "pt",
+        Punjabi => "pa",
+        Rhaetoromanic => "rm",
+        Romanian => "ro",
+        Russian => "ru",
+        Sami => "se",
+        Sanskrit => "sa",
+        Serbian => "sr",
+        Slovak => "sk",
+        Slovenian => "sl",
+        Sorbian => "hsb",
+        Spanish => "es",
+        Sutu => "st",
+        Swahili => "sw",
+        Swedish => "sv",
+        Tamil => "ta",
+        Tatar => "tt",
+        Telugu => "te",
+        Thai => "th",
+        Tsonga => "ts",
+        Tswana => "tn",
+        Turkish => "tr",
+        Ukrainian => "uk",
+        Urdu => "ur",
+        Uzbek => "uz",
+        Vietnamese => "vi",
+        Xhosa => "xh",
+        Zulu => "zu",
+        Neutral | Unknown => return None,
+    };
+    let regions: &[&str] = match language {
+        Arabic => &[
+            "SA", "IQ", "EG", "LY", "DZ", "MA", "TN", "OM", "YE", "SY", "JO", "LB", "KW", "AE",
+            "BH", "QA",
+        ],
+        Chinese => &["TW", "CN", "HK", "SG", "MO"],
+        Dutch => &["NL", "BE"],
+        English => &[
+            "US", "GB", "AU", "CA", "NZ", "IE", "ZA", "JM", "", "BZ", "TT", "ZW", "PH",
+        ],
+        French => &["FR", "BE", "CA", "CH", "LU", "MC"],
+        German => &["DE", "CH", "AT", "LU", "LI"],
+        Italian => &["IT", "CH"],
+        Portuguese => &["BR", "PT"],
+        Spanish => &[
+            "ES", "MX", "ES", "GT", "CR", "PA", "DO", "VE", "CO", "PE", "AR", "EC", "CL", "UY",
+            "PY", "BO", "SV", "HN", "NI", "PR",
+        ],
+        Swedish => &["SE", "FI"],
+        _ => &[],
+    };
+    // Sub-languages count from 1; 0 is the language in general.
+    let region = usize::from(sublanguage)
+        .checked_sub(1)
+        .and_then(|i| regions.get(i))
+        .filter(|region| !region.is_empty());
+    Some(match region {
+        Some(region) => format!("{code}-{region}"),
+        None => code.to_string(),
+    })
+}
+
+/// Read the table of contents of a MOBI file from its NCX index.
+///
+/// Targets are `filepos <n>` positions in the text, or `fid <n> offset <n>` for KF8 books.
+/// A book without an NCX index has an empty table of contents.
+///
+/// # Errors
+///
+/// Returns `Err` if the file cannot be opened or parsed as a MOBI document, or its NCX
+/// index is malformed.
+pub fn toc(filename: &Path) -> anyhow::Result<Vec<Entry>> {
+    let mobi_file = Mobi::from_path(filename)?;
+    let first = mobi_file.metadata.mobi.first_index_record;
+    if first == NO_RECORD || first == 0 {
+        return Ok(Vec::new());
+    }
+    let raw_records = mobi_file.raw_records();
+    let records = raw_records
+        .records()
+        .iter()
+        .map(|record| record.content)
+        .collect::<Vec<_>>();
+    ncx_entries(&records, first as usize)
+        .ok_or_else(|| anyhow::anyhow!("{}: malformed NCX index", filename.display()))
+}
+
+/// Extract the text of a MOBI file from its text records, and count its images.
+///
+/// # Errors
+///
+/// Returns `Err` if the file cannot be opened or parsed as a MOBI document.
+pub fn text(filename: &Path) -> anyhow::Result<Content> {
+    let mobi_file = Mobi::from_path(filename)?;
+    let first_image = mobi_file.metadata.mobi.first_image_index;
+    let images = if first_image == NO_RECORD {
+        0
+    } else {
+        let raw_records = mobi_file.raw_records();
+        raw_records
+            .records()
+            .iter()
+            .skip(first_image as usize)
+            .filter(|record| cover::sniff(record.content).is_some())
+            .count()
+    };
+    Ok(Content {
+        text: text::html_to_text(&mobi_file.content_as_string_lossy()),
+        images,
+    })
+}
+
+/// One entry of a tag table: the tag, how many values it has per entry, the bit mask
+/// selecting it in the control byte, and whether it ends a control byte.
+struct TagDefinition {
+    tag: u8,
+    values_per_entry: usize,
+    mask: u8,
+    end_of_control_byte: bool,
+}
+
+/// The NCX entries in the index starting at `records[first]`, or `None` if the index is
+/// malformed.
+///
+/// The first `INDX` record holds the tag table (`TAGX`) and the number of records that
+/// follow it with the entries. After those come the `CNCX` records holding the labels.
+fn ncx_entries(records: &[&[u8]], first: usize) -> Option<Vec<Entry>> {
+    let header = records.get(first)?;
+    if !header.starts_with(b"INDX") {
+        return None;
+    }
+    let header_length = be_u32(header, 4)?;
+    let entry_records = be_u32(header, 24)?;
+    let cncx_records = be_u32(header, 52)?;
+    let (control_bytes, tags) = tag_table(header.get(header_length..)?)?;
+
+    let mut labels = BTreeMap::new();
+    for n in 0..cncx_records {
+        let cncx = records.get(first + 1 + entry_records + n)?;
+        labels.extend(
+            strings(cncx)
+                .into_iter()
+                .map(|(offset, label)| (n * 0x10000 + offset, label)),
+        );
+    }
+
+    let mut entries = Vec::new();
+    for record in records.get(first + 1..=first + entry_records)? {
+        for (start, end) in entry_offsets(record)? {
+            let entry = record.get(start..end)?;
+            let ident_length = usize::from(*entry.first()?);
+            let tag_data = entry.get(1 + ident_length..)?;
+            let values = tag_values(tag_data, control_bytes, &tags)?;
+            let first_value = |tag| values.get(&tag).and_then(|v| v.first()).copied();
+
+            let label = first_value(TAG_LABEL)
+                .and_then(|offset| labels.get(&offset).cloned())
+                .unwrap_or_default();
+            let target = match (
+                values.get(&TAG_FRAGMENT).map(Vec::as_slice),
+                first_value(TAG_POSITION),
+            ) {
+                (Some([fid, offset, ..]), _) => Some(format!("fid {fid} offset {offset}")),
+                (_, Some(position)) => Some(format!("filepos {position}")),
+                _ => None,
+            };
+            entries.push(Entry {
+                depth: first_value(TAG_DEPTH).unwrap_or(0) + 1,
+                label,
+                target,
+            });
+        }
+    }
+    Some(entries)
+}
+
+/// The number of control bytes and the tag definitions in a `TAGX` section.
+fn tag_table(tagx: &[u8]) -> Option<(usize, Vec<TagDefinition>)> {
+    if !tagx.starts_with(b"TAGX") {
+        return None;
+    }
+    let length = be_u32(tagx, 4)?;
+    let control_bytes = be_u32(tagx, 8)?;
+    let tags = tagx
+        .get(12..length)?
+        .chunks_exact(4)
+        .map(|t| TagDefinition {
+            tag: t[0],
+            values_per_entry: usize::from(t[1]),
+            mask: t[2],
+            end_of_control_byte: t[3] & 1 == 1,
+        })
+        .collect();
+    Some((control_bytes, tags))
+}
+
+/// The start and end of each entry in an `INDX` record, from its `IDXT` table.
+fn entry_offsets(record: &[u8]) -> Option<Vec<(usize, usize)>> {
+    let idxt = be_u32(record, 20)?;
+    let count = be_u32(record, 24)?;
+    if record.get(idxt..idxt + 4)? != b"IDXT" {
+        return None;
+    }
+    let starts = (0..count)
+        .map(|n| be_u16(record, idxt + 4 + 2 * n))
+        .collect::<Option<Vec<_>>>()?;
+    let ends = starts.iter().skip(1).copied().chain([idxt]);
+    Some(starts.iter().copied().zip(ends).collect())
+}
+
+/// The values of each tag in an entry's `data`, which starts with its control bytes.
+fn tag_values(
+    data: &[u8],
+    control_bytes: usize,
+    tags: &[TagDefinition],
+) -> Option<HashMap<u8, Vec<usize>>> {
+    let control = data.get(..control_bytes)?;
+    let mut rest = data.get(control_bytes..)?;
+    let mut control_index = 0;
+
+    // First work out how many values, or bytes of values, each tag has.
+    let mut counts = Vec::new();
+    for definition in tags {
+        if definition.end_of_control_byte {
+            control_index += 1;
+            continue;
+        }
+        let value = control.get(control_index)? & definition.mask;
+        if value == 0 {
+            continue;
+        }
+        if value == definition.mask && definition.mask.count_ones() > 1 {
+            // All the mask's bits set: a byte count for the values follows.
+            let (bytes, used) = variable_width(rest)?;
+            rest = rest.get(used..)?;
+            counts.push((definition, None, Some(bytes)));
+        } else {
+            let count = usize::from(value >> definition.mask.trailing_zeros());
+            counts.push((definition, Some(count * definition.values_per_entry), None));
+        }
+    }
+
+    let mut values = HashMap::new();
+    for (definition, count, bytes) in counts {
+        let mut tag_values = Vec::new();
+        match (count, bytes) {
+            (Some(count), _) => {
+                for _ in 0..count {
+                    let (value, used) = variable_width(rest)?;
+                    rest = rest.get(used..)?;
+                    tag_values.push(value);
+                }
+            }
+            (None, Some(mut bytes)) => {
+                while bytes > 0 {
+                    let (value, used) = variable_width(rest)?;
+                    rest = rest.get(used..)?;
+                    bytes = bytes.checked_sub(used)?;
+                    tag_values.push(value);
+                }
+            }
+            (None, None) => {}
+        }
+        values.insert(definition.tag, tag_values);
+    }
+    Some(values)
+}
+
+/// The strings in a `CNCX` record, by their offset in it. Each is a variable-width length
+/// followed by that many bytes of text.
+fn strings(cncx: &[u8]) -> Vec<(usize, String)> {
+    let mut strings = Vec::new();
+    let mut offset = 0;
+    while let Some(&byte) = cncx.get(offset) {
+        if byte == 0 {
+            break;
+        }
+        let Some((length, used)) = variable_width(&cncx[offset..]) else {
+            break;
+        };
+        let Some(text) = cncx.get(offset + used..offset + used + length) else {
+            break;
+        };
+        strings.push((offset, String::from_utf8_lossy(text).into_owned()));
+        offset += used + length;
+    }
+    strings
+}
+
+/// A forward-encoded variable-width integer at the start of `data` and the number of bytes
+/// it takes: seven bits per byte, most significant first, with the top bit set on the last.
+fn variable_width(data: &[u8]) -> Option<(usize, usize)> {
+    let mut value = 0usize;
+    for (n, &byte) in data.iter().enumerate().take(5) {
+        value = (value << 7) | usize::from(byte & 0x7F);
+        if byte & 0x80 != 0 {
+            return Some((value, n + 1));
+        }
+    }
+    None
+}
+
+/// The big-endian `u32` at `offset` in `data`, as a `usize`.
+fn be_u32(data: &[u8], offset: usize) -> Option<usize> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes(bytes.try_into().ok()?) as usize)
+}
+
+/// The big-endian `u16` at `offset` in `data`, as a `usize`.
+fn be_u16(data: &[u8], offset: usize) -> Option<usize> {
+    let bytes = data.get(offset..offset + 2)?;
+    Some(usize::from(u16::from_be_bytes(bytes.try_into().ok()?)))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn get_metadata_includes_year_key() {
-        let map = get_metadata("tests/fixtures/Mastering.mobi").expect("should parse");
+        let map = get_metadata(Path::new("tests/fixtures/Mastering.mobi")).expect("should parse");
         assert_eq!(
             map.get("Year").and_then(Option::as_deref),
             Some("2019"),
             "unexpected Year value"
         );
     }
+
+    // ── language ─────────────────────────────────────────────────────────────
+
+    #[test]
+    fn header_locales_become_bcp47_tags() {
+        assert_eq!(locale_tag(Language::English, 1).as_deref(), Some("en-US"));
+        assert_eq!(locale_tag(Language::English, 2).as_deref(), Some("en-GB"));
+        assert_eq!(locale_tag(Language::German, 3).as_deref(), Some("de-AT"));
+        assert_eq!(locale_tag(Language::Spanish, 2).as_deref(), Some("es-MX"));
+        assert_eq!(locale_tag(Language::Norwegian, 2).as_deref(), Some("nn-NO"));
+        assert_eq!(locale_tag(Language::French, 0).as_deref(), Some("fr"));
+        assert_eq!(locale_tag(Language::Russian, 1).as_deref(), Some("ru"));
+        assert_eq!(locale_tag(Language::English, 40).as_deref(), Some("en"));
+    }
+
+    #[test]
+    fn neutral_and_unknown_locales_have_no_tag() {
+        assert_eq!(locale_tag(Language::Neutral, 0), None);
+        assert_eq!(locale_tag(Language::Unknown, 1), None);
+    }
+
+    // ── table of contents ────────────────────────────────────────────────────
+
+    /// `value` as a forward-encoded variable-width integer.
+    fn encode(value: usize) -> Vec<u8> {
+        let mut bytes = vec![(value & 0x7F) as u8 | 0x80];
+        let mut rest = value >> 7;
+        while rest > 0 {
+            bytes.insert(0, (rest & 0x7F) as u8);
+            rest >>= 7;
+        }
+        bytes
+    }
+
+    /// An `INDX` header with `idxt` at offset 20 and `count` at 24, plus `rest`.
+    fn indx(fields: &[(usize, u32)], rest: &[u8]) -> Vec<u8> {
+        let mut record = vec![0; 56];
+        record[..4].copy_from_slice(b"INDX");
+        record[4..8].copy_from_slice(&56u32.to_be_bytes());
+        for &(offset, value) in fields {
+            record[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+        }
+        record.extend_from_slice(rest);
+        record
+    }
+
+    #[test]
+    fn variable_width_reads_until_the_high_bit() {
+        assert_eq!(variable_width(&[0x85]), Some((5, 1)));
+        assert_eq!(variable_width(&encode(1234)), Some((1234, 2)));
+        assert_eq!(variable_width(&[0x01, 0x02]), None);
+    }
+
+    #[test]
+    fn ncx_entries_read_labels_depths_and_targets() {
+        // Tags: position, label, depth, and fragment (two values), then end of control byte.
+        let mut tagx = b"TAGX".to_vec();
+        tagx.extend_from_slice(&32u32.to_be_bytes());
+        tagx.extend_from_slice(&1u32.to_be_bytes());
+        tagx.extend_from_slice(&[1, 1, 0x01, 0, 3, 1, 0x02, 0, 4, 1, 0x04, 0, 6, 2, 0x08, 0]);
+        tagx.extend_from_slice(&[0, 0, 0, 1]);
+        let header = indx(&[(24, 1), (52, 1)], &tagx);
+
+        let first = [vec![1, b'0', 0x07], encode(1234), encode(0), encode(0)].concat();
+        let label_offset = 1 + "Part One".len();
+        let second = [
+            vec![1, b'1', 0x0E],
+            encode(label_offset),
+            encode(1),
+            encode(3),
+            encode(40),
+        ]
+        .concat();
+        let idxt = 56 + first.len() + second.len();
+        let mut body = [first.as_slice(), second.as_slice(), b"IDXT"].concat();
+        body.extend_from_slice(&56u16.to_be_bytes());
+        body.extend_from_slice(&((56 + first.len()) as u16).to_be_bytes());
+        let entries = indx(&[(20, idxt as u32), (24, 2)], &body);
+
+        let cncx = [b"\x88Part One".as_slice(), b"\x89Chapter 1"].concat();
+        let records = [b"text".as_slice(), &header, &entries, &cncx];
+
+        assert_eq!(
+            ncx_entries(&records, 1),
+            Some(vec![
+                Entry {
+                    depth: 1,
+                    label: "Part One".to_string(),
+                    target: Some("filepos 1234".to_string()),
+                },
+                Entry {
+                    depth: 2,
+                    label: "Chapter 1".to_string(),
+                    target: Some("fid 3 offset 40".to_string()),
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn ncx_entries_reject_a_record_that_is_not_an_index() {
+        let records = [b"text".as_slice(), b"not an index"];
+        assert_eq!(ncx_entries(&records, 1), None);
+    }
 }
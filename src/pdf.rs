@@ -1,5 +1,13 @@
-use pdf::primitive::PdfString;
-use std::collections::HashMap;
+use crate::{text::Content, toc::Entry};
+use pdf::{
+    content::{Op, TextDrawAdjusted},
+    object::{MaybeNamedDest, OutlineItem, PlainRef, Ref, Resolve, XObject},
+    primitive::{PdfString, Primitive},
+};
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
 
 /// Errors that can occur when reading PDF metadata.
 #[derive(Debug, thiserror::Error)]
@@ -8,8 +16,8 @@ pub enum PdfMetaError {
     #[error(transparent)]
     Pdf(#[from] pdf::PdfError),
     /// The PDF contains no info dictionary.
-    #[error("No info dictionary found in {0}")]
-    NoInfoDict(String),
+    #[error("No info dictionary found in {}", .0.display())]
+    NoInfoDict(PathBuf),
 }
 
 /// Convert an optional [`PdfString`] reference to an `Option<String>`.
@@ -21,6 +29,13 @@ fn pdf_string_to_string(s: Option<&PdfString>) -> Option<String> {
         .map(|v| v.replace('\"', ""))
 }
 
+/// The `/Lang` entry of the document catalog, which the `pdf` crate does not parse itself.
+fn language(resolver: &impl Resolve, catalog: PlainRef) -> Option<String> {
+    let catalog = resolver.resolve(catalog).ok()?.into_dictionary().ok()?;
+    let lang = catalog.get("Lang")?.as_string().ok()?.to_string_lossy();
+    Some(lang).filter(|lang| !lang.trim().is_empty())
+}
+
 /// Extract a named field from a PDF info dictionary into `$mm`.
 macro_rules! get_field {
     ($id:ident, $field:ident, $mm:ident, $key:literal) => {
@@ -47,7 +62,9 @@ macro_rules! get_field {
 /// | `"Keywords"` | `info.keywords` |
 /// | `"Creator"` | `info.creator` |
 /// | `"Producer"` | `info.producer` |
+/// | `"Date"` | `info.creation_date`, as `YYYY-MM-DD` |
 /// | `"Year"` | `info.creation_date.year` |
+/// | `"Language"` | `/Lang` in the document catalog |
 ///
 /// # Errors
 ///
@@ -55,12 +72,12 @@ macro_rules! get_field {
 /// - The `pdf` crate cannot open or parse the file (corrupt data, unsupported version,
 ///   permission denied, etc.) — the underlying crate error is propagated.
 /// - The PDF contains no info dictionary — returns [`PdfMetaError::NoInfoDict`] carrying `filename`.
-pub fn get_metadata(filename: &str) -> Result<HashMap<String, Option<String>>, PdfMetaError> {
-    log::debug!("Opening file: {filename}");
+pub fn get_metadata(filename: &Path) -> Result<HashMap<String, Option<String>>, PdfMetaError> {
+    log::debug!("Opening file: {}", filename.display());
 
     let file = pdf::file::FileOptions::cached().open(filename)?;
     let Some(info) = file.trailer.info_dict.as_ref() else {
-        return Err(PdfMetaError::NoInfoDict(filename.to_owned()));
+        return Err(PdfMetaError::NoInfoDict(filename.to_path_buf()));
     };
 
     let mut metadata_map: HashMap<String, Option<String>> = HashMap::new();
@@ -72,9 +89,15 @@ pub fn get_metadata(filename: &str) -> Result<HashMap<String, Option<String>>, P
     get_field!(info, creator, metadata_map, "Creator");
     get_field!(info, producer, metadata_map, "Producer");
 
+    let date = info.creation_date.as_ref();
+    metadata_map.insert(
+        "Date".to_string(),
+        date.map(|d| format!("{:04}-{:02}-{:02}", d.year, d.month, d.day)),
+    );
+    metadata_map.insert("Year".to_string(), date.map(|d| d.year.to_string()));
     metadata_map.insert(
-        "Year".to_string(),
-        info.creation_date.as_ref().map(|d| d.year.to_string()),
+        "Language".to_string(),
+        language(&file, file.trailer.root.get_ref().get_inner()),
     );
 
     log::debug!("metadata_map: {metadata_map:?}");
@@ -82,6 +105,146 @@ pub fn get_metadata(filename: &str) -> Result<HashMap<String, Option<String>>, P
     Ok(metadata_map)
 }
 
+/// `TJ` spacing, in thousandths of a text space unit, wide enough to stand for a space
+/// between words.
+const WORD_GAP: f32 = -200.0;
+
+/// Extract the text of a PDF file from its pages' content streams, and count the distinct
+/// images they use.
+///
+/// This is approximate: strings are decoded as UTF-16 if they start with a byte order mark
+/// and as Latin-1 otherwise, ignoring font encodings, and words are split where the text
+/// moves or `TJ` leaves a gap.
+///
+/// # Errors
+///
+/// Returns `Err` if the `pdf` crate cannot open or parse the file or a page's content.
+pub fn text(filename: &Path) -> Result<Content, PdfMetaError> {
+    let file = pdf::file::FileOptions::cached().open(filename)?;
+    let mut text = String::new();
+    let mut images = HashSet::new();
+    let mut inline_images = 0;
+    for page in file.pages() {
+        let page = page?;
+        if let Ok(resources) = page.resources() {
+            for &xobject in resources.xobjects.values() {
+                if let XObject::Image(_) = &*file.get(xobject)? {
+                    images.insert(xobject.get_inner());
+                }
+            }
+        }
+        let Some(contents) = &page.contents else {
+            continue;
+        };
+        for op in contents.operations(&file)? {
+            match op {
+                Op::TextDraw { text: string } => text.push_str(&decode(&string)),
+                Op::TextDrawAdjusted { array } => {
+                    for part in array {
+                        match part {
+                            TextDrawAdjusted::Text(string) => text.push_str(&decode(&string)),
+                            TextDrawAdjusted::Spacing(gap) if gap < WORD_GAP => text.push(' '),
+                            TextDrawAdjusted::Spacing(_) => {}
+                        }
+                    }
+                }
+                Op::BeginText
+                | Op::EndText
+                | Op::MoveTextPosition { .. }
+                | Op::SetTextMatrix { .. }
+                | Op::TextNewline => text.push(' '),
+                Op::InlineImage { .. } => inline_images += 1,
+                _ => {}
+            }
+        }
+        text.push('\n');
+    }
+    Ok(Content {
+        text,
+        images: images.len() + inline_images,
+    })
+}
+
+/// The text of a string drawn on a page: UTF-16 if it starts with a byte order mark,
+/// otherwise Latin-1.
+fn decode(string: &PdfString) -> String {
+    if string.as_bytes().starts_with(&[0xFE, 0xFF]) {
+        string.to_string_lossy()
+    } else {
+        string.as_bytes().iter().map(|&b| char::from(b)).collect()
+    }
+}
+
+/// Read the outline (bookmarks) of a PDF file as a table of contents.
+///
+/// Targets are `page <n>` for entries that point at a page, or the destination name or
+/// URI for those that don't. A PDF without an outline has an empty table of contents.
+///
+/// # Errors
+///
+/// Returns `Err` if the `pdf` crate cannot open or parse the file.
+pub fn toc(filename: &Path) -> Result<Vec<Entry>, PdfMetaError> {
+    let file = pdf::file::FileOptions::cached().open(filename)?;
+    let Some(first) = file.get_root().outlines.as_ref().and_then(|o| o.first) else {
+        return Ok(Vec::new());
+    };
+
+    let mut pages = HashMap::new();
+    for (n, page) in file.pages().enumerate() {
+        pages.insert(page?.get_ref(), n + 1);
+    }
+
+    // Depth first, following `/First` down to children and `/Next` across to siblings.
+    let mut entries = Vec::new();
+    let mut seen = HashSet::new();
+    let mut stack = vec![(first, 1)];
+    while let Some((item_ref, depth)) = stack.pop() {
+        if !seen.insert(item_ref.get_inner()) {
+            continue;
+        }
+        let item = file.get(item_ref)?;
+        entries.push(Entry {
+            depth,
+            label: item
+                .title
+                .as_ref()
+                .map(PdfString::to_string_lossy)
+                .unwrap_or_default(),
+            target: outline_target(&item, &pages),
+        });
+        if let Some(next) = item.next {
+            stack.push((next, depth));
+        }
+        if let Some(child) = item.first {
+            stack.push((child, depth + 1));
+        }
+    }
+    Ok(entries)
+}
+
+/// Where an outline item points: its destination, or else the destination or URI of its
+/// action.
+fn outline_target(item: &OutlineItem, pages: &HashMap<PlainRef, usize>) -> Option<String> {
+    let page = |page: PlainRef| pages.get(&page).map(|n| format!("page {n}"));
+    if let Some(dest) = &item.dest {
+        return match dest {
+            Primitive::Array(array) => match array.first() {
+                Some(Primitive::Reference(r)) => page(*r),
+                _ => None,
+            },
+            Primitive::Name(name) => Some(name.to_string()),
+            Primitive::String(name) => Some(name.to_string_lossy()),
+            _ => None,
+        };
+    }
+    let action = item.action.as_ref()?;
+    match &action.d {
+        Some(MaybeNamedDest::Direct(dest)) => dest.page.as_ref().map(Ref::get_inner).and_then(page),
+        Some(MaybeNamedDest::Named(name)) => Some(name.to_string_lossy()),
+        None => action.uri.as_ref().map(PdfString::to_string_lossy),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -112,11 +275,47 @@ mod tests {
 
     #[test]
     fn error_is_no_info_dict_variant() {
-        let filename = "tests/fixtures/no-info-dict.pdf";
+        let filename = Path::new("tests/fixtures/no-info-dict.pdf");
         let err = get_metadata(filename).expect_err("expected error for PDF with no info dict");
         assert!(
             matches!(err, PdfMetaError::NoInfoDict(ref f) if f == filename),
-            "expected NoInfoDict(\"{filename}\"), got: {err}"
+            "expected NoInfoDict({}), got: {err}",
+            filename.display()
+        );
+    }
+
+    #[test]
+    fn toc_follows_the_outline_tree() {
+        let entries = toc(Path::new("tests/fixtures/outline.pdf")).expect("should parse");
+        let entry = |depth, label: &str, target: &str| Entry {
+            depth,
+            label: label.to_string(),
+            target: Some(target.to_string()),
+        };
+        assert_eq!(
+            entries,
+            vec![
+                entry(1, "Part One", "page 1"),
+                entry(2, "Chapter 1", "page 2"),
+                entry(1, "Website", "https://example.com/"),
+            ]
+        );
+    }
+
+    #[test]
+    fn toc_is_empty_without_an_outline() {
+        let entries = toc(Path::new("tests/fixtures/no-info-dict.pdf")).expect("should parse");
+        assert!(entries.is_empty());
+    }
+
+    #[test]
+    fn text_splits_words_where_the_text_moves_or_leaves_a_gap() {
+        let content = text(Path::new("tests/fixtures/text.pdf")).expect("should parse");
+        assert_eq!(
+            content.text.split_whitespace().collect::<Vec<_>>(),
+            ["Hello", "world", "again", "Caf\u{e9}", "<3"]
         );
+        // Both pages draw the same image.
+        assert_eq!(content.images, 1);
     }
 }
@@ -0,0 +1,650 @@
+//! Plan a batch of renames before touching the disk.
+//!
+//! Renaming files one at a time lets two books that map to the same name race each
+//! other, and makes swaps such as `A → B, B → A` impossible. [`build`] looks at the whole
+//! batch first: it drops no-op renames, resolves clashes with existing files _and_
+//! between members of the batch using the [`ConflictPolicy`], and orders the moves so
+//! that no file is moved onto a name that is still in use. Cycles are broken by parking
+//! one file under a temporary name. [`Plan::execute`] then carries the moves out.
+use crate::{
+    journal::Journal,
+    rename_file::{self, ConflictPolicy, RenameError},
+    sanitise, utils,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::{OsStr, OsString},
+    path::{Path, PathBuf},
+};
+
+/// Number of hex digits of the content hash used by [`ConflictPolicy::Hash`].
+const SHORT_HASH_LEN: usize = 8;
+
+/// Prefix of the temporary names used to break rename cycles.
+const TEMP_PREFIX: &str = ".docmeta-tmp";
+
+/// A rename as the user sees it: where a file is now and where it will end up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Move {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+/// A single filesystem move, in execution order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+    pub from: PathBuf,
+    pub to: PathBuf,
+    /// `true` if `to` is a temporary name used to break a cycle.
+    pub temporary: bool,
+}
+
+/// The resolved plan for a batch of renames.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Plan {
+    /// The final renames, in the order they were requested.
+    pub moves: Vec<Move>,
+    /// The filesystem moves that carry out [`Self::moves`], in execution order.
+    pub steps: Vec<Step>,
+    /// Files that will not be renamed because of the conflict policy.
+    pub skipped: Vec<PathBuf>,
+}
+
+/// Plan the renames in `requests`, each a `(current path, desired path)` pair as produced
+/// by [`rename_file::new_path`].
+///
+/// - A file whose desired path is its current path is left out.
+/// - A file listed more than once is only planned once.
+/// - A desired path that is taken, either by a file that is not moving or by an earlier
+///   member of the batch, is resolved with `policy`. [`ConflictPolicy::Overwrite`] never
+///   lets one member of the batch replace another; such files are skipped instead.
+/// - A name given a ` (N)` or ` (hash)` suffix by the policy is shortened first, if need
+///   be, so that it stays within `max_bytes`.
+///
+/// # Errors
+///
+/// With [`ConflictPolicy::Fail`], returns [`RenameError::TargetExists`] or
+/// [`RenameError::Collision`] on the first clash. Returns [`RenameError::ReadFailed`] if a
+/// file cannot be read to compare or hash it.
+pub fn build(
+    requests: &[(PathBuf, PathBuf)],
+    policy: ConflictPolicy,
+    max_bytes: usize,
+) -> Result<Plan, RenameError> {
+    let mut seen = HashSet::new();
+    let mut wanted = Vec::with_capacity(requests.len());
+    for (from, to) in requests {
+        if !seen.insert(key(from)) {
+            log::debug!(
+                "{} was listed more than once. Ignoring the repeat.",
+                from.display()
+            );
+        } else if key(from) == key(to) {
+            log::debug!("{} already has the right name.", from.display());
+        } else {
+            wanted.push((from.as_path(), to.as_path()));
+        }
+    }
+
+    // Skipping a file means it no longer vacates its name, which can cause further clashes.
+    // Resolve until the set of files that actually move stops changing.
+    let mut moving: HashSet<PathBuf> = wanted.iter().map(|(from, _)| key(from)).collect();
+    loop {
+        let (moves, skipped) = resolve(&wanted, &moving, policy, max_bytes)?;
+        let now_moving: HashSet<PathBuf> = moves.iter().map(|m| key(&m.from)).collect();
+        if now_moving == moving {
+            for (file, reason) in &skipped {
+                log::warn!("Skipping {}: {reason}", file.display());
+            }
+            let steps = order(&moves);
+            return Ok(Plan {
+                moves,
+                steps,
+                skipped: skipped.into_iter().map(|(file, _)| file).collect(),
+            });
+        }
+        moving = now_moving;
+    }
+}
+
+impl Plan {
+    /// `true` if a cycle had to be broken with a temporary name.
+    pub fn uses_temporaries(&self) -> bool {
+        self.steps.iter().any(|s| s.temporary)
+    }
+
+    /// Carry out the plan's steps in order. In a dry run nothing is moved.
+    ///
+    /// If `journal` is present, every step (including moves to temporary names) is
+    /// recorded with the file's content hash, so an interrupted run can still be undone.
+    ///
+    /// # Errors
+    ///
+    /// Stops at the first step that fails and returns [`RenameError::RenameFailed`],
+    /// [`RenameError::ReadFailed`] or [`RenameError::Journal`]. Steps already carried out
+    /// stay done and are in the journal.
+    pub fn execute(
+        &self,
+        dry_run: bool,
+        mut journal: Option<&mut Journal>,
+    ) -> Result<(), RenameError> {
+        for step in &self.steps {
+            if dry_run {
+                log::debug!("dry_run: {} --> {}", step.from.display(), step.to.display());
+                continue;
+            }
+
+            // Hash before moving so the journal describes exactly what was moved.
+            let hash = journal
+                .as_ref()
+                .map(|_| utils::hash_file(&step.from))
+                .transpose()
+                .map_err(|source| RenameError::ReadFailed {
+                    path: step.from.clone(),
+                    source,
+                })?;
+            match std::fs::rename(&step.from, &step.to) {
+                Ok(()) => log::debug!("{} --> {}", step.from.display(), step.to.display()),
+                Err(source) => {
+                    return Err(RenameError::RenameFailed {
+                        from: step.from.clone(),
+                        to: step.to.clone(),
+                        source,
+                    });
+                }
+            }
+            if let (Some(journal), Some(hash)) = (journal.as_deref_mut(), hash) {
+                // Absolute paths so the journal can be undone from any working directory.
+                journal.record(&key(&step.from), &key(&step.to), hash)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Files skipped by [`resolve`], each with the reason it was skipped.
+type Skipped = Vec<(PathBuf, String)>;
+
+/// Assign a final, unique target to each wanted rename. Returns the moves and the
+/// skipped files.
+///
+/// `moving` holds the files assumed to vacate their current names.
+fn resolve(
+    wanted: &[(&Path, &Path)],
+    moving: &HashSet<PathBuf>,
+    policy: ConflictPolicy,
+    max_bytes: usize,
+) -> Result<(Vec<Move>, Skipped), RenameError> {
+    let mut moves: Vec<Move> = Vec::with_capacity(wanted.len());
+    let mut skipped = Vec::new();
+    // Final target -> the file that claimed it.
+    let mut claimed: HashMap<PathBuf, &Path> = HashMap::new();
+
+    for &(from, to) in wanted {
+        let on_disk = |p: &Path| p.exists() && !moving.contains(&key(p));
+        let is_free = |p: &Path| !claimed.contains_key(&key(p)) && !on_disk(p);
+
+        let target = if is_free(to) {
+            to.to_path_buf()
+        } else {
+            let claimant = claimed.get(&key(to)).copied();
+            match (policy, claimant) {
+                (ConflictPolicy::Fail, Some(first)) => {
+                    return Err(RenameError::Collision {
+                        first: first.to_path_buf(),
+                        second: from.to_path_buf(),
+                        target: to.to_path_buf(),
+                    });
+                }
+                (ConflictPolicy::Fail, None) => {
+                    return Err(RenameError::TargetExists(to.to_path_buf()));
+                }
+                (ConflictPolicy::Skip | ConflictPolicy::Overwrite, Some(first)) => {
+                    skipped.push((
+                        from.to_path_buf(),
+                        format!(
+                            "{} is also being renamed to {}",
+                            first.display(),
+                            to.display()
+                        ),
+                    ));
+                    continue;
+                }
+                (ConflictPolicy::Skip, None) => {
+                    let reason = if same_contents(from, to)? {
+                        format!("{} is an identical copy", to.display())
+                    } else {
+                        format!("{} already exists with different contents", to.display())
+                    };
+                    skipped.push((from.to_path_buf(), reason));
+                    continue;
+                }
+                (ConflictPolicy::Overwrite, None) => {
+                    log::warn!("{} already exists and will be overwritten.", to.display());
+                    to.to_path_buf()
+                }
+                (ConflictPolicy::Number, _) => {
+                    log::debug!("{} is taken. Appending a counter.", to.display());
+                    numbered(from, to, max_bytes, &is_free)
+                }
+                (ConflictPolicy::Hash, _) => {
+                    log::debug!("{} is taken. Appending a content hash.", to.display());
+                    let hash =
+                        utils::hash_file(from).map_err(|source| RenameError::ReadFailed {
+                            path: from.to_path_buf(),
+                            source,
+                        })?;
+                    let hashed = with_suffix(from, to, &hash[..SHORT_HASH_LEN], max_bytes);
+                    if is_free(&hashed) {
+                        hashed
+                    } else {
+                        numbered(from, &hashed, max_bytes, &is_free)
+                    }
+                }
+            }
+        };
+
+        claimed.insert(key(&target), from);
+        moves.push(Move {
+            from: from.to_path_buf(),
+            to: target,
+        });
+    }
+
+    Ok((moves, skipped))
+}
+
+/// Order `moves` so that no file is moved onto a name that another pending move has yet
+/// to vacate, breaking cycles with temporary names.
+fn order(moves: &[Move]) -> Vec<Step> {
+    let mut pending: Vec<Move> = moves.to_vec();
+    let mut occupied: HashSet<PathBuf> = pending.iter().map(|m| key(&m.from)).collect();
+    let mut steps = Vec::with_capacity(moves.len());
+    let mut temp_counter = 0_usize;
+
+    while !pending.is_empty() {
+        let mut waiting = Vec::with_capacity(pending.len());
+        let mut progressed = false;
+        for m in pending.drain(..) {
+            if occupied.contains(&key(&m.to)) {
+                waiting.push(m);
+            } else {
+                occupied.remove(&key(&m.from));
+                steps.push(Step {
+                    from: m.from,
+                    to: m.to,
+                    temporary: false,
+                });
+                progressed = true;
+            }
+        }
+
+        // Nothing could move: every remaining file waits for another one, so they form
+        // one or more cycles. Park the first under a temporary name to break its cycle.
+        if !progressed {
+            let first = &mut waiting[0];
+            let temp = loop {
+                temp_counter += 1;
+                let candidate =
+                    first
+                        .from
+                        .parent()
+                        .unwrap_or_else(|| Path::new("."))
+                        .join(format!(
+                            "{TEMP_PREFIX}-{}-{temp_counter}",
+                            std::process::id()
+                        ));
+                if !candidate.exists() && !occupied.contains(&key(&candidate)) {
+                    break candidate;
+                }
+            };
+            occupied.remove(&key(&first.from));
+            occupied.insert(key(&temp));
+            steps.push(Step {
+                from: std::mem::replace(&mut first.from, temp.clone()),
+                to: temp,
+                temporary: true,
+            });
+        }
+        pending = waiting;
+    }
+
+    steps
+}
+
+/// `to` with ` (suffix)` appended to its stem. The extension is taken from `from`, so
+/// dots inside the stem are never mistaken for one.
+///
+/// The stem is shortened, never splitting a UTF-8 character, so that the new name fits in
+/// `max_bytes`. Stems that are not valid UTF-8 are kept whole.
+fn with_suffix(from: &Path, to: &Path, suffix: &str, max_bytes: usize) -> PathBuf {
+    let ext = from.extension().unwrap_or_default();
+    let parent = to.parent().unwrap_or_else(|| Path::new("."));
+    let stem = if !ext.is_empty() && to.extension() == Some(ext) {
+        to.file_stem()
+    } else {
+        to.file_name()
+    };
+    let suffix = format!(" ({suffix})");
+    let mut stem = stem.map(OsStr::to_os_string).unwrap_or_default();
+    if let Some(utf8) = stem.to_str() {
+        let ext_bytes = if ext.is_empty() { 0 } else { ext.len() + 1 };
+        let budget = max_bytes.saturating_sub(ext_bytes + suffix.len());
+        stem = OsString::from(sanitise::truncate(utf8, budget).trim_end());
+    }
+    stem.push(suffix);
+    rename_file::target_path(parent, &stem, ext)
+}
+
+/// Return the first `stem (N).ext` variant of `to` that `is_free`, starting at 2, with the
+/// stem shortened to fit in `max_bytes`.
+fn numbered(from: &Path, to: &Path, max_bytes: usize, is_free: &dyn Fn(&Path) -> bool) -> PathBuf {
+    (2_u64..)
+        .map(|n| with_suffix(from, to, &n.to_string(), max_bytes))
+        .find(|candidate| is_free(candidate))
+        .unwrap_or_else(|| unreachable!("the counter is unbounded"))
+}
+
+/// Compare two files byte for byte via their content hashes.
+fn same_contents(a: &Path, b: &Path) -> Result<bool, RenameError> {
+    let hash = |p: &Path| {
+        utils::hash_file(p).map_err(|source| RenameError::ReadFailed {
+            path: p.to_path_buf(),
+            source,
+        })
+    };
+    if std::fs::metadata(a).map(|m| m.len()).ok() != std::fs::metadata(b).map(|m| m.len()).ok() {
+        return Ok(false);
+    }
+    Ok(hash(a)? == hash(b)?)
+}
+
+/// The absolute form of `path`, used to compare paths written in different ways.
+fn key<P: AsRef<Path>>(path: P) -> PathBuf {
+    let path = path.as_ref();
+    std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::{TempDir, tempdir};
+
+    /// Create `names` in a fresh directory, each containing its own name.
+    fn fixture(names: &[&str]) -> TempDir {
+        let dir = tempdir().expect("temp dir");
+        for name in names {
+            fs::write(dir.path().join(name), name.as_bytes()).expect("write");
+        }
+        dir
+    }
+
+    /// Build `(from, to)` requests from names relative to `dir`.
+    fn requests(dir: &TempDir, pairs: &[(&str, &str)]) -> Vec<(PathBuf, PathBuf)> {
+        let p = |n: &str| dir.path().join(n);
+        pairs.iter().map(|(f, t)| (p(f), p(t))).collect()
+    }
+
+    fn read(dir: &TempDir, name: &str) -> String {
+        fs::read_to_string(dir.path().join(name)).expect("read")
+    }
+
+    // ── batch ordering ───────────────────────────────────────────────────────
+
+    #[test]
+    fn swap_is_carried_out_through_a_temporary_name() {
+        let dir = fixture(&["a.epub", "b.epub"]);
+        let reqs = requests(&dir, &[("a.epub", "b.epub"), ("b.epub", "a.epub")]);
+
+        let plan = build(&reqs, ConflictPolicy::Fail, sanitise::DEFAULT_MAX_BYTES).expect("plan");
+        assert!(plan.uses_temporaries(), "a swap needs a temporary name");
+        assert_eq!(plan.steps.len(), 3);
+        plan.execute(false, None).expect("execute");
+
+        assert_eq!(read(&dir, "a.epub"), "b.epub");
+        assert_eq!(read(&dir, "b.epub"), "a.epub");
+        assert_eq!(
+            fs::read_dir(dir.path()).expect("ls").count(),
+            2,
+            "temp left behind"
+        );
+    }
+
+    #[test]
+    fn chain_is_ordered_without_temporaries() {
+        // a -> b must wait until b -> c has vacated b.
+        let dir = fixture(&["a.epub", "b.epub"]);
+        let reqs = requests(&dir, &[("a.epub", "b.epub"), ("b.epub", "c.epub")]);
+
+        let plan = build(&reqs, ConflictPolicy::Fail, sanitise::DEFAULT_MAX_BYTES).expect("plan");
+        assert!(!plan.uses_temporaries());
+        assert_eq!(plan.steps[0].to, reqs[1].1, "b -> c must go first");
+        plan.execute(false, None).expect("execute");
+
+        assert_eq!(read(&dir, "b.epub"), "a.epub");
+        assert_eq!(read(&dir, "c.epub"), "b.epub");
+    }
+
+    #[test]
+    fn batch_duplicates_are_numbered() {
+        let dir = fixture(&["a.epub", "b.epub"]);
+        let reqs = requests(&dir, &[("a.epub", "Book.epub"), ("b.epub", "Book.epub")]);
+
+        let plan = build(&reqs, ConflictPolicy::Number, sanitise::DEFAULT_MAX_BYTES).expect("plan");
+        plan.execute(false, None).expect("execute");
+
+        assert_eq!(read(&dir, "Book.epub"), "a.epub");
+        assert_eq!(read(&dir, "Book (2).epub"), "b.epub");
+    }
+
+    #[test]
+    fn suffixes_fit_within_the_name_limit() {
+        // "abcdeéééééé.epub" is 22 bytes, right at the limit.
+        let full = "abcde\u{e9}\u{e9}\u{e9}\u{e9}\u{e9}\u{e9}.epub";
+        let dir = fixture(&["a.epub", full]);
+        let reqs = requests(&dir, &[("a.epub", full)]);
+
+        let plan = build(&reqs, ConflictPolicy::Number, 22).expect("plan");
+        assert_eq!(
+            plan.moves[0].to,
+            dir.path().join("abcde\u{e9}\u{e9}\u{e9}\u{e9} (2).epub")
+        );
+        let plan = build(&reqs, ConflictPolicy::Hash, 22).expect("plan");
+        let name = plan.moves[0]
+            .to
+            .file_name()
+            .expect("name")
+            .to_string_lossy();
+        assert!(name.starts_with("abcde ("), "got {name}");
+        assert_eq!(name.len(), 21);
+    }
+
+    #[test]
+    fn batch_duplicates_fail_with_collision() {
+        let dir = fixture(&["a.epub", "b.epub"]);
+        let reqs = requests(&dir, &[("a.epub", "Book.epub"), ("b.epub", "Book.epub")]);
+
+        let err = build(&reqs, ConflictPolicy::Fail, sanitise::DEFAULT_MAX_BYTES)
+            .expect_err("should fail");
+        assert!(matches!(err, RenameError::Collision { .. }), "got: {err}");
+    }
+
+    #[test]
+    fn overwrite_never_replaces_another_batch_member() {
+        let dir = fixture(&["a.epub", "b.epub"]);
+        let reqs = requests(&dir, &[("a.epub", "Book.epub"), ("b.epub", "Book.epub")]);
+
+        let plan = build(
+            &reqs,
+            ConflictPolicy::Overwrite,
+            sanitise::DEFAULT_MAX_BYTES,
+        )
+        .expect("plan");
+        assert_eq!(plan.moves.len(), 1);
+        assert_eq!(plan.skipped, vec![reqs[1].0.clone()]);
+    }
+
+    #[test]
+    fn skipped_file_keeps_its_name_taken() {
+        // a -> x is skipped because x exists, so b -> a must be skipped too.
+        let dir = fixture(&["a.epub", "b.epub", "x.epub"]);
+        let reqs = requests(&dir, &[("a.epub", "x.epub"), ("b.epub", "a.epub")]);
+
+        let plan = build(&reqs, ConflictPolicy::Skip, sanitise::DEFAULT_MAX_BYTES).expect("plan");
+        assert!(plan.moves.is_empty(), "nothing should move: {plan:?}");
+        assert_eq!(plan.skipped.len(), 2);
+    }
+
+    #[test]
+    fn unchanged_and_repeated_files_are_left_out() {
+        let dir = fixture(&["a.epub"]);
+        let reqs = requests(&dir, &[("a.epub", "a.epub"), ("a.epub", "b.epub")]);
+
+        let plan = build(&reqs, ConflictPolicy::Fail, sanitise::DEFAULT_MAX_BYTES).expect("plan");
+        assert_eq!(plan, Plan::default());
+    }
+
+    // ── conflicts with files outside the batch ───────────────────────────────
+
+    #[test]
+    fn number_policy_appends_first_free_counter() {
+        let dir = fixture(&["source.epub", "Taken.epub", "Taken (2).epub"]);
+        let reqs = requests(&dir, &[("source.epub", "Taken.epub")]);
+
+        let plan = build(&reqs, ConflictPolicy::Number, sanitise::DEFAULT_MAX_BYTES).expect("plan");
+        assert_eq!(plan.moves[0].to, dir.path().join("Taken (3).epub"));
+    }
+
+    #[test]
+    fn hash_policy_appends_short_content_hash() {
+        let dir = fixture(&["source.epub", "Taken.epub"]);
+        let hash = utils::hash_file(dir.path().join("source.epub")).expect("hash");
+        let reqs = requests(&dir, &[("source.epub", "Taken.epub")]);
+
+        let plan = build(&reqs, ConflictPolicy::Hash, sanitise::DEFAULT_MAX_BYTES).expect("plan");
+        let expected = dir
+            .path()
+            .join(format!("Taken ({}).epub", &hash[..SHORT_HASH_LEN]));
+        assert_eq!(plan.moves[0].to, expected);
+    }
+
+    #[test]
+    fn skip_policy_leaves_source_in_place() {
+        let dir = fixture(&["source.epub", "Taken.epub"]);
+        let reqs = requests(&dir, &[("source.epub", "Taken.epub")]);
+
+        let plan = build(&reqs, ConflictPolicy::Skip, sanitise::DEFAULT_MAX_BYTES).expect("plan");
+        assert!(plan.moves.is_empty());
+        assert_eq!(plan.skipped, vec![reqs[0].0.clone()]);
+    }
+
+    #[test]
+    fn overwrite_policy_replaces_existing_file() {
+        let dir = fixture(&["source.epub", "Taken.epub"]);
+        let reqs = requests(&dir, &[("source.epub", "Taken.epub")]);
+
+        build(
+            &reqs,
+            ConflictPolicy::Overwrite,
+            sanitise::DEFAULT_MAX_BYTES,
+        )
+        .expect("plan")
+        .execute(false, None)
+        .expect("execute");
+        assert_eq!(read(&dir, "Taken.epub"), "source.epub");
+        assert!(!dir.path().join("source.epub").exists());
+    }
+
+    #[test]
+    fn fail_policy_returns_target_exists() {
+        let dir = fixture(&["source.epub", "Taken.epub"]);
+        let reqs = requests(&dir, &[("source.epub", "Taken.epub")]);
+
+        let err = build(&reqs, ConflictPolicy::Fail, sanitise::DEFAULT_MAX_BYTES)
+            .expect_err("should fail");
+        assert!(matches!(err, RenameError::TargetExists(_)), "got: {err}");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_names_are_numbered_without_loss() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = fixture(&["source.epub"]);
+        let taken = dir.path().join(OsStr::from_bytes(b"B\xfccher.epub"));
+        if fs::write(&taken, b"taken").is_err() {
+            return; // The filesystem insists on UTF-8 names.
+        }
+        let reqs = vec![(dir.path().join("source.epub"), taken)];
+
+        let plan = build(&reqs, ConflictPolicy::Number, sanitise::DEFAULT_MAX_BYTES).expect("plan");
+        assert_eq!(
+            plan.moves[0].to,
+            dir.path().join(OsStr::from_bytes(b"B\xfccher (2).epub"))
+        );
+    }
+
+    // ── execution ────────────────────────────────────────────────────────────
+
+    #[test]
+    fn dry_run_does_not_rename_file() {
+        let dir = fixture(&["source.epub"]);
+        let reqs = requests(&dir, &[("source.epub", "NewName.epub")]);
+
+        build(&reqs, ConflictPolicy::Number, sanitise::DEFAULT_MAX_BYTES)
+            .expect("plan")
+            .execute(true, None)
+            .expect("execute");
+        assert!(dir.path().join("source.epub").exists(), "source was moved");
+        assert!(
+            !dir.path().join("NewName.epub").exists(),
+            "target was created"
+        );
+    }
+
+    #[test]
+    fn rename_to_nonexistent_parent_returns_rename_failed() {
+        let dir = fixture(&["source.epub"]);
+        let reqs = requests(&dir, &[("source.epub", "missing/Target.epub")]);
+
+        let err = build(&reqs, ConflictPolicy::Number, sanitise::DEFAULT_MAX_BYTES)
+            .expect("plan")
+            .execute(false, None)
+            .expect_err("should fail");
+        assert!(
+            matches!(err, RenameError::RenameFailed { .. }),
+            "got: {err}"
+        );
+    }
+
+    #[test]
+    fn every_step_is_recorded_in_journal() {
+        let dir = fixture(&["a.epub", "b.epub"]);
+        let reqs = requests(&dir, &[("a.epub", "b.epub"), ("b.epub", "a.epub")]);
+        let mut journal = Journal::create(dir.path().join("journal"));
+
+        let plan = build(&reqs, ConflictPolicy::Fail, sanitise::DEFAULT_MAX_BYTES).expect("plan");
+        plan.execute(false, Some(&mut journal)).expect("execute");
+
+        let entries = crate::journal::read(journal.path()).expect("read journal");
+        assert_eq!(entries.len(), plan.steps.len());
+        let summary = crate::journal::undo(journal.path(), false).expect("undo");
+        assert_eq!(summary.skipped, 0);
+        assert_eq!(read(&dir, "a.epub"), "a.epub");
+        assert_eq!(read(&dir, "b.epub"), "b.epub");
+    }
+
+    #[test]
+    fn dry_run_writes_no_journal() {
+        let dir = fixture(&["source.epub"]);
+        let reqs = requests(&dir, &[("source.epub", "NewName.epub")]);
+        let mut journal = Journal::create(dir.path().join("journal"));
+
+        build(&reqs, ConflictPolicy::Number, sanitise::DEFAULT_MAX_BYTES)
+            .expect("plan")
+            .execute(true, Some(&mut journal))
+            .expect("execute");
+        assert!(!journal.is_written(), "dry run must not write a journal");
+    }
+}
@@ -1,10 +1,51 @@
-use crate::utils;
+use crate::sanitise::Sanitiser;
+use clap::ValueEnum;
+use clap::builder::PossibleValue;
 use std::{
     collections::HashMap,
-    path::Path,
-    time::{SystemTime, UNIX_EPOCH},
+    ffi::{OsStr, OsString},
+    path::{Path, PathBuf},
 };
 
+/// What to do when the generated filename is already taken by another file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConflictPolicy {
+    /// Leave the source file where it is. The existing file is compared with the
+    /// source so the log can say whether it is an identical copy.
+    Skip,
+    /// Replace the existing file with the source.
+    Overwrite,
+    /// Append the first free counter, e.g. `Book (2).epub`, `Book (3).epub`.
+    #[default]
+    Number,
+    /// Append a short hash of the source file's contents, e.g. `Book (3fa9c2d1).epub`.
+    Hash,
+    /// Abort with [`RenameError::TargetExists`].
+    Fail,
+}
+
+impl ValueEnum for ConflictPolicy {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[
+            Self::Skip,
+            Self::Overwrite,
+            Self::Number,
+            Self::Hash,
+            Self::Fail,
+        ]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(match self {
+            Self::Skip => PossibleValue::new("skip").help("Leave the file alone"),
+            Self::Overwrite => PossibleValue::new("overwrite").help("Replace the existing file"),
+            Self::Number => PossibleValue::new("number").help("Append (2), (3), ..."),
+            Self::Hash => PossibleValue::new("hash").help("Append a short content hash"),
+            Self::Fail => PossibleValue::new("fail").help("Stop with an error"),
+        })
+    }
+}
+
 /// Errors that can occur when renaming a file.
 #[derive(Debug, thiserror::Error)]
 pub enum RenameError {
@@ -14,174 +55,191 @@ pub enum RenameError {
     /// After tag substitution and sanitisation, the filename stem is empty.
     #[error("No new filename generated")]
     EmptyResult,
+    /// The target already exists and the policy is [`ConflictPolicy::Fail`].
+    #[error("{} already exists", .0.display())]
+    TargetExists(PathBuf),
+    /// Two files in the same batch would get the same name and the policy is
+    /// [`ConflictPolicy::Fail`].
+    #[error(
+        "Both {} and {} would be renamed to {}",
+        .first.display(),
+        .second.display(),
+        .target.display()
+    )]
+    Collision {
+        first: PathBuf,
+        second: PathBuf,
+        target: PathBuf,
+    },
+    /// A file could not be read while resolving a conflict.
+    #[error("Unable to read {}: {source}", .path.display())]
+    ReadFailed {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    /// The rename succeeded but could not be recorded in the journal.
+    #[error(transparent)]
+    Journal(#[from] crate::journal::JournalError),
     /// The underlying filesystem rename failed.
-    #[error("Unable to rename {from} to {to}: {source}")]
+    #[error("Unable to rename {} to {}: {source}", .from.display(), .to.display())]
     RenameFailed {
-        from: String,
-        to: String,
+        from: PathBuf,
+        to: PathBuf,
         #[source]
         source: std::io::Error,
     },
 }
 
-/// Renames the file provided based on the pattern provided.
+/// Works out the new name for a file based on the pattern provided.
 ///
 /// **Parameters:**
 ///
-/// - `filename: &str` -- the name of the file to be renamed
+/// - `filename: &Path` -- the file to be renamed. Its directory and extension are kept as they are, even if they are not valid UTF-8
 /// - `tags: &HashMap<String, Option<String>>` -- The metadata values (e.g. Title, Author, Year, Publisher). `None` values fall back to `"Unknown"` in the generated filename.
 /// - `pattern: &str` -- the tag pattern for the new filename. This has been validated to be OK by the CLI.
-/// - `dry_run: bool` -- if `true`, log what would happen but do not rename the file.
+/// - `sanitiser: &Sanitiser` -- the filesystem profile and length limit the new name must satisfy.
 ///
 /// Note that you'll need to populate the tags map _before_ using this function. This is to avoid having to re-open the file and re-read the data.
 ///
+/// Nothing is moved and no conflicts are resolved here: the result is the name the file
+/// _wants_. Collect these for the whole batch and hand them to [`crate::plan::build`].
+///
 /// **Returns**
 ///
-/// - The new file name if successful
+/// - The new path (in the same directory as `filename`, with the same extension) if successful
 /// - A [`RenameError`] variant indicating what failed.
-pub fn rename_file(
-    filename: &str,
+pub fn new_path(
+    filename: &Path,
     tags: &HashMap<String, Option<String>>,
     pattern: &str,
-    dry_run: bool,
-) -> Result<String, RenameError> {
+    sanitiser: &Sanitiser,
+) -> Result<PathBuf, RenameError> {
     // Check if there is a rename pattern
     if pattern.is_empty() {
         return Err(RenameError::EmptyPattern);
     }
 
-    let mut new_filename = pattern.to_string();
-
-    // replace any options (eg. %aa, %tg) with the corresponding tag
-    new_filename = new_filename.replace(
-        "%t",
-        tags.get("Title")
-            .and_then(Option::as_deref)
-            .unwrap_or("Unknown"),
-    );
-    new_filename = new_filename.replace(
-        "%a",
-        tags.get("Author")
-            .and_then(Option::as_deref)
-            .unwrap_or("Unknown"),
-    );
-    new_filename = new_filename.replace(
-        "%p",
-        tags.get("Publisher")
-            .and_then(Option::as_deref)
-            .unwrap_or("Unknown"),
-    );
-    new_filename = new_filename.replace(
-        "%i",
-        tags.get("Identifier")
-            .and_then(Option::as_deref)
-            .unwrap_or("Unknown"),
-    );
-    new_filename = new_filename.replace(
-        "%y",
-        tags.get("Year")
-            .and_then(Option::as_deref)
-            .unwrap_or("Unknown"),
-    );
-
-    // Single-pass sanitisation combining semantic replacements and forbidden-char removal.
-    // Avoids the intermediate Strings produced by chained .replace() calls.
-    // Reserve exact worst-case capacity: each ':' expands from one byte to the two-byte " -".
-    let extra_capacity = new_filename.bytes().filter(|&b| b == b':').count();
-    let mut sanitised = String::with_capacity(new_filename.len() + extra_capacity);
-    for ch in new_filename.chars() {
-        match ch {
-            '/' | '\\' => sanitised.push('-'),
-            ':' => sanitised.push_str(" -"),
-            // Dots removed for readability; rest are forbidden on Windows or universally invalid.
-            '.' | '*' | '?' | '"' | '<' | '>' | '|' | '\0' => {}
-            ch => sanitised.push(ch),
-        }
-    }
-    new_filename = sanitised;
+    let mut new_filename = expand(pattern, tags);
 
-    new_filename = new_filename.trim().to_string();
+    let ext = filename.extension().unwrap_or_default();
+    new_filename = sanitiser.file_stem(&new_filename, &ext.to_string_lossy());
 
     if new_filename.is_empty() {
         return Err(RenameError::EmptyResult);
     }
 
     // Get the path in front of the filename (eg. "books/book.pdf" returns "books/")
-    let parent = Path::new(&filename)
-        .parent()
-        .unwrap_or_else(|| Path::new("."));
+    let parent = filename.parent().unwrap_or_else(|| Path::new("."));
     log::debug!("parent = {}", parent.display());
 
     // Create the full destination path, including the source file's parent directory
-    let mut new_path =
-        parent.join(Path::new(&new_filename).with_extension(utils::get_extension(filename)));
+    let new_path = target_path(parent, OsStr::new(&new_filename), ext);
     log::debug!("new_path = {}", new_path.display());
 
-    // Return if the new filename is the same as the old.
-    {
-        let s = new_path.to_string_lossy();
-        if s == filename {
-            log::debug!("New filename == old filename. Returning.");
-            return Ok(s.into_owned());
-        }
-    }
-
-    // Check if a file with the new filename already exists - make the filename unique if it does.
-    if new_path.exists() {
-        log::warn!("{new_filename} already exists. Appending unique identifier.");
-        new_filename = format!("{new_filename} ({:0>4})", get_unique_value());
-        new_path =
-            parent.join(Path::new(&new_filename).with_extension(utils::get_extension(filename)));
-    }
+    Ok(new_path)
+}
 
-    if dry_run {
-        log::debug!("dry_run: {filename} --> {}", new_path.display());
-    } else {
-        match std::fs::rename(filename, &new_path) {
-            Ok(()) => log::debug!("{filename} --> {}", new_path.display()),
-            Err(source) => {
-                return Err(RenameError::RenameFailed {
-                    from: filename.to_owned(),
-                    to: new_path.to_string_lossy().into_owned(),
-                    source,
-                });
-            }
+/// Replace the placeholders in `pattern` with values from `tags`.
+///
+/// `%t`, `%a`, `%p`, `%i` and `%y` stand for Title, Author, Publisher, Identifier and Year.
+/// `{key}` stands for any tag, ignoring case, e.g. `{isbn13}` or `{Language}`. Missing
+/// values become `"Unknown"`. The pattern is read once from left to right, so text in a
+/// value that looks like a placeholder is left alone.
+pub fn expand(pattern: &str, tags: &HashMap<String, Option<String>>) -> String {
+    let value = |key: &str| {
+        tags.iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .and_then(|(_, v)| v.as_deref())
+            .unwrap_or("Unknown")
+    };
+    let mut out = String::with_capacity(pattern.len());
+    for segment in segments(pattern) {
+        match segment {
+            Segment::Text(text) => out.push_str(text),
+            Segment::Field(key) => out.push_str(value(key)),
         }
     }
+    out
+}
 
-    Ok(new_path.to_string_lossy().into_owned())
+/// The tags the placeholders in `pattern` stand for, as written in `{key}` placeholders.
+pub fn fields(pattern: &str) -> Vec<&str> {
+    segments(pattern)
+        .into_iter()
+        .filter_map(|segment| match segment {
+            Segment::Field(key) => Some(key),
+            Segment::Text(_) => None,
+        })
+        .collect()
 }
 
-/// Upper bound (exclusive) for the de-collision value: total microseconds relative to
-/// `UNIX_EPOCH` modulo this constant, giving a 10-second window of unique values.
-const UNIQUE_VALUE_MODULUS: u128 = 10_000_000;
+/// A piece of a rename pattern.
+enum Segment<'a> {
+    /// Text kept as it is.
+    Text(&'a str),
+    /// A placeholder, by the tag it stands for.
+    Field(&'a str),
+}
 
-/// Returns a unique numeric identifier in `0..UNIQUE_VALUE_MODULUS` derived from the
-/// total microseconds elapsed relative to `UNIX_EPOCH`. Used to de-collide file names.
-/// The implementation can be swapped (e.g. for an RNG) without affecting callers.
-fn get_unique_value() -> u128 {
-    unique_value_from(SystemTime::now())
+/// Split `pattern` into text and placeholders, reading it from left to right.
+fn segments(pattern: &str) -> Vec<Segment<'_>> {
+    let mut segments = Vec::new();
+    let mut rest = pattern;
+    while let Some(start) = rest.find(['%', '{']) {
+        segments.push(Segment::Text(&rest[..start]));
+        rest = &rest[start..];
+        let field = if rest.starts_with('%') {
+            let key = match rest.get(1..2) {
+                Some("t") => Some("Title"),
+                Some("a") => Some("Author"),
+                Some("p") => Some("Publisher"),
+                Some("i") => Some("Identifier"),
+                Some("y") => Some("Year"),
+                _ => None,
+            };
+            key.map(|key| (key, 2))
+        } else {
+            rest[1..].find('}').and_then(|end| {
+                let key = &rest[1..=end];
+                let valid =
+                    !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+                valid.then_some((key, end + 2))
+            })
+        };
+        match field {
+            Some((key, len)) => {
+                segments.push(Segment::Field(key));
+                rest = &rest[len..];
+            }
+            None => {
+                segments.push(Segment::Text(&rest[..1]));
+                rest = &rest[1..];
+            }
+        }
+    }
+    segments.push(Segment::Text(rest));
+    segments
 }
 
-/// Inner implementation so the clock source is injectable in tests.
+/// Join `parent`, `stem` and `ext` into a path.
 ///
-/// For times on or after `UNIX_EPOCH`, uses `time.duration_since(UNIX_EPOCH)`.
-/// For times before `UNIX_EPOCH`, falls back to the error's duration (the magnitude
-/// of the pre-epoch offset), so the result still varies rather than being a constant.
-/// The total microseconds are reduced modulo [`UNIQUE_VALUE_MODULUS`].
-fn unique_value_from(time: SystemTime) -> u128 {
-    time.duration_since(UNIX_EPOCH)
-        .unwrap_or_else(|e| e.duration())
-        .as_micros()
-        % UNIQUE_VALUE_MODULUS
+/// Unlike [`Path::with_extension`], dots already present in `stem` are never mistaken
+/// for an extension.
+pub fn target_path(parent: &Path, stem: &OsStr, ext: &OsStr) -> PathBuf {
+    if ext.is_empty() {
+        return parent.join(stem);
+    }
+    let mut name = OsString::with_capacity(stem.len() + ext.len() + 1);
+    name.push(stem);
+    name.push(".");
+    name.push(ext);
+    parent.join(name)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::fs;
-    use std::time::Duration;
-    use tempfile::NamedTempFile;
 
     fn tags(pairs: &[(&str, &str)]) -> HashMap<String, Option<String>> {
         pairs
@@ -190,52 +248,44 @@ mod tests {
             .collect()
     }
 
-    // ── get_unique_value ────────────────────────────────────────────────────
-
-    #[test]
-    fn unique_value_is_within_range() {
-        for _ in 0..100 {
-            let val = get_unique_value();
-            assert!(
-                val < UNIQUE_VALUE_MODULUS,
-                "expected < {UNIQUE_VALUE_MODULUS}, got {val}"
-            );
-        }
-    }
-
-    #[test]
-    fn unique_value_from_before_epoch_is_in_range() {
-        // Exercises the Err branch: a time before UNIX_EPOCH causes
-        // duration_since to fail; unique_value_from must still return a value
-        // in 0..UNIQUE_VALUE_MODULUS rather than panicking.
-        // checked_sub guards against platforms that don't support pre-epoch times.
-        let Some(before_epoch) = UNIX_EPOCH.checked_sub(Duration::from_secs(1)) else {
-            return;
-        };
-        let val = unique_value_from(before_epoch);
-        assert!(
-            val < UNIQUE_VALUE_MODULUS,
-            "expected < {UNIQUE_VALUE_MODULUS}, got {val}"
-        );
+    /// [`new_path`] for a UTF-8 `filename`, with the result as a string.
+    fn rename(
+        filename: &str,
+        tags: &HashMap<String, Option<String>>,
+        pattern: &str,
+        sanitiser: &Sanitiser,
+    ) -> Result<String, RenameError> {
+        new_path(Path::new(filename), tags, pattern, sanitiser)
+            .map(|p| p.to_string_lossy().into_owned())
     }
 
     // ── error paths ─────────────────────────────────────────────────────────
 
     #[test]
     fn empty_pattern_returns_error() {
-        let err = rename_file("some_file.epub", &tags(&[]), "", false).expect_err("should fail");
+        let err = rename("some_file.epub", &tags(&[]), "", &Sanitiser::default())
+            .expect_err("should fail");
         assert!(matches!(err, RenameError::EmptyPattern));
     }
 
     #[test]
     fn pattern_that_sanitises_to_empty_returns_error() {
-        // Pattern "." becomes "" after the '.' sanitisation step
-        let err = rename_file("some_file.epub", &tags(&[]), ".", false).expect_err("should fail");
+        // Leading dots are removed so the file is not hidden, which leaves nothing
+        let err = rename("some_file.epub", &tags(&[]), ".", &Sanitiser::default())
+            .expect_err("should fail");
         assert!(matches!(err, RenameError::EmptyResult));
     }
 
     // ── tag substitution ────────────────────────────────────────────────────
 
+    #[test]
+    fn fields_lists_the_placeholders_of_a_pattern() {
+        assert_eq!(
+            fields("%a - %t ({Pages}p) {not a key} 100%"),
+            ["Author", "Title", "Pages"]
+        );
+    }
+
     #[test]
     fn all_placeholders_are_substituted() {
         let t = tags(&[
@@ -245,8 +295,12 @@ mod tests {
             ("Identifier", "978-0-00-000000-0"),
             ("Year", "2024"),
         ]);
-        // dry_run so no actual file access is needed for the substitution check
-        let result = rename_file("placeholder.epub", &t, "%a - %t (%y) [%p] %i", true);
+        let result = rename(
+            "placeholder.epub",
+            &t,
+            "%a - %t (%y) [%p] %i",
+            &Sanitiser::default(),
+        );
         // The result path ends with the substituted stem + original extension
         let path = result.expect("should succeed");
         assert!(path.contains("Jane Doe"), "author missing: {path}");
@@ -261,7 +315,12 @@ mod tests {
 
     #[test]
     fn missing_tags_fall_back_to_unknown() {
-        let result = rename_file("placeholder.epub", &tags(&[]), "%t - %a", true);
+        let result = rename(
+            "placeholder.epub",
+            &tags(&[]),
+            "%t - %a",
+            &Sanitiser::default(),
+        );
         let path = result.expect("should succeed");
         assert!(
             path.contains("Unknown - Unknown"),
@@ -269,19 +328,49 @@ mod tests {
         );
     }
 
+    #[test]
+    fn any_tag_can_be_used_by_name() {
+        let t = tags(&[("Title", "Dune"), ("ISBN13", "9780441013593")]);
+        let result = rename(
+            "a.epub",
+            &t,
+            "{isbn13} {TITLE} {Missing}",
+            &Sanitiser::default(),
+        );
+        assert_eq!(result.expect("ok"), "9780441013593 Dune Unknown.epub");
+    }
+
+    #[test]
+    fn placeholders_inside_values_are_not_expanded() {
+        let t = tags(&[("Title", "100% {Author} %a"), ("Author", "Jane")]);
+        let result = rename("a.epub", &t, "%t by {author}", &Sanitiser::default());
+        assert_eq!(result.expect("ok"), "100% {Author} %a by Jane.epub");
+    }
+
+    #[test]
+    fn text_that_is_not_a_placeholder_is_kept() {
+        let result = rename(
+            "a.epub",
+            &tags(&[]),
+            "50% {a b} {} %q {",
+            &Sanitiser::default(),
+        );
+        assert_eq!(result.expect("ok"), "50% {a b} {} %q {.epub");
+    }
+
     // ── character sanitisation ───────────────────────────────────────────────
 
     #[test]
     fn slash_in_tag_is_replaced_with_dash() {
         let t = tags(&[("Title", "A/B")]);
-        let result = rename_file("placeholder.epub", &t, "%t", true).expect("ok");
+        let result = rename("placeholder.epub", &t, "%t", &Sanitiser::default()).expect("ok");
         assert!(result.contains("A-B"), "slash not sanitised: {result}");
     }
 
     #[test]
     fn colon_in_tag_is_replaced_with_space_dash() {
         let t = tags(&[("Title", "Volume: One")]);
-        let result = rename_file("placeholder.epub", &t, "%t", true).expect("ok");
+        let result = rename("placeholder.epub", &t, "%t", &Sanitiser::default()).expect("ok");
         assert!(
             result.contains("Volume - One"),
             "colon not sanitised: {result}"
@@ -289,16 +378,16 @@ mod tests {
     }
 
     #[test]
-    fn dot_in_tag_is_removed() {
+    fn dot_in_tag_is_kept() {
         let t = tags(&[("Title", "Mr. Smith")]);
-        let result = rename_file("placeholder.epub", &t, "%t", true).expect("ok");
-        assert!(result.contains("Mr Smith"), "dot not removed: {result}");
+        let result = rename("placeholder.epub", &t, "%t", &Sanitiser::default()).expect("ok");
+        assert!(result.contains("Mr. Smith"), "dot removed: {result}");
     }
 
     #[test]
     fn backslash_in_tag_is_replaced_with_dash() {
         let t = tags(&[("Title", r"A\B")]);
-        let result = rename_file("placeholder.epub", &t, "%t", true).expect("ok");
+        let result = rename("placeholder.epub", &t, "%t", &Sanitiser::default()).expect("ok");
         assert!(result.contains("A-B"), "backslash not sanitised: {result}");
     }
 
@@ -306,7 +395,7 @@ mod tests {
     fn windows_forbidden_chars_are_removed() {
         // * ? " < > | are forbidden on Windows
         let t = tags(&[("Title", "A*B?C\"D<E>F|G")]);
-        let result = rename_file("placeholder.epub", &t, "%t", true).expect("ok");
+        let result = rename("placeholder.epub", &t, "%t", &Sanitiser::default()).expect("ok");
         assert!(
             result.contains("ABCDEFG"),
             "forbidden chars not removed: {result}"
@@ -316,15 +405,15 @@ mod tests {
     #[test]
     fn pattern_of_only_forbidden_chars_returns_error() {
         // After stripping forbidden chars the stem is empty → error
-        let err =
-            rename_file("placeholder.epub", &tags(&[]), "*<>", false).expect_err("should fail");
+        let err = rename("placeholder.epub", &tags(&[]), "*<>", &Sanitiser::default())
+            .expect_err("should fail");
         assert!(matches!(err, RenameError::EmptyResult));
     }
 
     #[test]
     fn nul_byte_in_tag_is_removed() {
         let t = tags(&[("Title", "A\0B")]);
-        let result = rename_file("placeholder.epub", &t, "%t", true).expect("ok");
+        let result = rename("placeholder.epub", &t, "%t", &Sanitiser::default()).expect("ok");
         assert!(result.contains("AB"), "NUL byte not removed: {result}");
         assert!(
             !result.contains('\0'),
@@ -332,25 +421,12 @@ mod tests {
         );
     }
 
-    #[test]
-    fn rename_to_nonexistent_parent_returns_rename_failed() {
-        // Trigger a filesystem error by targeting a directory that does not exist.
-        let t = tags(&[("Title", "SomeTitle")]);
-        let err =
-            rename_file("nonexistent_dir/source.epub", &t, "%t", false).expect_err("should fail");
-        assert!(
-            matches!(err, RenameError::RenameFailed { .. }),
-            "expected RenameFailed, got: {err}"
-        );
-    }
-
     // ── whitespace trimming ──────────────────────────────────────────────────
 
     #[test]
     fn leading_and_trailing_spaces_in_tag_are_trimmed() {
-        // dry_run=true — no real file needed; exercises the trim path only.
         let t = tags(&[("Title", "  Spaced Title  ")]);
-        let result = rename_file("placeholder.epub", &t, "%t", true).expect("ok");
+        let result = rename("placeholder.epub", &t, "%t", &Sanitiser::default()).expect("ok");
         let stem = std::path::Path::new(&result)
             .file_stem()
             .unwrap()
@@ -362,64 +438,48 @@ mod tests {
         );
     }
 
-    // ── dry run ──────────────────────────────────────────────────────────────
-
     #[test]
-    fn dry_run_does_not_rename_file() {
-        let src = NamedTempFile::new().expect("temp file");
-        let src_path = src.path().to_string_lossy().to_string();
+    fn new_path_keeps_directory_and_extension() {
         let t = tags(&[("Title", "NewName")]);
-
-        let result = rename_file(&src_path, &t, "%t", true).expect("ok");
-
-        // Source still exists
-        assert!(
-            fs::metadata(&src_path).is_ok(),
-            "source was deleted in dry-run"
-        );
-        // Destination (result path) does not exist
-        assert!(
-            fs::metadata(&result).is_err(),
-            "destination was created in dry-run"
+        let result = rename("books/old.EPUB", &t, "%t", &Sanitiser::default()).expect("ok");
+        assert_eq!(
+            Path::new(&result),
+            Path::new("books").join("NewName.EPUB"),
+            "unexpected path: {result}"
         );
     }
 
-    // ── actual rename ────────────────────────────────────────────────────────
-
     #[test]
-    fn rename_moves_file_to_new_path() {
-        let dir = tempfile::tempdir().expect("temp dir");
-        let src_path = dir.path().join("source.epub");
-        fs::write(&src_path, b"").expect("create src");
-        let src_str = src_path.to_string_lossy().to_string();
-
-        let t = tags(&[("Title", "RenamedFile")]);
-        let result = rename_file(&src_str, &t, "%t", false).expect("rename should succeed");
-
-        assert!(
-            fs::metadata(&src_str).is_err(),
-            "source still exists after rename"
-        );
-        assert!(
-            fs::metadata(&result).is_ok(),
-            "destination does not exist after rename"
-        );
+    fn dotted_stem_keeps_original_extension() {
+        let t = tags(&[("Title", "Vol. 2")]);
+        let result = rename("old.epub", &t, "%t", &Sanitiser::default()).expect("ok");
+        assert_eq!(result, "Vol. 2.epub");
     }
 
-    // ── same-filename guard ──────────────────────────────────────────────────
+    #[test]
+    fn profile_and_length_limit_are_applied() {
+        let t = tags(&[("Title", "A:B"), ("Author", "x".repeat(300).as_str())]);
+        let posix = Sanitiser {
+            profile: crate::sanitise::FsProfile::Posix,
+            max_bytes: 20,
+        };
+        let result = rename("old.epub", &t, "%t %a", &posix).expect("ok");
+        assert_eq!(result, format!("A:B {}.epub", "x".repeat(11)));
+    }
 
+    #[cfg(unix)]
     #[test]
-    fn same_filename_returns_ok_without_moving() {
-        let dir = tempfile::tempdir().expect("temp dir");
-        let src_path = dir.path().join("mybook.epub");
-        fs::write(&src_path, b"").expect("create src");
-        let src_str = src_path.to_string_lossy().to_string();
-
-        // Pattern "%t" with Title="mybook" produces "mybook.epub" — same as source
-        let t = tags(&[("Title", "mybook")]);
-        let result = rename_file(&src_str, &t, "%t", false).expect("ok");
-
-        assert_eq!(result, src_str, "should return the same path unchanged");
-        assert!(fs::metadata(&src_str).is_ok(), "file should still exist");
+    fn non_utf8_directory_and_extension_are_kept() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let t = tags(&[("Title", "Café")]);
+        let old =
+            Path::new(OsStr::from_bytes(b"B\xfccher")).join(OsStr::from_bytes(b"caf\xe9.\xe9pub"));
+        let result = new_path(&old, &t, "%t", &Sanitiser::default()).expect("ok");
+        assert_eq!(
+            result,
+            Path::new(OsStr::from_bytes(b"B\xfccher"))
+                .join(OsStr::from_bytes(b"Caf\xc3\xa9.\xe9pub"))
+        );
     }
 }
@@ -0,0 +1,238 @@
+//! Make generated filenames safe for the filesystem they will live on.
+//!
+//! Each [`FsProfile`] knows which characters its target filesystem rejects and which
+//! names it reserves. [`Sanitiser`] applies a profile plus a per-component length limit
+//! measured in UTF-8 bytes.
+use clap::ValueEnum;
+use clap::builder::PossibleValue;
+
+/// Default limit for a single path component, in bytes. Matches ext4, APFS and NTFS.
+pub const DEFAULT_MAX_BYTES: usize = 255;
+
+/// Device names Windows reserves regardless of extension (`CON.epub` is still `CON`).
+const WINDOWS_RESERVED: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// The filesystem rules a generated filename must satisfy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FsProfile {
+    /// Linux and other POSIX filesystems: only `/` and NUL are forbidden.
+    Posix,
+    /// NTFS: `< > : " / \ | ? *` are forbidden, trailing dots and spaces are dropped by
+    /// the OS, and device names such as `CON` and `NUL` are reserved.
+    #[default]
+    Windows,
+    /// FAT32/exFAT, as found on e-reader SD cards: the Windows rules, and characters
+    /// outside the Basic Multilingual Plane (e.g. emoji) are dropped because many device
+    /// drivers store names as UCS-2.
+    Fat32,
+    /// APFS/HFS+: `/` and `:` are forbidden (Finder shows one as the other).
+    Macos,
+}
+
+impl ValueEnum for FsProfile {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Self::Posix, Self::Windows, Self::Fat32, Self::Macos]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(match self {
+            Self::Posix => PossibleValue::new("posix").help("Linux and other POSIX systems"),
+            Self::Windows => PossibleValue::new("windows").help("Windows / NTFS"),
+            Self::Fat32 => {
+                PossibleValue::new("fat32").help("FAT32 / exFAT, e.g. e-reader SD cards")
+            }
+            Self::Macos => PossibleValue::new("macos").help("macOS / APFS"),
+        })
+    }
+}
+
+/// Turns arbitrary text into a filename stem that is valid under a [`FsProfile`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sanitiser {
+    /// The filesystem rules to apply.
+    pub profile: FsProfile,
+    /// Maximum length of the whole file name (stem, dot and extension) in bytes.
+    pub max_bytes: usize,
+}
+
+impl Default for Sanitiser {
+    fn default() -> Self {
+        Self {
+            profile: FsProfile::default(),
+            max_bytes: DEFAULT_MAX_BYTES,
+        }
+    }
+}
+
+impl Sanitiser {
+    /// Sanitise `stem` and shorten it so that `stem.ext` fits in [`Self::max_bytes`].
+    ///
+    /// Path separators become `-` and, where `:` is forbidden, it becomes ` -`. Other
+    /// forbidden characters and all control characters are removed. Dots are kept except
+    /// at the start of the name (which would hide the file) and, on Windows and FAT, at
+    /// the end. Truncation never splits a UTF-8 character.
+    ///
+    /// Returns an empty string if nothing usable is left.
+    pub fn file_stem(&self, stem: &str, ext: &str) -> String {
+        let windows_like = matches!(self.profile, FsProfile::Windows | FsProfile::Fat32);
+
+        // Single-pass sanitisation combining semantic replacements and forbidden-char removal.
+        // Avoids the intermediate Strings produced by chained .replace() calls.
+        let mut sanitised = String::with_capacity(stem.len() + stem.len() / 4);
+        for ch in stem.chars() {
+            match ch {
+                '/' => sanitised.push('-'),
+                '\\' if windows_like => sanitised.push('-'),
+                ':' if self.profile != FsProfile::Posix => sanitised.push_str(" -"),
+                '*' | '?' | '"' | '<' | '>' | '|' if windows_like => {}
+                ch if ch.is_control() => {}
+                ch if self.profile == FsProfile::Fat32 && u32::from(ch) > 0xFFFF => {}
+                ch => sanitised.push(ch),
+            }
+        }
+
+        let ext_bytes = if ext.is_empty() { 0 } else { ext.len() + 1 };
+        let budget = self.max_bytes.saturating_sub(ext_bytes);
+        let trimmed = self.trim(&sanitised);
+        let mut result = self.trim(truncate(trimmed, budget)).to_string();
+
+        if windows_like && is_reserved(&result) {
+            // Only the part before the first dot counts, so the marker goes right after it.
+            let base_len = result.find('.').unwrap_or(result.len());
+            result.insert(base_len, '_');
+            if result.len() > budget {
+                return String::new();
+            }
+        }
+        result
+    }
+
+    /// Strip whitespace and leading dots, plus trailing dots on Windows and FAT.
+    fn trim<'a>(&self, s: &'a str) -> &'a str {
+        let s = s.trim().trim_start_matches('.').trim_start();
+        if matches!(self.profile, FsProfile::Windows | FsProfile::Fat32) {
+            s.trim_end_matches(|c: char| c == '.' || c.is_whitespace())
+        } else {
+            s
+        }
+    }
+}
+
+/// The longest prefix of `s` that is at most `max_bytes` long and ends on a character
+/// boundary.
+pub fn truncate(s: &str, max_bytes: usize) -> &str {
+    if s.len() <= max_bytes {
+        return s;
+    }
+    let end = (0..=max_bytes)
+        .rev()
+        .find(|&i| s.is_char_boundary(i))
+        .unwrap_or(0);
+    &s[..end]
+}
+
+/// `true` if Windows treats `stem` as a device name. Only the part before the first dot
+/// counts, and case and trailing spaces are ignored.
+fn is_reserved(stem: &str) -> bool {
+    let base = stem.split('.').next().unwrap_or_default().trim_end();
+    WINDOWS_RESERVED
+        .iter()
+        .any(|r| r.eq_ignore_ascii_case(base))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sanitiser(profile: FsProfile) -> Sanitiser {
+        Sanitiser {
+            profile,
+            ..Sanitiser::default()
+        }
+    }
+
+    #[test]
+    fn dots_are_kept_inside_names() {
+        for profile in FsProfile::value_variants() {
+            let s = sanitiser(*profile);
+            assert_eq!(
+                s.file_stem("J.R.R. Tolkien - Vol. 2", "epub"),
+                "J.R.R. Tolkien - Vol. 2"
+            );
+        }
+    }
+
+    #[test]
+    fn leading_dots_are_removed_so_files_are_not_hidden() {
+        assert_eq!(
+            sanitiser(FsProfile::Posix).file_stem("..hidden", "epub"),
+            "hidden"
+        );
+    }
+
+    #[test]
+    fn posix_only_replaces_slash() {
+        let s = sanitiser(FsProfile::Posix);
+        assert_eq!(s.file_stem(r#"A/B:C\D*?"<>|"#, "epub"), r#"A-B:C\D*?"<>|"#);
+    }
+
+    #[test]
+    fn macos_replaces_colon() {
+        let s = sanitiser(FsProfile::Macos);
+        assert_eq!(s.file_stem("Volume: One/Two", "epub"), "Volume - One-Two");
+    }
+
+    #[test]
+    fn windows_trims_trailing_dots_and_spaces() {
+        let s = sanitiser(FsProfile::Windows);
+        assert_eq!(s.file_stem("And so on... ", "epub"), "And so on");
+    }
+
+    #[test]
+    fn windows_reserved_names_are_suffixed() {
+        let s = sanitiser(FsProfile::Windows);
+        assert_eq!(s.file_stem("con", "epub"), "con_");
+        assert_eq!(s.file_stem("NUL.part", "epub"), "NUL_.part");
+        assert_eq!(s.file_stem("Console", "epub"), "Console");
+        assert_eq!(sanitiser(FsProfile::Posix).file_stem("CON", "epub"), "CON");
+    }
+
+    #[test]
+    fn fat32_drops_characters_outside_the_bmp() {
+        let s = sanitiser(FsProfile::Fat32);
+        assert_eq!(s.file_stem("Book 📚 Title", "epub"), "Book  Title");
+        assert_eq!(sanitiser(FsProfile::Windows).file_stem("📚", "epub"), "📚");
+    }
+
+    #[test]
+    fn control_characters_are_removed() {
+        assert_eq!(
+            sanitiser(FsProfile::Posix).file_stem("A\0B\nC", "epub"),
+            "ABC"
+        );
+    }
+
+    #[test]
+    fn long_names_are_truncated_on_char_boundaries() {
+        let s = Sanitiser {
+            profile: FsProfile::Posix,
+            max_bytes: 10,
+        };
+        // "é" is two bytes: 10 - ".epub" leaves 5 bytes, which would split the third "é".
+        let stem = s.file_stem("ééééé", "epub");
+        assert_eq!(stem, "éé");
+        assert!(stem.len() + ".epub".len() <= 10);
+    }
+
+    #[test]
+    fn truncation_does_not_leave_trailing_space() {
+        let s = Sanitiser {
+            profile: FsProfile::Windows,
+            max_bytes: 9,
+        };
+        assert_eq!(s.file_stem("abc def", "epub"), "abc");
+    }
+}
@@ -0,0 +1,278 @@
+//! Plain text of a book, and the statistics worked out from it: words, characters,
+//! estimated pages and images.
+//!
+//! Each format's reader module does the extracting: [`crate::epub::text`] (the spine
+//! documents), [`crate::mobi::text`] (the text records) and [`crate::pdf::text`] (the page
+//! content streams). The statistics are added to a file's metadata as `Words`,
+//! `Characters`, `Pages` and `Images`, so they can be filtered on and used in patterns.
+use crate::{
+    epub,
+    extract::{Format, Tags},
+    mobi, pdf,
+};
+use std::path::Path;
+
+/// Words on an estimated page, roughly a printed paperback's.
+pub const WORDS_PER_PAGE: usize = 250;
+
+/// The tags [`add_tags`] adds.
+pub const KEYS: [&str; 4] = ["Words", "Characters", "Pages", "Images"];
+
+/// HTML elements that don't break a word when text runs across their tags.
+const INLINE_ELEMENTS: &[&str] = &[
+    "a", "abbr", "b", "bdi", "bdo", "big", "cite", "code", "del", "dfn", "em", "font", "i", "ins",
+    "kbd", "mark", "q", "s", "samp", "small", "span", "strike", "strong", "sub", "sup", "tt", "u",
+    "var", "wbr",
+];
+
+/// HTML elements whose content is not text of the book.
+const HIDDEN_ELEMENTS: &[&str] = &["head", "script", "style", "title"];
+
+/// The extracted text of a book and how many images it has.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Content {
+    pub text: String,
+    pub images: usize,
+}
+
+/// Extract the content of `path` with the reader for its [`Format`].
+///
+/// Files of an unknown format have no content.
+///
+/// # Errors
+///
+/// Returns `Err` if the file cannot be opened or its text cannot be read.
+pub fn read(path: &Path) -> anyhow::Result<Content> {
+    match Format::of(path) {
+        Some(Format::Epub) => epub::text(path),
+        Some(Format::Mobi) => mobi::text(path),
+        Some(Format::Pdf) => Ok(pdf::text(path)?),
+        None => Ok(Content::default()),
+    }
+}
+
+/// Statistics about a book's [`Content`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    /// Runs of non-whitespace that contain a letter or digit.
+    pub words: usize,
+    /// Characters other than whitespace.
+    pub characters: usize,
+    /// `words` divided by [`WORDS_PER_PAGE`], rounded up.
+    pub pages: usize,
+    pub images: usize,
+}
+
+impl Stats {
+    /// The statistics of `content`.
+    pub fn of(content: &Content) -> Self {
+        let words = content
+            .text
+            .split_whitespace()
+            .filter(|word| word.chars().any(char::is_alphanumeric))
+            .count();
+        Self {
+            words,
+            characters: content.text.chars().filter(|c| !c.is_whitespace()).count(),
+            pages: words.div_ceil(WORDS_PER_PAGE),
+            images: content.images,
+        }
+    }
+}
+
+/// Add the `Words`, `Characters`, `Pages` and `Images` tags for `stats` to `tags`.
+pub fn add_tags(stats: Stats, tags: &mut Tags) {
+    for (key, value) in [
+        ("Words", stats.words),
+        ("Characters", stats.characters),
+        ("Pages", stats.pages),
+        ("Images", stats.images),
+    ] {
+        tags.insert(key.to_string(), Some(value.to_string()));
+    }
+}
+
+/// `true` if any of `fields`, e.g. from a `--where` filter or a rename pattern, is one of
+/// the [`KEYS`], ignoring case.
+pub fn is_requested<'a>(fields: impl IntoIterator<Item = &'a str>) -> bool {
+    fields
+        .into_iter()
+        .any(|field| KEYS.iter().any(|key| key.eq_ignore_ascii_case(field)))
+}
+
+/// The text of an HTML or XHTML document.
+///
+/// Tags are dropped, block elements such as `<p>` and `<br>` become spaces, and the
+/// content of `<head>`, `<script>` and `<style>` is skipped. This is lenient: it does not
+/// need the document to be well-formed.
+pub fn html_to_text(html: &str) -> String {
+    let mut text = String::with_capacity(html.len() / 2);
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        push_decoded(&mut text, &rest[..start]);
+        rest = &rest[start..];
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+        let end = rest.find('>').map_or(rest.len(), |end| end + 1);
+        let tag = rest[1..end].trim_end_matches('>');
+        rest = &rest[end..];
+
+        let closing = tag.starts_with('/');
+        let name = tag
+            .trim_start_matches('/')
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default()
+            .rsplit(':')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        if !closing && !tag.ends_with('/') && HIDDEN_ELEMENTS.contains(&name.as_str()) {
+            // Skip to the end of the element, whatever is in it.
+            let close = format!("</{name}");
+            rest = rest
+                .to_ascii_lowercase()
+                .find(&close)
+                .and_then(|at| rest[at..].find('>').map(|end| &rest[at + end + 1..]))
+                .unwrap_or("");
+        }
+        if !INLINE_ELEMENTS.contains(&name.as_str()) {
+            text.push(' ');
+        }
+    }
+    push_decoded(&mut text, rest);
+    text
+}
+
+/// Push `raw` text onto `text`, decoding character references such as `&amp;` and
+/// `&#8212;`. Unknown references are kept as they are.
+fn push_decoded(text: &mut String, raw: &str) {
+    let mut rest = raw;
+    while let Some(start) = rest.find('&') {
+        text.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let decoded = rest.find(';').filter(|&end| end <= 10).and_then(|end| {
+            let reference = &rest[1..end];
+            let c = match reference {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some('\u{a0}'),
+                _ => reference.strip_prefix('#').and_then(|number| {
+                    match number.strip_prefix(['x', 'X']) {
+                        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                        None => number.parse().ok(),
+                    }
+                    .and_then(char::from_u32)
+                }),
+            };
+            c.map(|c| (c, end + 1))
+        });
+        match decoded {
+            Some((c, len)) => {
+                text.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                text.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    text.push_str(rest);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(text: &str) -> Vec<String> {
+        text.split_whitespace().map(str::to_string).collect()
+    }
+
+    // ── html_to_text ─────────────────────────────────────────────────────────
+
+    #[test]
+    fn block_elements_separate_words_and_inline_ones_do_not() {
+        let html = "<p>First</p><p>Sec<em>ond</em> para<br/>graph</p>";
+        assert_eq!(
+            words(&html_to_text(html)),
+            ["First", "Second", "para", "graph"]
+        );
+    }
+
+    #[test]
+    fn head_scripts_styles_and_comments_are_skipped() {
+        let html = "<html><head><title>Not this</title><style>p { x: y }</style></head>\
+                    <body><!-- nor <p>this</p> --><script>var a = 1 < 2;</script>Only this</body></html>";
+        assert_eq!(words(&html_to_text(html)), ["Only", "this"]);
+    }
+
+    #[test]
+    fn character_references_are_decoded() {
+        let html = "<p>Fish &amp; chips &#8212; &#x201C;hot&#x201D; &lt;3 &bogus; a&b</p>";
+        assert_eq!(
+            html_to_text(html).trim(),
+            "Fish & chips \u{2014} \u{201C}hot\u{201D} <3 &bogus; a&b"
+        );
+    }
+
+    #[test]
+    fn namespaced_and_unclosed_tags_are_handled() {
+        assert_eq!(
+            words(&html_to_text(
+                "<mbp:pagebreak/>One<xhtml:em>two</xhtml:em> three <p"
+            )),
+            ["Onetwo", "three"]
+        );
+    }
+
+    // ── stats ────────────────────────────────────────────────────────────────
+
+    #[test]
+    fn stats_count_words_characters_and_pages() {
+        let content = Content {
+            text: "It was a dark — and stormy — night.\n".repeat(50),
+            images: 2,
+        };
+        let stats = Stats::of(&content);
+        assert_eq!(stats.words, 350);
+        assert_eq!(stats.characters, 50 * 27);
+        assert_eq!(stats.pages, 2);
+        assert_eq!(stats.images, 2);
+    }
+
+    #[test]
+    fn empty_content_has_no_pages() {
+        let stats = Stats::of(&Content::default());
+        assert_eq!((stats.words, stats.characters, stats.pages), (0, 0, 0));
+    }
+
+    #[test]
+    fn stats_become_tags() {
+        let mut tags = Tags::new();
+        add_tags(
+            Stats {
+                words: 1200,
+                characters: 5400,
+                pages: 5,
+                images: 0,
+            },
+            &mut tags,
+        );
+        assert_eq!(tags.get("Words"), Some(&Some("1200".to_string())));
+        assert_eq!(tags.get("Pages"), Some(&Some("5".to_string())));
+        assert_eq!(tags.get("Images"), Some(&Some("0".to_string())));
+    }
+
+    #[test]
+    fn stats_are_requested_by_any_of_their_keys() {
+        assert!(is_requested(["Title", "words"]));
+        assert!(is_requested(["PAGES"]));
+        assert!(!is_requested(["Title", "Year", "Wordsmith"]));
+    }
+}
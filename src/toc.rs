@@ -0,0 +1,133 @@
+//! Read a book's table of contents and report it as text or JSON.
+//!
+//! Each format's reader module does the reading: [`crate::epub::toc`] (the navigation
+//! document or NCX), [`crate::mobi::toc`] (the NCX index) and [`crate::pdf::toc`] (the
+//! outline). They all produce a flat list of [`Entry`]s in reading order, where `depth`
+//! gives the hierarchy.
+use crate::{epub, extract::Format, mobi, pdf, utils::OutputFormat};
+use serde::Serialize;
+use std::{
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+/// One entry in a table of contents.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Entry {
+    /// 1 for top-level entries, 2 for those under them, and so on.
+    pub depth: usize,
+    pub label: String,
+    /// Where the entry points, e.g. `OEBPS/ch1.xhtml#s2`, `filepos 1234` or `page 5`.
+    pub target: Option<String>,
+}
+
+/// Read the table of contents of `path` with the reader for its [`Format`].
+///
+/// Files of an unknown format have an empty table of contents.
+///
+/// # Errors
+///
+/// Returns `Err` if the file cannot be opened or its table of contents cannot be parsed.
+pub fn read(path: &Path) -> anyhow::Result<Vec<Entry>> {
+    match Format::of(path) {
+        Some(Format::Epub) => epub::toc(path),
+        Some(Format::Mobi) => mobi::toc(path),
+        Some(Format::Pdf) => Ok(pdf::toc(path)?),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// The table of contents of one file.
+#[derive(Debug, Serialize)]
+pub struct Toc {
+    pub path: PathBuf,
+    pub entries: Vec<Entry>,
+}
+
+/// Write `tocs` to `out` in `format`.
+///
+/// As text, each file's path is followed by its entries, indented by depth, with their
+/// targets in brackets.
+///
+/// # Errors
+///
+/// Returns `Err` if writing to `out` fails.
+pub fn write<W: Write>(tocs: &[Toc], format: OutputFormat, mut out: W) -> io::Result<()> {
+    match format {
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut out, tocs)?;
+            writeln!(out)
+        }
+        OutputFormat::Text => {
+            for (n, toc) in tocs.iter().enumerate() {
+                if n > 0 {
+                    writeln!(out)?;
+                }
+                writeln!(out, "{}:", toc.path.display())?;
+                if toc.entries.is_empty() {
+                    writeln!(out, "  (no table of contents)")?;
+                }
+                for entry in &toc.entries {
+                    let indent = "  ".repeat(entry.depth);
+                    match &entry.target {
+                        Some(target) => writeln!(out, "{indent}{} [{target}]", entry.label)?,
+                        None => writeln!(out, "{indent}{}", entry.label)?,
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tocs() -> Vec<Toc> {
+        let entry = |depth, label: &str, target: Option<&str>| Entry {
+            depth,
+            label: label.to_string(),
+            target: target.map(str::to_string),
+        };
+        vec![
+            Toc {
+                path: PathBuf::from("a.epub"),
+                entries: vec![
+                    entry(1, "Part One", Some("text/part1.xhtml")),
+                    entry(2, "Chapter 1", Some("text/ch1.xhtml#start")),
+                    entry(1, "Afterword", None),
+                ],
+            },
+            Toc {
+                path: PathBuf::from("b.pdf"),
+                entries: vec![],
+            },
+        ]
+    }
+
+    #[test]
+    fn text_is_indented_by_depth() {
+        let mut out = Vec::new();
+        write(&tocs(), OutputFormat::Text, &mut out).expect("write");
+        assert_eq!(
+            String::from_utf8(out).expect("utf-8"),
+            "a.epub:\n  Part One [text/part1.xhtml]\n    Chapter 1 [text/ch1.xhtml#start]\n  \
+             Afterword\n\nb.pdf:\n  (no table of contents)\n"
+        );
+    }
+
+    #[test]
+    fn json_lists_entries_per_file() {
+        let mut out = Vec::new();
+        write(&tocs(), OutputFormat::Json, &mut out).expect("write");
+        let json: serde_json::Value = serde_json::from_slice(&out).expect("json");
+        assert_eq!(json[0]["path"], "a.epub");
+        assert_eq!(
+            json[0]["entries"][1],
+            serde_json::json!({"depth": 2, "label": "Chapter 1", "target": "text/ch1.xhtml#start"})
+        );
+        assert_eq!(json[0]["entries"][2]["target"], serde_json::Value::Null);
+        assert_eq!(json[1]["entries"], serde_json::json!([]));
+    }
+}
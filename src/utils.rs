@@ -1,10 +1,37 @@
+use clap::ValueEnum;
+use clap::builder::PossibleValue;
 use std::ffi::OsStr;
 use std::path::Path;
 
-/// Return the file extension of `filename` as a `&str`, or `""` if there is none.
+/// How subcommands that produce a report write it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// Aligned plain text for reading in a terminal.
+    #[default]
+    Text,
+    /// JSON for other programs.
+    Json,
+}
+
+impl ValueEnum for OutputFormat {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Self::Text, Self::Json]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(match self {
+            Self::Text => PossibleValue::new("text").help("Plain text"),
+            Self::Json => PossibleValue::new("json").help("JSON"),
+        })
+    }
+}
+
+/// Return the file extension of `filename` as a `&str`, or `""` if there is none or it is
+/// not valid UTF-8.
 ///
 /// The extension is returned as-is (original case). Callers that need case-insensitive
-/// matching should use [`str::eq_ignore_ascii_case`].
+/// matching should use [`str::eq_ignore_ascii_case`]. Use [`Path::extension`] directly
+/// where a non-UTF-8 extension must be preserved.
 ///
 /// # Examples
 ///
@@ -14,45 +41,26 @@ use std::path::Path;
 /// assert_eq!(get_extension("README"), "");
 /// assert_eq!(get_extension("BOOK.EPUB"), "EPUB");
 /// ```
-pub fn get_extension(filename: &str) -> &str {
-    Path::new(filename)
+pub fn get_extension<P: AsRef<Path> + ?Sized>(filename: &P) -> &str {
+    filename
+        .as_ref()
         .extension()
         .and_then(OsStr::to_str)
         .unwrap_or("")
 }
 
-/// Extract the four-digit year from a date string.
+/// Return the BLAKE3 hash of the contents of `path` as a lowercase hex string.
 ///
-/// Handles two formats:
+/// The file is streamed, so large PDFs are not read into memory in one go.
 ///
-/// - A bare four-digit year (`"2024"`) is returned as-is.
-/// - A hyphen-separated date (e.g. `"2024-01-01"` or `"2024-03-15T04:00:00+00:00"`) —
-///   the part before the first hyphen is returned.
+/// # Errors
 ///
-/// Any other string is returned unchanged. Returns an empty string only when the input
-/// is empty.
-///
-/// # Examples
-///
-/// ```ignore
-/// assert_eq!(get_year("2024-06-15"), "2024");
-/// assert_eq!(get_year("2024-03-15T04:00:00+00:00"), "2024");
-/// assert_eq!(get_year("2024"), "2024");
-/// assert_eq!(get_year(""), "");
-/// ```
-pub fn get_year(date: &str) -> String {
-    // If it's already a year, just return it
-    if date.len() == 4 && date.chars().all(char::is_numeric) {
-        return date.to_string();
-    }
-
-    let year = if date.contains('-') {
-        date.split('-').next().unwrap_or("").to_string()
-    } else {
-        date.to_string()
-    };
-
-    year.trim().to_string()
+/// Returns `Err` if the file cannot be opened or read.
+pub fn hash_file<P: AsRef<Path>>(path: P) -> std::io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = blake3::Hasher::new();
+    std::io::copy(&mut file, &mut hasher)?;
+    Ok(hasher.finalize().to_hex().to_string())
 }
 
 /// Print each metadata key/value pair to stdout.
@@ -67,24 +75,101 @@ pub fn print_metadata(tags: &std::collections::HashMap<String, Option<String>>)
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Format a size in bytes for people, e.g. `512 B`, `1.4 MiB`.
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = UNITS[0];
+    for next in &UNITS[1..] {
+        if size < 1024.0 {
+            break;
+        }
+        size /= 1024.0;
+        unit = next;
+    }
+    format!("{size:.1} {unit}")
+}
 
-    #[test]
-    fn test_get_year() {
-        assert_eq!(get_year("2020-01-01"), "2020");
-        assert_eq!(get_year("2011-03-15T04:00:00+00:00"), "2011");
-        assert_eq!(get_year("2020-02-07"), "2020");
-        assert_eq!(get_year("2024"), "2024");
+/// Serde helpers for storing paths in JSON without losing non-UTF-8 ones.
+///
+/// Paths are written as strings when they are valid UTF-8. Other paths are written as an
+/// array of their raw bytes (Unix) or UTF-16 code units (Windows). Use with
+/// `#[serde(with = "utils::stored_path")]`.
+pub mod stored_path {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::path::{Path, PathBuf};
+
+    /// How a path appears in the JSON.
+    #[derive(Serialize, Deserialize)]
+    #[serde(untagged)]
+    enum Stored {
+        Text(String),
+        Raw(Raw),
     }
 
-    #[test]
-    fn test_get_year_edge_cases() {
-        assert_eq!(get_year(""), "");
-        assert_eq!(get_year("unknown"), "unknown");
+    #[cfg(unix)]
+    type Raw = Vec<u8>;
+    #[cfg(windows)]
+    type Raw = Vec<u16>;
+    #[cfg(not(any(unix, windows)))]
+    type Raw = String;
+
+    #[cfg(unix)]
+    fn to_raw(path: &Path) -> Raw {
+        use std::os::unix::ffi::OsStrExt;
+        path.as_os_str().as_bytes().to_vec()
     }
 
+    #[cfg(windows)]
+    fn to_raw(path: &Path) -> Raw {
+        use std::os::windows::ffi::OsStrExt;
+        path.as_os_str().encode_wide().collect()
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    fn to_raw(path: &Path) -> Raw {
+        path.to_string_lossy().into_owned()
+    }
+
+    #[cfg(unix)]
+    fn from_raw(raw: Raw) -> PathBuf {
+        use std::os::unix::ffi::OsStringExt;
+        std::ffi::OsString::from_vec(raw).into()
+    }
+
+    #[cfg(windows)]
+    fn from_raw(raw: Raw) -> PathBuf {
+        use std::os::windows::ffi::OsStringExt;
+        std::ffi::OsString::from_wide(&raw).into()
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    fn from_raw(raw: Raw) -> PathBuf {
+        raw.into()
+    }
+
+    pub fn serialize<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+        match path.to_str() {
+            Some(text) => serializer.serialize_str(text),
+            None => to_raw(path).serialize(serializer),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PathBuf, D::Error> {
+        Ok(match Stored::deserialize(deserializer)? {
+            Stored::Text(text) => text.into(),
+            Stored::Raw(raw) => from_raw(raw),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
     #[test]
     fn test_get_extension() {
         assert_eq!(get_extension("file.txt"), "txt");
@@ -94,9 +179,33 @@ mod tests {
         assert_eq!(get_extension("no_extension"), "");
     }
 
+    #[test]
+    fn hash_file_matches_for_equal_contents() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let a = dir.path().join("a.epub");
+        let b = dir.path().join("b.epub");
+        let c = dir.path().join("c.epub");
+        std::fs::write(&a, b"same").expect("write a");
+        std::fs::write(&b, b"same").expect("write b");
+        std::fs::write(&c, b"different").expect("write c");
+
+        let ha = hash_file(&a).expect("hash a");
+        assert_eq!(ha.len(), 64, "expected a 256-bit hex digest: {ha}");
+        assert_eq!(ha, hash_file(&b).expect("hash b"));
+        assert_ne!(ha, hash_file(&c).expect("hash c"));
+    }
+
     #[test]
     fn get_extension_preserves_original_case() {
         assert_eq!(get_extension("BOOK.EPUB"), "EPUB");
         assert_eq!(get_extension("archive.TAR"), "TAR");
     }
+
+    #[test]
+    fn human_size_picks_a_unit() {
+        assert_eq!(human_size(0), "0 B");
+        assert_eq!(human_size(1023), "1023 B");
+        assert_eq!(human_size(1536), "1.5 KiB");
+        assert_eq!(human_size(5 * 1024 * 1024), "5.0 MiB");
+    }
 }
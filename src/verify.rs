@@ -0,0 +1,683 @@
+//! Check that files are structurally intact: that their container can be read from end to
+//! end, rather than that their metadata makes sense (see [`crate::lint`] for that).
+//!
+//! Each problem found has a [`Code`] that stays the same between releases, so scripts can
+//! act on it.
+use crate::{epub, utils::OutputFormat};
+use serde::Serialize;
+use std::{
+    collections::HashSet,
+    fmt,
+    fs::File,
+    io::{self, BufReader, Write},
+    path::{Path, PathBuf},
+};
+use zip::ZipArchive;
+
+/// The extensions of files that can be verified.
+pub const EXTENSIONS: &[&str] = &["epub", "mobi", "azw", "azw3", "cbz", "pdf"];
+
+/// How far from the end of a PDF its `%%EOF` marker may be.
+const PDF_TAIL: usize = 1024;
+
+/// The length of a Palm database header, before its record list.
+const PDB_HEADER: usize = 78;
+
+/// What is wrong with a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Code {
+    /// The file could not be opened or read.
+    Unreadable,
+    /// The ZIP central directory is missing or corrupt.
+    ZipCentralDirectory,
+    /// A ZIP entry's data does not match its CRC-32.
+    ZipCrc,
+    /// A ZIP entry could not be read, e.g. its local header is corrupt.
+    ZipEntry,
+    /// An EPUB fails one of the [conformance checks](epub::conformance).
+    Epub(epub::Check),
+    /// There is no `%%EOF` marker at the end, so the file is probably truncated.
+    PdfEof,
+    /// `startxref` is missing or does not point at a cross-reference table.
+    PdfStartxref,
+    /// The trailer or cross-reference table can't be parsed.
+    PdfTrailer,
+    /// An object in the cross-reference table is not where the table says, or can't be read.
+    PdfObject,
+    /// The Palm database header or record list is cut short.
+    MobiHeader,
+    /// A record starts outside the file.
+    MobiRecordOffset,
+    /// A record starts before the one listed ahead of it.
+    MobiRecordOrder,
+}
+
+impl Code {
+    /// The code as it is shown, e.g. `zip-crc`.
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Unreadable => "unreadable",
+            Self::ZipCentralDirectory => "zip-central-directory",
+            Self::ZipCrc => "zip-crc",
+            Self::ZipEntry => "zip-entry",
+            Self::Epub(check) => check.name(),
+            Self::PdfEof => "pdf-eof",
+            Self::PdfStartxref => "pdf-startxref",
+            Self::PdfTrailer => "pdf-trailer",
+            Self::PdfObject => "pdf-object",
+            Self::MobiHeader => "mobi-header",
+            Self::MobiRecordOffset => "mobi-record-offset",
+            Self::MobiRecordOrder => "mobi-record-order",
+        }
+    }
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl Serialize for Code {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+/// A problem found in a file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Problem {
+    pub code: Code,
+    pub message: String,
+}
+
+impl Problem {
+    fn new(code: Code, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+/// Check the structure of the file at `path`, judged by its extension. Files with other
+/// extensions have no problems.
+pub fn check(path: &Path) -> Vec<Problem> {
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    let result = match extension.as_str() {
+        "epub" => check_zip(path, true),
+        "cbz" => check_zip(path, false),
+        "pdf" => std::fs::read(path).map(|data| check_pdf(path, &data)),
+        "mobi" | "azw" | "azw3" => std::fs::read(path).map(|data| check_pdb(&data)),
+        _ => Ok(Vec::new()),
+    };
+    result.unwrap_or_else(|err| vec![Problem::new(Code::Unreadable, err.to_string())])
+}
+
+/// Check a ZIP archive's central directory and the CRC of each entry, and if it is an
+/// EPUB, its [conformance](epub::conformance).
+fn check_zip(path: &Path, is_epub: bool) -> io::Result<Vec<Problem>> {
+    let file = BufReader::new(File::open(path)?);
+    let mut archive = match ZipArchive::new(file) {
+        Ok(archive) => archive,
+        Err(err) => {
+            return Ok(vec![Problem::new(
+                Code::ZipCentralDirectory,
+                err.to_string(),
+            )]);
+        }
+    };
+    let mut problems = Vec::new();
+    for i in 0..archive.len() {
+        let mut entry = match archive.by_index(i) {
+            Ok(entry) => entry,
+            Err(err) => {
+                problems.push(Problem::new(Code::ZipEntry, format!("entry {i}: {err}")));
+                continue;
+            }
+        };
+        let name = entry.name().to_string();
+        // Reading to the end makes the zip crate check the CRC.
+        if let Err(err) = io::copy(&mut entry, &mut io::sink()) {
+            let code = if err.kind() == io::ErrorKind::InvalidData
+                && err.to_string().contains("checksum")
+            {
+                Code::ZipCrc
+            } else {
+                Code::ZipEntry
+            };
+            problems.push(Problem::new(code, format!("{name}: {err}")));
+        }
+    }
+    if is_epub {
+        problems.extend(
+            epub::conformance(&mut archive)
+                .into_iter()
+                .map(|finding| Problem::new(Code::Epub(finding.check), finding.message)),
+        );
+    }
+    Ok(problems)
+}
+
+/// Check a PDF's end-of-file marker, `startxref` and trailer, and that every object in its
+/// cross-reference table can be read.
+fn check_pdf(path: &Path, data: &[u8]) -> Vec<Problem> {
+    use pdf::{
+        PdfError,
+        object::{PlainRef, Resolve},
+    };
+
+    let mut problems = Vec::new();
+    let tail = &data[data.len().saturating_sub(PDF_TAIL)..];
+    if find(tail, b"%%EOF").is_none() {
+        problems.push(Problem::new(
+            Code::PdfEof,
+            "no %%EOF marker at the end of the file; it is probably truncated",
+        ));
+    }
+    let mut tables = None;
+    match startxref(data) {
+        Some(offset) if offset < data.len() => {
+            let target = &data[offset..];
+            if target.starts_with(b"xref") {
+                tables = Some(check_xref_tables(data, offset, &mut problems));
+            } else if tokens(target).nth(2) != Some(b"obj".as_slice()) {
+                problems.push(Problem::new(
+                    Code::PdfStartxref,
+                    format!("startxref offset {offset} does not point at a cross-reference table"),
+                ));
+            }
+        }
+        Some(offset) => problems.push(Problem::new(
+            Code::PdfStartxref,
+            format!(
+                "startxref offset {offset} is past the end of the file ({} bytes)",
+                data.len()
+            ),
+        )),
+        None => problems.push(Problem::new(Code::PdfStartxref, "no startxref found")),
+    }
+
+    let file = match pdf::file::FileOptions::cached().open(path) {
+        Ok(file) => file,
+        Err(err) => {
+            problems.push(Problem::new(Code::PdfTrailer, cause(&err).to_string()));
+            return problems;
+        }
+    };
+    let size = u64::try_from(file.trailer.size).unwrap_or_default();
+    let resolver = file.resolver();
+    // `/Size` is only what the trailer claims, so resolve no more objects than are in use,
+    // and report a `/Size` beyond the entries the tables list (or, for cross-reference
+    // streams, beyond those in use).
+    let in_use = objects_in_use(data, &resolver);
+    let listed = tables.as_ref().map_or(in_use, |tables| tables.entries);
+    if size > listed {
+        problems.push(Problem::new(
+            Code::PdfTrailer,
+            format!("the trailer's /Size is {size}, but the cross-reference data only covers {listed} objects"),
+        ));
+    }
+    let misplaced = tables.map(|tables| tables.misplaced).unwrap_or_default();
+    for id in (1..size.min(in_use)).filter(|id| !misplaced.contains(id)) {
+        match resolver.resolve(PlainRef { id, r#gen: 0 }) {
+            Ok(_) => {}
+            Err(err) => match cause(&err) {
+                PdfError::FreeObject { .. } | PdfError::NullRef { .. } => {}
+                err => problems.push(Problem::new(Code::PdfObject, format!("object {id}: {err}"))),
+            },
+        }
+    }
+    problems
+}
+
+/// One more than the highest object number in use in the cross-reference tables or
+/// streams of `data`, or 0 if they can't be read.
+fn objects_in_use(data: &[u8], resolver: &impl pdf::object::Resolve) -> u64 {
+    use pdf::backend::Backend;
+
+    // The table the `pdf` crate builds is as long as `/Size`, with free entries filling the
+    // gaps, so only the entries in use say how many objects there really are.
+    data.locate_start_offset()
+        .and_then(|start| data.read_xref_table_and_trailer(start, resolver))
+        .ok()
+        .and_then(|(refs, _)| refs.keys().max())
+        .map_or(0, |id| u64::from(id) + 1)
+}
+
+/// What [`check_xref_tables`] found.
+#[derive(Debug, Default)]
+struct XrefTables {
+    /// The objects that aren't where the tables say.
+    misplaced: HashSet<u64>,
+    /// One more than the highest object number the tables have an entry for, in use or free.
+    entries: u64,
+}
+
+/// Check that each object in use in the cross-reference table at `offset`, and the tables
+/// before it that its trailer's `/Prev` leads to, is where the table says. Each object that
+/// isn't is added to `problems`.
+///
+/// The `pdf` crate doesn't check this itself: it reads whatever object is at the offset.
+fn check_xref_tables(data: &[u8], offset: usize, problems: &mut Vec<Problem>) -> XrefTables {
+    let mut found = XrefTables::default();
+    let mut seen = HashSet::new();
+    let mut next = Some(offset);
+    while let Some(offset) = next.take().filter(|&o| seen.insert(o)) {
+        let Some(table) = data.get(offset..).and_then(|t| t.strip_prefix(b"xref")) else {
+            break;
+        };
+        let mut words = tokens(table);
+        // Subsections: the first object number and a count, then an offset, generation
+        // and `n` (in use) or `f` (free) for each object.
+        while let Some(word) = words.next() {
+            if word == b"trailer" {
+                break;
+            }
+            let (Some(start), Some(count)) = (number(word), words.next().and_then(number)) else {
+                break;
+            };
+            let Some(end) = start.checked_add(count) else {
+                problems.push(Problem::new(
+                    Code::PdfTrailer,
+                    format!("cross-reference subsection {start} {count} runs past the largest object number"),
+                ));
+                break;
+            };
+            for id in start..end {
+                let (Some(at), Some(generation), Some(kind)) = (
+                    words.next().and_then(number),
+                    words.next().and_then(number),
+                    words.next(),
+                ) else {
+                    break;
+                };
+                found.entries = found.entries.max(id + 1);
+                if kind == b"n" && !is_object_at(data, at, id, generation) {
+                    found.misplaced.insert(id);
+                    problems.push(Problem::new(
+                        Code::PdfObject,
+                        format!("object {id}: the cross-reference table puts it at offset {at}, but it isn't there"),
+                    ));
+                }
+            }
+        }
+        // The trailer dictionary ends before `startxref`.
+        let trailer = &table[..find(table, b"startxref").unwrap_or(table.len())];
+        next = find(trailer, b"/Prev").and_then(|at| {
+            tokens(&trailer[at + b"/Prev".len()..])
+                .next()
+                .and_then(number)
+                .and_then(|prev| usize::try_from(prev).ok())
+        });
+    }
+    found
+}
+
+/// `true` if object `id` with generation `generation` starts at `offset` in `data`.
+fn is_object_at(data: &[u8], offset: u64, id: u64, generation: u64) -> bool {
+    let Some(object) = usize::try_from(offset).ok().and_then(|o| data.get(o..)) else {
+        return false;
+    };
+    let mut words = tokens(object);
+    words.next().and_then(number) == Some(id)
+        && words.next().and_then(number) == Some(generation)
+        && words.next().is_some_and(|w| w.starts_with(b"obj"))
+}
+
+/// The runs of non-whitespace in `data`.
+fn tokens(data: &[u8]) -> impl Iterator<Item = &[u8]> {
+    data.split(u8::is_ascii_whitespace)
+        .filter(|word| !word.is_empty())
+}
+
+/// `word` as a decimal number.
+fn number(word: &[u8]) -> Option<u64> {
+    std::str::from_utf8(word).ok()?.parse().ok()
+}
+
+/// The innermost cause of a `pdf` crate error, without the source locations it is wrapped
+/// in on the way out.
+fn cause(err: &pdf::PdfError) -> &pdf::PdfError {
+    match err {
+        pdf::PdfError::Try { source, .. } => cause(source),
+        err => err,
+    }
+}
+
+/// The offset after the last `startxref` keyword in a PDF.
+fn startxref(data: &[u8]) -> Option<usize> {
+    let at = rfind(data, b"startxref")?;
+    let rest = &data[at + b"startxref".len()..];
+    let digits = rest
+        .iter()
+        .skip_while(|b| b.is_ascii_whitespace())
+        .take_while(|b| b.is_ascii_digit())
+        .map(|&b| char::from(b))
+        .collect::<String>();
+    digits.parse().ok()
+}
+
+/// Check that a Palm database's (MOBI, AZW or AZW3) record list fits in the file and each
+/// record starts inside it, after the one before.
+fn check_pdb(data: &[u8]) -> Vec<Problem> {
+    let Some(count) = data.get(76..PDB_HEADER) else {
+        return vec![Problem::new(
+            Code::MobiHeader,
+            format!("the file is too short ({} bytes) for a header", data.len()),
+        )];
+    };
+    let count = usize::from(u16::from_be_bytes([count[0], count[1]]));
+    let records_start = PDB_HEADER + 8 * count;
+    let Some(list) = data.get(PDB_HEADER..records_start) else {
+        return vec![Problem::new(
+            Code::MobiHeader,
+            format!("the list of {count} records runs past the end of the file"),
+        )];
+    };
+    let offsets = list
+        .chunks_exact(8)
+        .map(|entry| u32::from_be_bytes([entry[0], entry[1], entry[2], entry[3]]) as usize);
+
+    let mut outside = Vec::new();
+    let mut out_of_order = Vec::new();
+    let mut previous = records_start;
+    for (n, offset) in offsets.enumerate() {
+        if offset < records_start || offset >= data.len() {
+            outside.push((n, offset));
+        } else if offset < previous {
+            out_of_order.push((n, offset));
+        } else {
+            previous = offset;
+        }
+    }
+
+    let mut problems = Vec::new();
+    if let Some(&(n, offset)) = outside.first() {
+        problems.push(Problem::new(
+            Code::MobiRecordOffset,
+            format!(
+                "{} record(s) start outside the file ({} bytes), the first being record {n} at offset {offset}",
+                outside.len(),
+                data.len()
+            ),
+        ));
+    }
+    if let Some(&(n, offset)) = out_of_order.first() {
+        problems.push(Problem::new(
+            Code::MobiRecordOrder,
+            format!(
+                "{} record(s) start before the record ahead of them, the first being record {n} at offset {offset}",
+                out_of_order.len()
+            ),
+        ));
+    }
+    problems
+}
+
+/// The position of the first `needle` in `haystack`.
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// The position of the last `needle` in `haystack`.
+fn rfind(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).rposition(|w| w == needle)
+}
+
+/// The problems found in one file.
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub path: PathBuf,
+    pub problems: Vec<Problem>,
+}
+
+/// Write the `reports` of files with problems to `out` in `format`.
+///
+/// # Errors
+///
+/// Returns `Err` if writing to `out` fails.
+pub fn write<W: Write>(reports: &[Report], format: OutputFormat, mut out: W) -> io::Result<()> {
+    let reports = reports.iter().filter(|r| !r.problems.is_empty());
+    match format {
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut out, &reports.collect::<Vec<_>>())?;
+            writeln!(out)
+        }
+        OutputFormat::Text => {
+            for report in reports {
+                for problem in &report.problems {
+                    writeln!(
+                        out,
+                        "{}: {}: {}",
+                        report.path.display(),
+                        problem.code,
+                        problem.message
+                    )?;
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zip::{CompressionMethod, ZipWriter, write::SimpleFileOptions};
+
+    const CONTAINER: &[u8] = br#"<?xml version="1.0"?><container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container"><rootfiles><rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/></rootfiles></container>"#;
+    const OPF: &[u8] = br#"<?xml version="1.0"?><package xmlns="http://www.idpf.org/2007/opf" version="2.0" unique-identifier="id"><metadata xmlns:dc="http://purl.org/dc/elements/1.1/"><dc:identifier id="id">urn:isbn:9780306406157</dc:identifier></metadata><manifest><item id="ch1" href="Chapter%201.xhtml" media-type="application/xhtml+xml"/><item id="css" href="../styles/book.css" media-type="text/css"/><item id="font" href="https://example.com/font.otf" media-type="font/otf"/></manifest><spine><itemref idref="ch1"/></spine></package>"#;
+
+    /// Write an uncompressed zip archive of `entries` to `path`.
+    fn write_zip(path: &Path, entries: &[(&str, &[u8])]) {
+        let mut zip = ZipWriter::new(File::create(path).expect("create"));
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+        for (name, data) in entries {
+            zip.start_file(*name, options).expect("start file");
+            zip.write_all(data).expect("write");
+        }
+        zip.finish().expect("finish");
+    }
+
+    fn epub_entries() -> Vec<(&'static str, &'static [u8])> {
+        vec![
+            ("mimetype", b"application/epub+zip"),
+            ("META-INF/container.xml", CONTAINER),
+            ("OEBPS/content.opf", OPF),
+            ("OEBPS/Chapter 1.xhtml", b"<html>chapter text</html>"),
+            ("styles/book.css", b"p {}"),
+        ]
+    }
+
+    fn codes(problems: &[Problem]) -> Vec<Code> {
+        problems.iter().map(|p| p.code).collect()
+    }
+
+    // ── ZIP and EPUB ─────────────────────────────────────────────────────────
+
+    #[test]
+    fn intact_epub_has_no_problems() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let path = dir.path().join("book.epub");
+        write_zip(&path, &epub_entries());
+        assert_eq!(check(&path), []);
+    }
+
+    #[test]
+    fn corrupt_entry_data_fails_its_crc() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let path = dir.path().join("book.epub");
+        write_zip(&path, &epub_entries());
+        let mut data = std::fs::read(&path).expect("read");
+        let at = find(&data, b"chapter text").expect("stored text");
+        data[at] = b'C';
+        std::fs::write(&path, data).expect("write");
+
+        let problems = check(&path);
+        assert_eq!(codes(&problems), [Code::ZipCrc]);
+        assert!(problems[0].message.starts_with("OEBPS/Chapter 1.xhtml:"));
+    }
+
+    #[test]
+    fn truncated_zip_has_no_central_directory() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let path = dir.path().join("comic.cbz");
+        write_zip(&path, &[("001.jpg", b"\xFF\xD8\xFF image")]);
+        let data = std::fs::read(&path).expect("read");
+        std::fs::write(&path, &data[..data.len() / 2]).expect("write");
+        assert_eq!(codes(&check(&path)), [Code::ZipCentralDirectory]);
+    }
+
+    #[test]
+    fn missing_manifest_items_and_containers_are_reported() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let path = dir.path().join("book.epub");
+        let mut entries = epub_entries();
+        entries.retain(|(name, _)| *name != "styles/book.css");
+        write_zip(&path, &entries);
+        let problems = check(&path);
+        assert_eq!(
+            codes(&problems),
+            [Code::Epub(epub::Check::ManifestItemMissing)]
+        );
+        assert!(problems[0].message.contains("styles/book.css"));
+
+        entries.retain(|(name, _)| *name != "OEBPS/content.opf");
+        write_zip(&path, &entries);
+        assert_eq!(codes(&check(&path)), [Code::Epub(epub::Check::OpfMissing)]);
+
+        entries.retain(|(name, _)| *name != "META-INF/container.xml");
+        write_zip(&path, &entries);
+        assert_eq!(codes(&check(&path)), [Code::Epub(epub::Check::Container)]);
+    }
+
+    // ── PDF ──────────────────────────────────────────────────────────────────
+
+    #[test]
+    fn intact_pdf_has_no_problems() {
+        assert_eq!(check(Path::new("tests/fixtures/text.pdf")), []);
+    }
+
+    #[test]
+    fn truncated_pdf_loses_its_trailer() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let path = dir.path().join("paper.pdf");
+        let data = std::fs::read("tests/fixtures/text.pdf").expect("read");
+        std::fs::write(&path, &data[..data.len() - 60]).expect("write");
+        assert_eq!(
+            codes(&check(&path)),
+            [Code::PdfEof, Code::PdfStartxref, Code::PdfTrailer]
+        );
+    }
+
+    #[test]
+    fn xref_entries_must_point_at_their_objects() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let path = dir.path().join("paper.pdf");
+        let data = std::fs::read("tests/fixtures/outline.pdf").expect("read");
+        let text = String::from_utf8_lossy(&data);
+        // Point the entry for object 7 at the start of the file.
+        let xref = text.find("xref\n").expect("xref");
+        let entry = xref + "xref\n0 10\n".len() + 7 * 20;
+        let mut data = data.clone();
+        data[entry..entry + 10].copy_from_slice(b"0000000000");
+        std::fs::write(&path, data).expect("write");
+
+        let problems = check(&path);
+        assert_eq!(codes(&problems), [Code::PdfObject]);
+        assert!(problems[0].message.starts_with("object 7:"));
+    }
+
+    #[test]
+    fn size_beyond_the_xref_table_is_reported_not_resolved() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let path = dir.path().join("paper.pdf");
+        let mut data = std::fs::read("tests/fixtures/text.pdf").expect("read");
+        // The trailer comes after the table, so growing it moves no offsets.
+        let at = find(&data, b"/Size 10").expect("/Size");
+        data.splice(at..at + 8, b"/Size 900000".iter().copied());
+        std::fs::write(&path, data).expect("write");
+
+        let problems = check(&path);
+        assert_eq!(codes(&problems), [Code::PdfTrailer]);
+        assert!(problems[0].message.contains("/Size is 900000"));
+    }
+
+    #[test]
+    fn xref_subsections_too_large_to_count_are_reported() {
+        let data = b"xref\n18446744073709551615 2\n0000000000 65535 f \ntrailer\n<< >>\n";
+        let mut problems = Vec::new();
+        let tables = check_xref_tables(data, 0, &mut problems);
+        assert!(tables.misplaced.is_empty());
+        assert_eq!(codes(&problems), [Code::PdfTrailer]);
+    }
+
+    // ── MOBI ─────────────────────────────────────────────────────────────────
+
+    /// A Palm database with records at `offsets`, padded to `len` bytes.
+    fn pdb(offsets: &[u32], len: usize) -> Vec<u8> {
+        let mut data = vec![0; PDB_HEADER];
+        data[60..68].copy_from_slice(b"BOOKMOBI");
+        data[76..78].copy_from_slice(&(offsets.len() as u16).to_be_bytes());
+        for offset in offsets {
+            data.extend_from_slice(&offset.to_be_bytes());
+            data.extend_from_slice(&[0; 4]);
+        }
+        data.resize(len, 0);
+        data
+    }
+
+    #[test]
+    fn record_offsets_must_be_in_the_file_and_in_order() {
+        assert_eq!(check_pdb(&pdb(&[120, 150, 150, 180], 200)), []);
+        assert_eq!(
+            codes(&check_pdb(&pdb(&[120, 150, 250, 300], 200))),
+            [Code::MobiRecordOffset]
+        );
+        let problems = check_pdb(&pdb(&[120, 150, 130, 180], 200));
+        assert_eq!(codes(&problems), [Code::MobiRecordOrder]);
+        assert!(problems[0].message.contains("record 2 at offset 130"));
+    }
+
+    #[test]
+    fn short_pdb_headers_are_reported() {
+        assert_eq!(codes(&check_pdb(b"BOOKMOBI")), [Code::MobiHeader]);
+        let mut data = pdb(&[], PDB_HEADER);
+        data[76..78].copy_from_slice(&5u16.to_be_bytes());
+        assert_eq!(codes(&check_pdb(&data)), [Code::MobiHeader]);
+    }
+
+    // ── report ───────────────────────────────────────────────────────────────
+
+    #[test]
+    fn text_report_lists_problems_with_their_codes() {
+        let reports = [
+            Report {
+                path: PathBuf::from("ok.epub"),
+                problems: vec![],
+            },
+            Report {
+                path: PathBuf::from("bad.pdf"),
+                problems: vec![Problem::new(Code::PdfEof, "no %%EOF")],
+            },
+        ];
+        let mut out = Vec::new();
+        write(&reports, OutputFormat::Text, &mut out).expect("write");
+        assert_eq!(
+            String::from_utf8(out).expect("utf-8"),
+            "bad.pdf: pdf-eof: no %%EOF\n"
+        );
+
+        let mut out = Vec::new();
+        write(&reports, OutputFormat::Json, &mut out).expect("write");
+        let json: serde_json::Value = serde_json::from_slice(&out).expect("json");
+        assert_eq!(
+            json,
+            serde_json::json!([{"path": "bad.pdf", "problems": [{"code": "pdf-eof", "message": "no %%EOF"}]}])
+        );
+    }
+}
@@ -1,24 +1,317 @@
-use walkdir::WalkDir;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::{
+    collections::HashMap,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+use walkdir::{DirEntry, WalkDir};
 
 const SUPPORTED_EXTENSIONS: &[&str] = &["epub", "mobi", "pdf"];
 
+/// Name of the gitignore-syntax file that excludes paths below the directory it is in.
+pub const IGNORE_FILE: &str = ".docmetaignore";
+
+/// Rules applied while walking a directory. Apart from [`ignore_files`](Self::ignore_files),
+/// they have no effect on files named directly.
+#[derive(Debug, Clone)]
+pub struct WalkOptions {
+    /// If set, only files matching one of these globs are collected.
+    pub include: Option<GlobSet>,
+    /// Files and directories matching one of these globs are skipped. Matching
+    /// directories are not descended into.
+    pub exclude: Option<GlobSet>,
+    /// Only collect files at least this deep (files directly inside the directory are at depth 1).
+    pub min_depth: Option<usize>,
+    /// Do not descend deeper than this.
+    pub max_depth: Option<usize>,
+    /// Skip files and directories whose names start with a `.`.
+    pub skip_hidden: bool,
+    /// Follow symbolic links to files and directories.
+    pub follow_links: bool,
+    /// Honour [`IGNORE_FILE`]s in the directories above each file, whether it was walked
+    /// or named directly.
+    pub ignore_files: bool,
+    /// Only files with one of these extensions (ignoring case) are collected.
+    pub extensions: &'static [&'static str],
+}
+
+impl Default for WalkOptions {
+    fn default() -> Self {
+        Self {
+            include: None,
+            exclude: None,
+            min_depth: None,
+            max_depth: None,
+            skip_hidden: false,
+            follow_links: true,
+            ignore_files: true,
+            extensions: SUPPORTED_EXTENSIONS,
+        }
+    }
+}
+
+/// Compile `patterns` into a [`GlobSet`], or `None` if there are no patterns.
+///
+/// Patterns are matched against both the path relative to the directory being walked
+/// and the full path, so `_calibre_backup/**` and `**/.Trash/**` both work as expected.
+///
+/// # Errors
+///
+/// Returns `Err` if a pattern is not a valid glob.
+pub fn glob_set<S: AsRef<str>>(patterns: &[S]) -> Result<Option<GlobSet>, globset::Error> {
+    if patterns.is_empty() {
+        return Ok(None);
+    }
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern.as_ref())?);
+    }
+    builder.build().map(Some)
+}
+
+/// `true` if `path`, found while walking `root`, matches `globs`.
+///
+/// Directories are also tested with a trailing separator so that `dir/**` excludes the
+/// directory itself rather than only its contents.
+fn matches(globs: &GlobSet, root: &Path, path: &Path, is_dir: bool) -> bool {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    [relative, path]
+        .iter()
+        .any(|p| globs.is_match(p) || (is_dir && globs.is_match(p.join(""))))
+}
+
+/// The [`IGNORE_FILE`] rules of each directory seen while collecting files, loaded once per
+/// directory.
+#[derive(Default)]
+struct IgnoreFiles {
+    /// `None` for directories without a (readable) ignore file.
+    rules: HashMap<PathBuf, Option<Gitignore>>,
+}
+
+impl IgnoreFiles {
+    /// `true` if an ignore file in any directory above `path` excludes it. `is_dir` says
+    /// whether `path` is a directory.
+    ///
+    /// Every ancestor up to the filesystem root is consulted, not just those inside the
+    /// directory being walked, so a folder marked off-limits stays off-limits however its
+    /// files are named. As with git, the nearest file that has an opinion wins, so a
+    /// subdirectory can re-include (`!name`) something its parent ignores. A file in an
+    /// ignored directory is ignored too, even if the directory itself was never pruned.
+    fn is_ignored(&mut self, path: &Path, is_dir: bool) -> bool {
+        // The rules are anchored at their directory, so match on the absolute path.
+        let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+        for dir in path.ancestors().skip(1) {
+            let rules = self
+                .rules
+                .entry(dir.to_path_buf())
+                .or_insert_with(|| load_ignore_file(dir));
+            if let Some(rules) = rules {
+                let verdict = if is_dir {
+                    rules.matched(&path, true)
+                } else {
+                    rules.matched_path_or_any_parents(&path, false)
+                };
+                if !verdict.is_none() {
+                    return verdict.is_ignore();
+                }
+            }
+        }
+        false
+    }
+}
+
+/// Read the [`IGNORE_FILE`] in `dir`, if there is one. Unreadable files and bad lines are
+/// reported and otherwise ignored.
+fn load_ignore_file(dir: &Path) -> Option<Gitignore> {
+    let file = dir.join(IGNORE_FILE);
+    if !file.is_file() {
+        return None;
+    }
+    let mut builder = GitignoreBuilder::new(dir);
+    if let Some(err) = builder.add(&file) {
+        log::warn!("Problem reading {}: {err}", file.display());
+    }
+    match builder.build() {
+        Ok(rules) => {
+            log::debug!(
+                "Loaded {} rule(s) from {}",
+                rules.num_ignores() + rules.num_whitelists(),
+                file.display()
+            );
+            Some(rules)
+        }
+        Err(err) => {
+            log::warn!("Ignoring {}: {err}", file.display());
+            None
+        }
+    }
+}
+
+/// `true` if the walk should yield (and, for directories, descend into) `entry`.
+fn keep_entry(
+    entry: &DirEntry,
+    root: &Path,
+    options: &WalkOptions,
+    ignore_files: &mut IgnoreFiles,
+) -> bool {
+    // Never filter out the directory the user asked for.
+    if entry.depth() == 0 {
+        return true;
+    }
+    keep_path(
+        entry.path(),
+        entry.file_type().is_dir(),
+        root,
+        options,
+        ignore_files,
+    )
+}
+
+/// `true` if `path` passes `--skip-hidden`, `--exclude` and the [`IGNORE_FILE`]s.
+fn keep_path(
+    path: &Path,
+    is_dir: bool,
+    root: &Path,
+    options: &WalkOptions,
+    ignore_files: &mut IgnoreFiles,
+) -> bool {
+    let hidden = path
+        .file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+    if options.skip_hidden && hidden {
+        log::debug!("Skipping hidden {}", path.display());
+        return false;
+    }
+    if let Some(exclude) = &options.exclude {
+        if matches(exclude, root, path, is_dir) {
+            log::debug!("Excluded: {}", path.display());
+            return false;
+        }
+    }
+    if options.ignore_files && ignore_files.is_ignored(path, is_dir) {
+        log::debug!("Ignored by {IGNORE_FILE}: {}", path.display());
+        return false;
+    }
+    true
+}
+
+/// `true` if a file read from a list passes the rules a walk would have applied to it: its
+/// extension and `--include`, and `--skip-hidden`, `--exclude` and the [`IGNORE_FILE`]s for
+/// the file and each directory in its path below the current directory. There is no walk
+/// root, so globs are matched against the path as listed and the depth limits don't apply.
+fn keep_listed(path: &Path, options: &WalkOptions, ignore_files: &mut IgnoreFiles) -> bool {
+    let root = Path::new("");
+    let cwd = std::env::current_dir().unwrap_or_default();
+    if !has_extension(path, options.extensions) {
+        log::debug!("Skipping unsupported {}", path.display());
+        return false;
+    }
+    let included = options
+        .include
+        .as_ref()
+        .is_none_or(|include| matches(include, root, path, false));
+    included
+        && path
+            .ancestors()
+            .take_while(|part| part.file_name().is_some() && !cwd.starts_with(part))
+            .enumerate()
+            .all(|(depth, part)| keep_path(part, depth > 0, root, options, ignore_files))
+}
+
+/// `true` if `path` has one of `extensions`, ignoring case.
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .is_some_and(|ext| extensions.iter().any(|s| ext.eq_ignore_ascii_case(s)))
+}
+
+/// Read a list of paths, one per line or, with `nul_separated`, separated by NUL bytes as
+/// written by `find -print0`.
+///
+/// Empty entries are skipped, and in line mode a trailing `\r` is removed. On Unix the
+/// bytes are used as they are, so non-UTF-8 paths survive; elsewhere they are decoded
+/// as UTF-8, replacing invalid sequences.
+///
+/// # Errors
+///
+/// Returns `Err` if reading from `reader` fails.
+pub fn read_file_list<R: Read>(mut reader: R, nul_separated: bool) -> io::Result<Vec<PathBuf>> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+
+    let separator = if nul_separated { b'\0' } else { b'\n' };
+    Ok(bytes
+        .split(|&b| b == separator)
+        .map(|entry| {
+            if nul_separated {
+                entry
+            } else {
+                entry.strip_suffix(b"\r").unwrap_or(entry)
+            }
+        })
+        .filter(|entry| !entry.is_empty())
+        .map(path_from_bytes)
+        .collect())
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
 /// Collect file paths from the supplied inputs.
 ///
 /// Each entry in `inputs` is treated as follows:
 ///
-/// - **File**: included as-is (regardless of extension or `recursive`), unless its
-///   path is not valid UTF-8, in which case it is skipped with a warning (the return
-///   type `Vec<String>` cannot represent non-UTF-8 paths).
+/// - **File**: included as-is (regardless of extension or `recursive`), unless an
+///   [`IGNORE_FILE`] in one of its directories excludes it. Paths that are not valid UTF-8
+///   are kept as they are.
 /// - **Directory** with `recursive = true`: walked depth-first; only files
-///   whose extensions appear in [`SUPPORTED_EXTENSIONS`] are included.
+///   whose extensions appear in [`WalkOptions::extensions`] are included, subject to
+///   `options` (see [`WalkOptions`]). Excluded and hidden directories, and those listed
+///   in an [`IGNORE_FILE`], are pruned during the walk rather than filtered afterwards.
 /// - **Directory** with `recursive = false`: skipped with a warning.
 /// - Anything that cannot be stat'd (does not exist, permission denied, etc.): skipped with a warning.
 ///
 /// The returned list follows the order of `inputs`: each input's contribution
 /// (the path itself for files, or the sorted directory contents for directories)
 /// is appended when that input is encountered.
-pub fn collect_files<S: AsRef<std::path::Path>>(inputs: &[S], recursive: bool) -> Vec<String> {
+pub fn collect_files<S: AsRef<Path>>(
+    inputs: &[S],
+    recursive: bool,
+    options: &WalkOptions,
+) -> Vec<PathBuf> {
+    collect(inputs, recursive, options, false)
+}
+
+/// Collect file paths from `inputs` read from a list, e.g. by [`read_file_list`].
+///
+/// As [`collect_files`], except that listed files are only included if they pass the same
+/// rules as files found by a walk, so that listing a tree with `find` collects what walking
+/// it would.
+pub fn collect_listed_files<S: AsRef<Path>>(
+    inputs: &[S],
+    recursive: bool,
+    options: &WalkOptions,
+) -> Vec<PathBuf> {
+    collect(inputs, recursive, options, true)
+}
+
+/// [`collect_files`], or with `listed`, [`collect_listed_files`].
+fn collect<S: AsRef<Path>>(
+    inputs: &[S],
+    recursive: bool,
+    options: &WalkOptions,
+    listed: bool,
+) -> Vec<PathBuf> {
     let mut result = Vec::new();
+    let mut ignore_files = IgnoreFiles::default();
 
     for input in inputs {
         let input = input.as_ref();
@@ -31,10 +324,15 @@ pub fn collect_files<S: AsRef<std::path::Path>>(inputs: &[S], recursive: bool) -
         };
 
         if meta.is_file() {
-            match input.to_str() {
-                Some(s) => result.push(s.to_owned()),
-                None => log::warn!("Skipping non-UTF-8 path: {}", input.display()),
+            if listed {
+                if !keep_listed(input, options, &mut ignore_files) {
+                    continue;
+                }
+            } else if options.ignore_files && ignore_files.is_ignored(input, false) {
+                log::warn!("Skipping {} (ignored by {IGNORE_FILE})", input.display());
+                continue;
             }
+            result.push(input.to_path_buf());
         } else if meta.is_dir() {
             if !recursive {
                 log::warn!(
@@ -43,10 +341,15 @@ pub fn collect_files<S: AsRef<std::path::Path>>(inputs: &[S], recursive: bool) -
                 );
                 continue;
             }
-            for entry in WalkDir::new(input)
-                .follow_links(true)
-                .sort_by_file_name()
+            let mut walk = WalkDir::new(input)
+                .follow_links(options.follow_links)
+                .sort_by_file_name();
+            if let Some(depth) = options.max_depth {
+                walk = walk.max_depth(depth);
+            }
+            for entry in walk
                 .into_iter()
+                .filter_entry(|e| keep_entry(e, input, options, &mut ignore_files))
                 .filter_map(|e| match e {
                     Ok(entry) => Some(entry),
                     Err(err) => {
@@ -59,19 +362,19 @@ pub fn collect_files<S: AsRef<std::path::Path>>(inputs: &[S], recursive: bool) -
                         None
                     }
                 })
-                .filter(|e| e.file_type().is_file())
+                // Shallow files are dropped here rather than with `WalkDir::min_depth`, so
+                // that the directories above them still go through `keep_entry`.
+                .filter(|e| {
+                    e.file_type().is_file()
+                        && options.min_depth.is_none_or(|depth| e.depth() >= depth)
+                })
             {
-                let path = entry.path();
-                let ext_matches = path.extension().is_some_and(|ext| {
-                    SUPPORTED_EXTENSIONS
-                        .iter()
-                        .any(|s| ext.eq_ignore_ascii_case(s))
+                let ext_matches = has_extension(entry.path(), options.extensions);
+                let included = options.include.as_ref().is_none_or(|include| {
+                    matches(include, input, entry.path(), entry.file_type().is_dir())
                 });
-                if ext_matches {
-                    match path.to_str() {
-                        Some(s) => result.push(s.to_owned()),
-                        None => log::warn!("Skipping non-UTF-8 path: {}", path.display()),
-                    }
+                if ext_matches && included {
+                    result.push(entry.into_path());
                 }
             }
         } else {
@@ -100,13 +403,13 @@ mod tests {
         let file = dir.path().join("book.epub");
         fs::write(&file, b"").expect("write");
         let path = file.to_string_lossy().into_owned();
-        let result = collect_files(&[path.as_str()], false);
-        assert_eq!(result, vec![path]);
+        let result = collect_files(&[path.as_str()], false, &WalkOptions::default());
+        assert_eq!(result, vec![file]);
     }
 
     #[test]
     fn str_slice_with_nonexistent_path_returns_empty() {
-        let result = collect_files(&["nonexistent_xyz_path"], false);
+        let result = collect_files(&["nonexistent_xyz_path"], false, &WalkOptions::default());
         assert!(result.is_empty());
     }
 
@@ -118,7 +421,7 @@ mod tests {
         let missing = dir.path().join("book.epub"); // never created
         let path = missing.to_string_lossy().to_string();
 
-        let result = collect_files(std::slice::from_ref(&path), false);
+        let result = collect_files(std::slice::from_ref(&path), false, &WalkOptions::default());
         assert!(result.is_empty());
     }
 
@@ -133,10 +436,10 @@ mod tests {
         fs::write(&file, b"").expect("write");
         let path = file.to_string_lossy().to_string();
 
-        let result = collect_files(std::slice::from_ref(&path), false);
+        let result = collect_files(std::slice::from_ref(&path), false, &WalkOptions::default());
         assert_eq!(
             result,
-            vec![path],
+            vec![file],
             "unsupported-extension file should pass through"
         );
     }
@@ -148,8 +451,8 @@ mod tests {
         fs::write(&file, b"").expect("write");
         let path = file.to_string_lossy().to_string();
 
-        let result = collect_files(std::slice::from_ref(&path), false);
-        assert_eq!(result, vec![path]);
+        let result = collect_files(std::slice::from_ref(&path), false, &WalkOptions::default());
+        assert_eq!(result, vec![file]);
     }
 
     #[test]
@@ -159,8 +462,8 @@ mod tests {
         fs::write(&file, b"").expect("write");
         let path = file.to_string_lossy().to_string();
 
-        let result = collect_files(std::slice::from_ref(&path), true);
-        assert_eq!(result, vec![path]);
+        let result = collect_files(std::slice::from_ref(&path), true, &WalkOptions::default());
+        assert_eq!(result, vec![file]);
     }
 
     #[test]
@@ -175,10 +478,10 @@ mod tests {
             a.to_string_lossy().to_string(),
             b.to_string_lossy().to_string(),
         ];
-        let result = collect_files(&inputs, false);
+        let result = collect_files(&inputs, false, &WalkOptions::default());
         assert_eq!(result.len(), 2);
-        assert!(result.contains(&a.to_string_lossy().to_string()));
-        assert!(result.contains(&b.to_string_lossy().to_string()));
+        assert!(result.contains(&a));
+        assert!(result.contains(&b));
     }
 
     // ── directory without recursive flag ────────────────────────────────────
@@ -189,7 +492,7 @@ mod tests {
         fs::write(dir.path().join("book.epub"), b"").expect("write");
         let dir_path = dir.path().to_string_lossy().to_string();
 
-        let result = collect_files(&[dir_path], false);
+        let result = collect_files(&[dir_path], false, &WalkOptions::default());
         assert!(result.is_empty(), "expected empty; got {result:?}");
     }
 
@@ -207,7 +510,7 @@ mod tests {
         fs::write(dir.path().join("readme.txt"), b"").expect("write");
 
         let dir_path = dir.path().to_string_lossy().to_string();
-        let result = collect_files(&[dir_path], true);
+        let result = collect_files(&[dir_path], true, &WalkOptions::default());
         assert_eq!(
             result.len(),
             3,
@@ -222,7 +525,7 @@ mod tests {
         fs::write(dir.path().join("image.png"), b"").expect("write");
 
         let dir_path = dir.path().to_string_lossy().to_string();
-        let result = collect_files(&[dir_path], true);
+        let result = collect_files(&[dir_path], true, &WalkOptions::default());
         assert!(result.is_empty(), "expected empty; got {result:?}");
     }
 
@@ -230,7 +533,7 @@ mod tests {
     fn recursive_empty_directory_returns_empty() {
         let dir = tempdir().expect("temp dir");
         let dir_path = dir.path().to_string_lossy().to_string();
-        let result = collect_files(&[dir_path], true);
+        let result = collect_files(&[dir_path], true, &WalkOptions::default());
         assert!(result.is_empty());
     }
 
@@ -250,9 +553,9 @@ mod tests {
             explicit_file.to_string_lossy().to_string(),
             sub.to_string_lossy().to_string(),
         ];
-        let result = collect_files(&inputs, true);
+        let result = collect_files(&inputs, true, &WalkOptions::default());
         assert_eq!(result.len(), 2, "expected 2 files; got {result:?}");
-        assert!(result.contains(&explicit_file.to_string_lossy().to_string()));
+        assert!(result.contains(&explicit_file));
     }
 
     // ── extension case-insensitivity ─────────────────────────────────────────
@@ -264,7 +567,7 @@ mod tests {
         fs::write(&file, b"").expect("write");
 
         let dir_path = dir.path().to_string_lossy().to_string();
-        let result = collect_files(&[dir_path], true);
+        let result = collect_files(&[dir_path], true, &WalkOptions::default());
         assert_eq!(result.len(), 1, "expected 1 file; got {result:?}");
     }
 
@@ -280,12 +583,16 @@ mod tests {
         fs::write(dir.path().join("b.pdf"), b"").expect("write b");
 
         let dir_path = dir.path().to_string_lossy().to_string();
-        let result = collect_files(std::slice::from_ref(&dir_path), true);
+        let result = collect_files(
+            std::slice::from_ref(&dir_path),
+            true,
+            &WalkOptions::default(),
+        );
 
         let expected = vec![
-            dir.path().join("a.epub").to_string_lossy().to_string(),
-            dir.path().join("b.pdf").to_string_lossy().to_string(),
-            dir.path().join("c.epub").to_string_lossy().to_string(),
+            dir.path().join("a.epub"),
+            dir.path().join("b.pdf"),
+            dir.path().join("c.epub"),
         ];
         assert_eq!(result, expected, "files must be sorted alphabetically");
     }
@@ -308,16 +615,352 @@ mod tests {
             explicit.to_string_lossy().to_string(),
             sub.to_string_lossy().to_string(),
         ];
-        let result = collect_files(&inputs, true);
+        let result = collect_files(&inputs, true, &WalkOptions::default());
 
-        let expected = vec![
-            explicit.to_string_lossy().to_string(),
-            sub.join("a.mobi").to_string_lossy().to_string(),
-            sub.join("b.epub").to_string_lossy().to_string(),
-        ];
+        let expected = vec![explicit.clone(), sub.join("a.mobi"), sub.join("b.epub")];
         assert_eq!(
             result, expected,
             "explicit file must come first, then dir contents in alphabetical order"
         );
     }
+
+    // ── walk options ─────────────────────────────────────────────────────────
+
+    /// Build a library with nested, hidden and backup folders and return its root, which
+    /// is not hidden itself.
+    fn library() -> tempfile::TempDir {
+        let dir = tempfile::Builder::new()
+            .prefix("library")
+            .tempdir()
+            .expect("temp dir");
+        let root = dir.path();
+        for sub in ["fiction/deep", ".Trash", "_calibre_backup", ".hidden"] {
+            fs::create_dir_all(root.join(sub)).expect("mkdir");
+        }
+        for file in [
+            "top.epub",
+            ".dotfile.epub",
+            "fiction/novel.epub",
+            "fiction/notes.pdf",
+            "fiction/deep/buried.mobi",
+            ".Trash/deleted.epub",
+            "_calibre_backup/backup.epub",
+            ".hidden/secret.pdf",
+        ] {
+            fs::write(root.join(file), b"").expect("write");
+        }
+        dir
+    }
+
+    /// Collect from `dir` with `options` and return the paths relative to `dir`.
+    fn walk(dir: &tempfile::TempDir, options: &WalkOptions) -> Vec<String> {
+        let root = dir.path().to_string_lossy().to_string();
+        collect_files(&[root], true, options)
+            .iter()
+            .map(|p| {
+                p.strip_prefix(dir.path())
+                    .expect("under root")
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn exclude_prunes_matching_directories() {
+        let dir = library();
+        let options = WalkOptions {
+            exclude: glob_set(&["**/.Trash/**", "_calibre_backup/**"]).expect("globs"),
+            ..WalkOptions::default()
+        };
+        let result = walk(&dir, &options);
+        assert!(
+            !result
+                .iter()
+                .any(|p| p.contains("Trash") || p.contains("backup")),
+            "excluded directories were walked: {result:?}"
+        );
+        assert!(result.contains(&"top.epub".to_string()));
+    }
+
+    #[test]
+    fn include_limits_collected_files() {
+        let dir = library();
+        let options = WalkOptions {
+            include: glob_set(&["fiction/**/*.epub", "*.mobi"]).expect("globs"),
+            ..WalkOptions::default()
+        };
+        assert_eq!(
+            walk(&dir, &options),
+            vec!["fiction/deep/buried.mobi", "fiction/novel.epub"]
+        );
+    }
+
+    #[test]
+    fn depth_limits_are_applied() {
+        let dir = library();
+        let shallow = WalkOptions {
+            max_depth: Some(1),
+            skip_hidden: true,
+            ..WalkOptions::default()
+        };
+        assert_eq!(walk(&dir, &shallow), vec!["top.epub"]);
+
+        let deep = WalkOptions {
+            min_depth: Some(3),
+            ..WalkOptions::default()
+        };
+        assert_eq!(walk(&dir, &deep), vec!["fiction/deep/buried.mobi"]);
+    }
+
+    #[test]
+    fn min_depth_still_prunes_shallower_directories() {
+        let dir = library();
+        let options = WalkOptions {
+            min_depth: Some(2),
+            skip_hidden: true,
+            exclude: glob_set(&["_calibre_backup/**"]).expect("globs"),
+            ..WalkOptions::default()
+        };
+        assert_eq!(
+            walk(&dir, &options),
+            vec![
+                "fiction/deep/buried.mobi",
+                "fiction/notes.pdf",
+                "fiction/novel.epub"
+            ]
+        );
+
+        let options = WalkOptions {
+            min_depth: Some(3),
+            exclude: glob_set(&["fiction/deep/**"]).expect("globs"),
+            ..WalkOptions::default()
+        };
+        assert!(walk(&dir, &options).is_empty());
+    }
+
+    #[test]
+    fn skip_hidden_skips_dotfiles_and_dot_directories() {
+        let dir = library();
+        let options = WalkOptions {
+            skip_hidden: true,
+            ..WalkOptions::default()
+        };
+        let result = walk(&dir, &options);
+        assert!(
+            !result
+                .iter()
+                .any(|p| p.contains("/.") || p.starts_with('.')),
+            "hidden entries were collected: {result:?}"
+        );
+        assert_eq!(result.len(), 5, "unexpected result: {result:?}");
+    }
+
+    #[test]
+    fn listed_files_pass_the_same_rules_as_walked_files() {
+        let dir = library();
+        fs::write(dir.path().join("cover.jpg"), b"").expect("write");
+        fs::write(dir.path().join(IGNORE_FILE), "fiction/deep/\n").expect("write");
+        let listed = [
+            "cover.jpg",
+            IGNORE_FILE,
+            ".dotfile.epub",
+            ".hidden/secret.pdf",
+            ".Trash/deleted.epub",
+            "fiction/deep/buried.mobi",
+            "fiction/notes.pdf",
+            "fiction/novel.epub",
+            "top.epub",
+        ]
+        .map(|name| dir.path().join(name));
+        let options = WalkOptions {
+            include: glob_set(&["**/*.epub", "**/*.pdf", "**/*.mobi"]).expect("globs"),
+            exclude: glob_set(&["**/fiction/*.pdf"]).expect("globs"),
+            skip_hidden: true,
+            ..WalkOptions::default()
+        };
+        assert_eq!(
+            collect_listed_files(&listed, false, &options),
+            [
+                dir.path().join("fiction/novel.epub"),
+                dir.path().join("top.epub")
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_glob_is_an_error() {
+        assert!(glob_set(&["a/**/[b"]).is_err());
+        assert!(glob_set::<&str>(&[]).expect("empty").is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_are_only_followed_when_asked() {
+        let dir = tempdir().expect("temp dir");
+        let outside = tempdir().expect("outside dir");
+        fs::write(outside.path().join("linked.epub"), b"").expect("write");
+        std::os::unix::fs::symlink(outside.path(), dir.path().join("link")).expect("symlink");
+
+        assert_eq!(
+            walk(&dir, &WalkOptions::default()),
+            vec!["link/linked.epub"]
+        );
+        let no_follow = WalkOptions {
+            follow_links: false,
+            ..WalkOptions::default()
+        };
+        assert!(walk(&dir, &no_follow).is_empty());
+    }
+
+    // ── ignore files ─────────────────────────────────────────────────────────
+
+    #[test]
+    fn ignore_file_excludes_paths_in_its_subtree() {
+        let dir = library();
+        fs::write(dir.path().join(IGNORE_FILE), "fiction/deep/\n.*\n").expect("write");
+        fs::write(dir.path().join("fiction").join(IGNORE_FILE), "*.pdf\n").expect("write");
+        assert_eq!(
+            walk(&dir, &WalkOptions::default()),
+            vec![
+                "_calibre_backup/backup.epub",
+                "fiction/novel.epub",
+                "top.epub"
+            ]
+        );
+    }
+
+    #[test]
+    fn ignored_directories_never_leak_their_files() {
+        let dir = library();
+        fs::write(dir.path().join(IGNORE_FILE), "fiction/deep/\n").expect("write");
+        let options = WalkOptions {
+            min_depth: Some(3),
+            ..WalkOptions::default()
+        };
+        assert!(walk(&dir, &options).is_empty());
+
+        // A file is ignored by its directory's rule even when the directory wasn't pruned.
+        let buried = WalkDir::new(dir.path())
+            .into_iter()
+            .filter_map(Result::ok)
+            .find(|e| e.file_name() == "buried.mobi")
+            .expect("buried.mobi");
+        assert!(IgnoreFiles::default().is_ignored(buried.path(), false));
+    }
+
+    #[test]
+    fn ignore_files_above_the_inputs_still_apply() {
+        let dir = library();
+        fs::write(dir.path().join(IGNORE_FILE), "fiction/deep/\n*.pdf\n").expect("write");
+        let fiction = dir.path().join("fiction");
+        let named = [
+            fiction.join("deep/buried.mobi"),
+            fiction.join("notes.pdf"),
+            fiction.join("novel.epub"),
+        ];
+        assert_eq!(
+            collect_files(&named, false, &WalkOptions::default()),
+            [fiction.join("novel.epub")]
+        );
+        assert_eq!(
+            collect_files(&[&fiction], true, &WalkOptions::default()),
+            [fiction.join("novel.epub")]
+        );
+
+        let options = WalkOptions {
+            ignore_files: false,
+            ..WalkOptions::default()
+        };
+        assert_eq!(collect_files(&named, false, &options), named);
+    }
+
+    #[test]
+    fn nearer_ignore_file_can_reinclude() {
+        let dir = library();
+        fs::write(dir.path().join(IGNORE_FILE), "*.epub\n").expect("write");
+        fs::write(
+            dir.path().join("fiction").join(IGNORE_FILE),
+            "!novel.epub\n",
+        )
+        .expect("write");
+        let options = WalkOptions {
+            skip_hidden: true,
+            ..WalkOptions::default()
+        };
+        assert_eq!(
+            walk(&dir, &options),
+            vec![
+                "fiction/deep/buried.mobi",
+                "fiction/notes.pdf",
+                "fiction/novel.epub"
+            ]
+        );
+    }
+
+    #[test]
+    fn ignore_files_can_be_disabled() {
+        let dir = library();
+        fs::write(dir.path().join(IGNORE_FILE), "*\n").expect("write");
+        assert!(walk(&dir, &WalkOptions::default()).is_empty());
+        let options = WalkOptions {
+            ignore_files: false,
+            ..WalkOptions::default()
+        };
+        assert_eq!(walk(&dir, &options).len(), 8);
+    }
+
+    // ── non-UTF-8 paths ──────────────────────────────────────────────────────
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_paths_are_collected() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let dir = tempdir().expect("temp dir");
+        // "café.epub" in Latin-1, as left behind by old Windows shares.
+        let file = dir.path().join(OsStr::from_bytes(b"caf\xe9.epub"));
+        if fs::write(&file, b"").is_err() {
+            return; // The filesystem insists on UTF-8 names (e.g. APFS).
+        }
+
+        let found = collect_files(&[dir.path()], true, &WalkOptions::default());
+        assert_eq!(found, vec![file.clone()]);
+        assert_eq!(
+            collect_files(&[&file], false, &WalkOptions::default()),
+            vec![file]
+        );
+    }
+
+    // ── file lists ───────────────────────────────────────────────────────────
+
+    #[test]
+    fn file_list_is_split_on_newlines() {
+        let list = read_file_list(&b"a.epub\r\n\nb dir/c.pdf\n"[..], false).expect("read");
+        assert_eq!(
+            list,
+            vec![PathBuf::from("a.epub"), PathBuf::from("b dir/c.pdf")]
+        );
+    }
+
+    #[test]
+    fn file_list_is_split_on_nul_bytes() {
+        let list = read_file_list(&b"a\nb.epub\0c.pdf\0"[..], true).expect("read");
+        assert_eq!(
+            list,
+            vec![PathBuf::from("a\nb.epub"), PathBuf::from("c.pdf")]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn file_list_keeps_non_utf8_bytes() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let list = read_file_list(&b"caf\xe9.epub\0"[..], true).expect("read");
+        assert_eq!(
+            list,
+            vec![PathBuf::from(OsStr::from_bytes(b"caf\xe9.epub"))]
+        );
+    }
 }
@@ -0,0 +1,270 @@
+//! Watch a directory for new files and hand them over once they have finished arriving.
+//!
+//! Files are reported by filesystem notifications (inotify on Linux) when they are created
+//! or moved in. A file is only considered complete once its size has not changed for the
+//! settle period, so half-downloaded or half-copied books are left alone until they are done.
+use crate::extract::Format;
+use notify::{
+    EventKind, RecursiveMode, Watcher as _,
+    event::{ModifyKind, RenameMode},
+};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::mpsc,
+    time::{Duration, Instant},
+};
+
+/// The longest the watcher waits between checks on files that are still arriving.
+const MAX_POLL: Duration = Duration::from_secs(1);
+
+/// A file that has been seen but has not settled yet.
+#[derive(Debug)]
+struct Pending {
+    /// The size at the last check, or `None` before the first one.
+    size: Option<u64>,
+    /// When the file was last seen to change.
+    changed: Instant,
+}
+
+/// Tracks arriving files until their size stops changing.
+#[derive(Debug)]
+pub struct Settler {
+    settle: Duration,
+    pending: HashMap<PathBuf, Pending>,
+    /// Files docmeta produced itself, which must not be picked up again, and when the
+    /// events from producing them have had time to arrive.
+    ignored: HashMap<PathBuf, Instant>,
+}
+
+impl Settler {
+    /// Create a tracker that waits `settle` after the last change to a file.
+    pub fn new(settle: Duration) -> Self {
+        Self {
+            settle,
+            pending: HashMap::new(),
+            ignored: HashMap::new(),
+        }
+    }
+
+    /// Note that `path` was created or changed at `now`. Files of unsupported types and
+    /// ignored files are passed over.
+    pub fn touch(&mut self, path: &Path, now: Instant) {
+        let ignored = self.ignored.get(path).is_some_and(|&until| now < until);
+        if Format::of(path).is_none() || ignored {
+            return;
+        }
+        log::debug!("Seen {}", path.display());
+        let pending = self.pending.entry(path.to_path_buf()).or_insert(Pending {
+            size: None,
+            changed: now,
+        });
+        pending.changed = now;
+    }
+
+    /// Note that `path` is gone, e.g. deleted or moved away.
+    pub fn forget(&mut self, path: &Path) {
+        self.pending.remove(path);
+        self.ignored.remove(path);
+    }
+
+    /// Stop reporting changes to `path` for one settle period from `now`, or until it is
+    /// [forgotten](Self::forget). Used for files docmeta has just renamed: the events of
+    /// the rename are passed over, but a new file arriving later under the same name is not.
+    pub fn ignore(&mut self, path: PathBuf, now: Instant) {
+        self.pending.remove(&path);
+        self.ignored.insert(path, now + self.settle);
+    }
+
+    /// `true` if files are waiting to settle.
+    pub fn is_waiting(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Check the size of every pending file and return those that have not changed for
+    /// the settle period, sorted by path. Files that have disappeared are dropped.
+    pub fn take_settled(&mut self, now: Instant) -> Vec<PathBuf> {
+        self.ignored.retain(|_, until| now < *until);
+        let mut settled = Vec::new();
+        self.pending.retain(|path, pending| {
+            let Ok(meta) = std::fs::metadata(path) else {
+                log::debug!("{} is gone", path.display());
+                return false;
+            };
+            if !meta.is_file() {
+                return false;
+            }
+            if pending.size != Some(meta.len()) {
+                pending.size = Some(meta.len());
+                pending.changed = now;
+            }
+            if now.duration_since(pending.changed) >= self.settle {
+                settled.push(path.clone());
+                return false;
+            }
+            true
+        });
+        settled.sort();
+        settled
+    }
+}
+
+/// Watch `dir` (and its subdirectories if `recursive`) until the process is stopped, calling
+/// `handle` with each batch of files that have settled. `handle` returns the files it has
+/// created, which are then ignored.
+///
+/// # Errors
+///
+/// Returns `Err` if `dir` cannot be watched, the watch fails, or `handle` fails.
+pub fn watch<F>(dir: &Path, recursive: bool, settle: Duration, mut handle: F) -> anyhow::Result<()>
+where
+    F: FnMut(Vec<PathBuf>) -> anyhow::Result<Vec<PathBuf>>,
+{
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    let mode = if recursive {
+        RecursiveMode::Recursive
+    } else {
+        RecursiveMode::NonRecursive
+    };
+    watcher.watch(dir, mode)?;
+    log::info!("Watching {} for new files", dir.display());
+
+    let mut settler = Settler::new(settle);
+    loop {
+        let timeout = if settler.is_waiting() {
+            settle.min(MAX_POLL)
+        } else {
+            Duration::MAX
+        };
+        match rx.recv_timeout(timeout) {
+            Ok(event) => {
+                let event = event?;
+                log::trace!("{event:?}");
+                let now = Instant::now();
+                match event.kind {
+                    EventKind::Remove(_)
+                    | EventKind::Modify(ModifyKind::Name(RenameMode::From)) => {
+                        event.paths.iter().for_each(|p| settler.forget(p));
+                    }
+                    // Both the old and the new name, in that order.
+                    EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {
+                        if let [from, to] = event.paths.as_slice() {
+                            settler.forget(from);
+                            settler.touch(to, now);
+                        }
+                    }
+                    EventKind::Create(_) | EventKind::Modify(_) => {
+                        event.paths.iter().for_each(|p| settler.touch(p, now));
+                    }
+                    _ => {}
+                }
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => anyhow::bail!("the watch stopped"),
+        }
+
+        let settled = settler.take_settled(Instant::now());
+        if !settled.is_empty() {
+            let now = Instant::now();
+            for created in handle(settled)? {
+                settler.ignore(created, now);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    const SETTLE: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn files_settle_once_their_size_stops_changing() {
+        let dir = tempdir().expect("temp dir");
+        let book = dir.path().join("book.epub");
+        fs::write(&book, "part").expect("write");
+        let start = Instant::now();
+        let mut settler = Settler::new(SETTLE);
+
+        settler.touch(&book, start);
+        assert!(
+            settler.take_settled(start + SETTLE).is_empty(),
+            "size not known yet"
+        );
+
+        // Still growing: the clock starts again.
+        fs::write(&book, "partial").expect("write");
+        assert!(settler.take_settled(start + SETTLE * 2).is_empty());
+        assert!(
+            settler
+                .take_settled(start + SETTLE * 3 - Duration::from_secs(1))
+                .is_empty()
+        );
+        assert_eq!(settler.take_settled(start + SETTLE * 3), vec![book]);
+        assert!(!settler.is_waiting());
+    }
+
+    #[test]
+    fn new_events_restart_the_settle_period() {
+        let dir = tempdir().expect("temp dir");
+        let book = dir.path().join("book.pdf");
+        fs::write(&book, "data").expect("write");
+        let start = Instant::now();
+        let mut settler = Settler::new(SETTLE);
+
+        settler.touch(&book, start);
+        settler.take_settled(start);
+        settler.touch(&book, start + SETTLE);
+        assert!(settler.take_settled(start + SETTLE).is_empty());
+        assert_eq!(settler.take_settled(start + SETTLE * 2), vec![book]);
+    }
+
+    #[test]
+    fn unsupported_missing_and_ignored_files_are_not_reported() {
+        let dir = tempdir().expect("temp dir");
+        let partial = dir.path().join("book.epub.part");
+        let gone = dir.path().join("gone.epub");
+        let renamed = dir.path().join("Renamed.epub");
+        fs::write(&partial, "x").expect("write");
+        fs::write(&renamed, "x").expect("write");
+        let start = Instant::now();
+        let mut settler = Settler::new(SETTLE);
+
+        settler.ignore(renamed.clone(), start);
+        for path in [&partial, &gone, &renamed] {
+            settler.touch(path, start);
+        }
+        assert!(settler.take_settled(start + SETTLE).is_empty());
+
+        // Once an ignored file leaves, its name can arrive afresh.
+        settler.ignore(renamed.clone(), start);
+        settler.forget(&renamed);
+        settler.touch(&renamed, start);
+        settler.take_settled(start);
+        assert_eq!(settler.take_settled(start + SETTLE), vec![renamed]);
+    }
+
+    #[test]
+    fn ignored_names_can_be_reused_after_the_settle_period() {
+        let dir = tempdir().expect("temp dir");
+        let renamed = dir.path().join("Renamed.epub");
+        fs::write(&renamed, "x").expect("write");
+        let start = Instant::now();
+        let mut settler = Settler::new(SETTLE);
+
+        // The rename's own events are passed over...
+        settler.ignore(renamed.clone(), start);
+        settler.touch(&renamed, start + Duration::from_secs(1));
+        assert!(!settler.is_waiting());
+
+        // ...but a download that later lands on the same name is picked up.
+        settler.touch(&renamed, start + SETTLE);
+        assert!(settler.is_waiting());
+        settler.take_settled(start + SETTLE);
+        assert_eq!(settler.take_settled(start + SETTLE * 2), vec![renamed]);
+    }
+}